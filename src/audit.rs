@@ -0,0 +1,257 @@
+#[cfg(feature = "audit-db")]
+use rusqlite::{params, Connection};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::UnboundedSender;
+#[cfg(feature = "audit-db")]
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use tokio::task::JoinHandle;
+
+/// Schema of the audit database, created on first use
+#[cfg(feature = "audit-db")]
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS audit (
+    run_id    TEXT NOT NULL,
+    doc_id    TEXT NOT NULL,
+    rev       TEXT,
+    rule      TEXT NOT NULL,
+    outcome   TEXT NOT NULL,
+    timestamp TEXT NOT NULL
+)";
+
+/// Outcome of processing a document, as recorded in the audit database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Updated,     // The document was written to the database
+    WouldUpdate, // Dry-run: the document would have been written
+    Deleted,     // The document was soft-deleted
+    WouldDelete, // Dry-run: the document would have been soft-deleted
+    Rejected,    // Dry-run: the server's validation would reject the update
+    Failed,      // Writing the document failed
+    Missing,     // The document has none of the old fields
+    Unchanged,   // The document already holds the result of the operation
+    Conflict, // Skipped because of a merge conflict or, with `--on-conflict skip`, an existing new field
+    Ambiguous, // Skipped because several case variants of a field coexist
+}
+
+impl Outcome {
+    /// Name of the outcome stored in the `outcome` column.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Updated => "updated",
+            Outcome::WouldUpdate => "would_update",
+            Outcome::Deleted => "deleted",
+            Outcome::WouldDelete => "would_delete",
+            Outcome::Rejected => "rejected",
+            Outcome::Failed => "failed",
+            Outcome::Missing => "missing",
+            Outcome::Unchanged => "unchanged",
+            Outcome::Conflict => "conflict",
+            Outcome::Ambiguous => "ambiguous",
+        }
+    }
+}
+
+/// A row of the audit database, sent by the processing tasks to the writer.
+/// Without the `audit-db` feature there is no writer to read it.
+#[derive(Debug)]
+#[cfg_attr(not(feature = "audit-db"), allow(dead_code))]
+struct AuditRow {
+    doc_id: String,      // ID of the document
+    rev: Option<String>, // Revision of the document, if known
+    rule: String,        // Description of the operation
+    outcome: Outcome,    // What happened to the document
+}
+
+/// A message handled by the writer task.
+#[derive(Debug)]
+#[cfg_attr(not(feature = "audit-db"), allow(dead_code))]
+enum AuditEvent {
+    Row(AuditRow), // A row to insert
+    Finish,        // Stop once the rows received so far are written
+}
+
+/// Handle for recording outcomes in the local SQLite database opened by `AuditLog::open`, one row
+/// per processed document, appended to across runs. Rows of the same run share a `run_id`.
+/// The rows are written by a blocking task of their own, so recording never blocks the caller.
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    sender: UnboundedSender<AuditEvent>,
+}
+
+/// The running writer task, to be finished with `finish` once every document is processed.
+pub struct AuditWriter {
+    sender: UnboundedSender<AuditEvent>,
+    task: JoinHandle<Result<(), String>>,
+}
+
+impl AuditLog {
+    /// Opens (or creates) the audit database at `path`, creating the schema if needed, and starts
+    /// the task writing its rows. Fails when refield was built without the `audit-db` feature.
+    #[cfg(feature = "audit-db")]
+    pub fn open(path: &str, run_id: String) -> Result<(AuditLog, AuditWriter), String> {
+        let connection = Connection::open(path)
+            .and_then(|connection| connection.execute(SCHEMA, []).map(|_| connection))
+            .map_err(|e| format!("Failed to open audit database '{}': {}", path, e))?;
+
+        let (sender, receiver) = unbounded_channel();
+        let task = tokio::task::spawn_blocking(move || write_rows(connection, run_id, receiver));
+
+        Ok((
+            AuditLog {
+                sender: sender.clone(),
+            },
+            AuditWriter { sender, task },
+        ))
+    }
+
+    /// Opens (or creates) the audit database at `path`, creating the schema if needed, and starts
+    /// the task writing its rows. Fails when refield was built without the `audit-db` feature.
+    #[cfg(not(feature = "audit-db"))]
+    pub fn open(path: &str, run_id: String) -> Result<(AuditLog, AuditWriter), String> {
+        let _ = (path, run_id);
+        Err("--audit-db requires refield to be built with the `audit-db` feature".to_string())
+    }
+
+    /// Records the outcome of a document under the current run.
+    pub fn record(&self, doc_id: &str, rev: Option<&str>, rule: &str, outcome: Outcome) {
+        // Rows recorded after the writer has finished are dropped
+        let _ = self.sender.send(AuditEvent::Row(AuditRow {
+            doc_id: doc_id.to_string(),
+            rev: rev.map(String::from),
+            rule: rule.to_string(),
+            outcome,
+        }));
+    }
+}
+
+impl AuditWriter {
+    /// Stops the writer task once every row recorded before is written. Fails if any of them
+    /// could not be written.
+    pub async fn finish(self) -> Result<(), String> {
+        let _ = self.sender.send(AuditEvent::Finish);
+        self.task
+            .await
+            .map_err(|e| format!("The audit database writer failed: {}", e))?
+    }
+}
+
+/// Writer loop: inserts each row until finished or every sender is gone. A row that cannot be
+/// written does not stop the others; the failures are counted and returned at the end.
+#[cfg(feature = "audit-db")]
+fn write_rows(
+    connection: Connection,
+    run_id: String,
+    mut receiver: UnboundedReceiver<AuditEvent>,
+) -> Result<(), String> {
+    let mut failed = 0;
+    let mut first_error = None;
+    while let Some(AuditEvent::Row(row)) = receiver.blocking_recv() {
+        let inserted = connection.execute(
+            "INSERT INTO audit (run_id, doc_id, rev, rule, outcome, timestamp)
+             VALUES (?1, ?2, ?3, ?4, ?5, strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))",
+            params![run_id, row.doc_id, row.rev, row.rule, row.outcome.as_str()],
+        );
+        if let Err(err) = inserted {
+            failed += 1;
+            first_error.get_or_insert(err.to_string());
+        }
+    }
+
+    match first_error {
+        Some(err) => Err(format!(
+            "Failed to write {} rows to the audit database: {}",
+            failed, err
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Generates an identifier for the current run from the start time and the process ID.
+pub fn new_run_id() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    format!("{}-{}", now.as_millis(), std::process::id())
+}
+
+#[cfg(all(test, feature = "audit-db"))]
+mod tests {
+    use super::*;
+
+    /// A path for a new audit database in the temporary directory.
+    fn temp_db_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("refield-{}-{}.db", name, new_run_id()));
+        path.to_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_audit_rows_append_across_runs() {
+        let path = temp_db_path("audit");
+        let path = path.as_str();
+
+        let (first, writer) = AuditLog::open(path, "run-1".to_string()).unwrap();
+        first.record("a", Some("1-x"), "old -> new", Outcome::WouldUpdate);
+        writer.finish().await.unwrap();
+
+        let (second, writer) = AuditLog::open(path, "run-2".to_string()).unwrap();
+        second.record("b", None, "old -> new", Outcome::Missing);
+        writer.finish().await.unwrap();
+
+        let connection = Connection::open(path).unwrap();
+        let mut statement = connection
+            .prepare("SELECT run_id, doc_id, rev, outcome FROM audit ORDER BY rowid")
+            .unwrap();
+        let rows: Vec<(String, String, Option<String>, String)> = statement
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(
+            rows,
+            vec![
+                (
+                    "run-1".to_string(),
+                    "a".to_string(),
+                    Some("1-x".to_string()),
+                    "would_update".to_string()
+                ),
+                (
+                    "run-2".to_string(),
+                    "b".to_string(),
+                    None,
+                    "missing".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_rows_recorded_by_concurrent_tasks_are_written_once_finished() {
+        let path = temp_db_path("audit-tasks");
+        let (audit, writer) = AuditLog::open(&path, "run".to_string()).unwrap();
+
+        let tasks: Vec<_> = (0..20)
+            .map(|i| {
+                let audit = audit.clone();
+                tokio::spawn(async move {
+                    audit.record(&format!("doc{}", i), None, "a -> b", Outcome::Updated)
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        writer.finish().await.unwrap();
+        audit.record("late", None, "a -> b", Outcome::Updated);
+
+        let connection = Connection::open(&path).unwrap();
+        let count: usize = connection
+            .query_row("SELECT COUNT(*) FROM audit", [], |row| row.get(0))
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(count, 20, "Every row before finishing, and none after");
+    }
+}
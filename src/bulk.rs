@@ -0,0 +1,149 @@
+use crate::iam::{authorize, IamAuth};
+use crate::retry::send_with_retry;
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+
+/// Outcome of one document of a `_bulk_docs` request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BulkOutcome {
+    Written(Option<String>), // Saved, with the new revision if the server returned it
+    Conflict,                // Rejected: the document has a newer revision than the one written
+    Failed(String),          // Rejected for another reason, as `error: reason`
+}
+
+/// The outcome of a document of a `_bulk_docs` request, with its ID.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BulkRow {
+    pub id: String,           // ID of the document
+    pub outcome: BulkOutcome, // Whether and how the document was saved
+}
+
+/// Writes a batch of documents with a single `_bulk_docs` request to `{db_host}/{table_name}`.
+/// Transient failures of the request are retried within `max_retries` (see `send_with_retry`).
+/// With a `write_quorum`, each document is acknowledged once that many replicas have it (`w`).
+///
+/// CouchDB accepts or rejects each document on its own, so the request succeeds as a whole
+/// even if some documents fail; their outcomes are returned in the order of `docs`.
+pub async fn bulk_update(
+    client: &Client,
+    db_host: &str,
+    table_name: &str,
+    docs: &[Value],
+    auth: Option<&IamAuth>,
+    max_retries: usize,
+    write_quorum: Option<usize>,
+) -> Result<Vec<BulkRow>, String> {
+    let url = format!("{}/{}/_bulk_docs", db_host, table_name);
+    let mut request = client.post(&url).json(&json!({ "docs": docs }));
+    if let Some(w) = write_quorum {
+        request = request.query(&[("w", w)]);
+    }
+    let request = authorize(auth, request).await?;
+    let response = send_with_retry(request, max_retries)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if response.status() != StatusCode::CREATED && response.status() != StatusCode::OK {
+        return Err(format!(
+            "Failed to update {} documents: Status code {}",
+            docs.len(),
+            response.status()
+        ));
+    }
+
+    let rows: Vec<Value> = response.json().await.map_err(|e| e.to_string())?;
+    Ok(rows.iter().map(parse_row).collect())
+}
+
+/// Reads a row of the `_bulk_docs` response: `{"id", "rev"}` on success, `{"id", "error", "reason"}` otherwise.
+fn parse_row(row: &Value) -> BulkRow {
+    let id = row["id"].as_str().unwrap_or("<unknown>").to_string();
+    let outcome = match row["error"].as_str() {
+        None => BulkOutcome::Written(row["rev"].as_str().map(String::from)),
+        Some("conflict") => BulkOutcome::Conflict,
+        Some(error) => BulkOutcome::Failed(match row["reason"].as_str() {
+            Some(reason) => format!("{}: {}", error, reason),
+            None => error.to_string(),
+        }),
+    };
+    BulkRow { id, outcome }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_bulk_update_sends_one_request_per_batch() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/db/_bulk_docs"))
+            .respond_with(ResponseTemplate::new(201).set_body_json(json!([
+                { "ok": true, "id": "a", "rev": "2-a" },
+                { "id": "b", "error": "conflict", "reason": "Document update conflict." },
+                { "id": "c", "error": "forbidden", "reason": "Missing owner" },
+            ])))
+            .mount(&server)
+            .await;
+
+        let docs = vec![
+            json!({ "_id": "a", "_rev": "1-a", "name": "x" }),
+            json!({ "_id": "b", "_rev": "1-b", "name": "y" }),
+            json!({ "_id": "c", "_rev": "1-c", "name": "z" }),
+        ];
+        let rows = bulk_update(&Client::new(), &server.uri(), "db", &docs, None, 0, None)
+            .await
+            .unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(
+            requests[0].body_json::<Value>().unwrap(),
+            json!({ "docs": docs })
+        );
+        assert_eq!(
+            rows,
+            vec![
+                BulkRow {
+                    id: "a".to_string(),
+                    outcome: BulkOutcome::Written(Some("2-a".to_string()))
+                },
+                BulkRow {
+                    id: "b".to_string(),
+                    outcome: BulkOutcome::Conflict
+                },
+                BulkRow {
+                    id: "c".to_string(),
+                    outcome: BulkOutcome::Failed("forbidden: Missing owner".to_string())
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bulk_update_sends_the_write_quorum() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/db/_bulk_docs"))
+            .and(query_param("w", "2"))
+            .respond_with(
+                ResponseTemplate::new(201)
+                    .set_body_json(json!([{ "ok": true, "id": "a", "rev": "2-a" }])),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let docs = vec![json!({ "_id": "a", "_rev": "1-a" })];
+        let rows = bulk_update(&Client::new(), &server.uri(), "db", &docs, None, 0, Some(2))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            rows[0].outcome,
+            BulkOutcome::Written(Some("2-a".to_string()))
+        );
+    }
+}
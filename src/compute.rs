@@ -0,0 +1,238 @@
+use serde_json::{Map, Value};
+use std::str::FromStr;
+
+/// A field computed from other fields of the document, from `--compute "target = template"`.
+///
+/// The template is literal text in which `{field}` is replaced with the value of `field`
+/// (dot notation for nested fields, from the document root). Strings are inserted as they are,
+/// other values as JSON. `{{` and `}}` stand for literal braces.
+/// Whitespace around the target and the template is ignored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ComputeTemplate {
+    source: String,   // Definition as given on the command line
+    target: String,   // Field receiving the computed value (dot notation)
+    parts: Vec<Part>, // Parsed template
+}
+
+/// A piece of a template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String), // Text copied as it is
+    Field(String),   // Reference replaced with the value of the field
+}
+
+/// Why a computed field could not be set in a document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ComputeError {
+    Missing(Vec<String>), // Referenced fields that are missing or `null`
+    Blocked(String),      // A parent of the target that exists but is not an object
+}
+
+impl FromStr for ComputeTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (target, template) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid --compute '{}': expected 'target = template'", s))?;
+        let target = target.trim();
+        if target.is_empty() || target.split('.').any(str::is_empty) {
+            return Err(format!("Invalid --compute '{}': invalid target field", s));
+        }
+
+        let parts = parse_template(template.trim())
+            .map_err(|e| format!("Invalid --compute '{}': {}", s, e))?;
+        if !parts.iter().any(|part| matches!(part, Part::Field(_))) {
+            return Err(format!(
+                "Invalid --compute '{}': the template references no {{field}}",
+                s
+            ));
+        }
+
+        Ok(ComputeTemplate {
+            source: s.to_string(),
+            target: target.to_string(),
+            parts,
+        })
+    }
+}
+
+impl ComputeTemplate {
+    /// The definition as it was given.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// The field receiving the computed value.
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// The fields referenced by the template, each listed once, in order of appearance.
+    pub fn fields(&self) -> Vec<&str> {
+        let mut fields: Vec<&str> = Vec::new();
+        for part in &self.parts {
+            if let Part::Field(field) = part {
+                if !fields.contains(&field.as_str()) {
+                    fields.push(field);
+                }
+            }
+        }
+        fields
+    }
+
+    /// Renders the template against a document, or lists the referenced fields it lacks.
+    pub fn render(&self, doc: &Value) -> Result<String, Vec<String>> {
+        let mut rendered = String::new();
+        let mut missing = Vec::new();
+
+        for part in &self.parts {
+            match part {
+                Part::Literal(text) => rendered.push_str(text),
+                Part::Field(field) => match lookup(doc, field) {
+                    Some(Value::String(s)) => rendered.push_str(s),
+                    Some(value) => rendered.push_str(&value.to_string()),
+                    None if missing.contains(field) => {}
+                    None => missing.push(field.clone()),
+                },
+            }
+        }
+
+        match missing.is_empty() {
+            true => Ok(rendered),
+            false => Err(missing),
+        }
+    }
+
+    /// Sets the target field of a document to the rendered template, creating missing parent objects.
+    /// Returns whether the document changed.
+    pub fn apply(&self, doc: &mut Value) -> Result<bool, ComputeError> {
+        let value = Value::String(self.render(doc).map_err(ComputeError::Missing)?);
+
+        let path: Vec<&str> = self.target.split('.').collect();
+        let (key, parents) = path.split_last().unwrap(); // The target is never empty
+        let mut obj: &mut Map<String, Value> = doc
+            .as_object_mut()
+            .ok_or_else(|| ComputeError::Blocked(String::new()))?;
+        for (depth, parent) in parents.iter().enumerate() {
+            obj = obj
+                .entry(parent.to_string())
+                .or_insert_with(|| Value::Object(Map::new()))
+                .as_object_mut()
+                .ok_or_else(|| ComputeError::Blocked(parents[..=depth].join(".")))?;
+        }
+
+        match obj.get(*key) {
+            Some(existing) if *existing == value => Ok(false),
+            _ => {
+                obj.insert(key.to_string(), value);
+                Ok(true)
+            }
+        }
+    }
+}
+
+/// Splits a template into literal text and `{field}` references.
+fn parse_template(template: &str) -> Result<Vec<Part>, String> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '{' => {
+                let mut field = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some('{') | None => return Err("unclosed '{'".to_string()),
+                        Some(c) => field.push(c),
+                    }
+                }
+                let field = field.trim();
+                if field.is_empty() || field.split('.').any(str::is_empty) {
+                    return Err(format!("invalid field reference '{{{}}}'", field));
+                }
+                if !literal.is_empty() {
+                    parts.push(Part::Literal(std::mem::take(&mut literal)));
+                }
+                parts.push(Part::Field(field.to_string()));
+            }
+            '}' => return Err("unmatched '}' (use '}}' for a literal brace)".to_string()),
+            c => literal.push(c),
+        }
+    }
+
+    if !literal.is_empty() {
+        parts.push(Part::Literal(literal));
+    }
+    Ok(parts)
+}
+
+/// The value of a dot-notation field, unless it is missing or `null`.
+fn lookup<'v>(doc: &'v Value, field: &str) -> Option<&'v Value> {
+    field
+        .split('.')
+        .try_fold(doc, |value, key| value.get(key))
+        .filter(|value| !value.is_null())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_compute_renders_template() {
+        let compute: ComputeTemplate = "fullName = {firstName} {lastName}".parse().unwrap();
+        let mut doc = json!({ "firstName": "Ada", "lastName": "Lovelace" });
+
+        assert_eq!(compute.apply(&mut doc), Ok(true));
+        assert_eq!(doc["fullName"], "Ada Lovelace");
+        assert_eq!(compute.apply(&mut doc), Ok(false), "Already computed");
+
+        let compute: ComputeTemplate = "meta.key = {{{ type }:{n.id}}}".parse().unwrap();
+        let mut doc = json!({ "type": "order", "n": { "id": 42 } });
+        assert_eq!(compute.apply(&mut doc), Ok(true));
+        assert_eq!(doc["meta"], json!({ "key": "{order:42}" }));
+        assert_eq!(compute.fields(), vec!["type", "n.id"]);
+    }
+
+    #[test]
+    fn test_compute_reports_missing_fields() {
+        let compute: ComputeTemplate = "name = {first} {last} ({first})".parse().unwrap();
+        let mut doc = json!({ "last": null });
+
+        assert_eq!(
+            compute.apply(&mut doc),
+            Err(ComputeError::Missing(vec![
+                "first".to_string(),
+                "last".to_string()
+            ]))
+        );
+
+        let compute: ComputeTemplate = "a.b = {x}".parse().unwrap();
+        assert_eq!(
+            compute.apply(&mut json!({ "x": 1, "a": 2 })),
+            Err(ComputeError::Blocked("a".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_compute_rejects_invalid_templates() {
+        assert!("no equals sign".parse::<ComputeTemplate>().is_err());
+        assert!(" = {a}".parse::<ComputeTemplate>().is_err());
+        assert!("x = constant".parse::<ComputeTemplate>().is_err());
+        assert!("x = {a".parse::<ComputeTemplate>().is_err());
+        assert!("x = a}".parse::<ComputeTemplate>().is_err());
+        assert!("x = {}".parse::<ComputeTemplate>().is_err());
+    }
+}
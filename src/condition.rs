@@ -0,0 +1,378 @@
+use serde_json::Value;
+use std::str::FromStr;
+
+/// A boolean expression evaluated against a document, used to decide whether it is processed.
+///
+/// Supported syntax:
+/// - Field access with dot notation: `amount`, `customer.address.country` (missing fields are `null`)
+/// - Literals: numbers, `'single'` or `"double"` quoted strings, `true`, `false`, `null`
+/// - Comparisons: `==`, `!=`, `<`, `<=`, `>`, `>=` (ordering only applies to two numbers or two strings)
+/// - Logic: `and`/`&&`, `or`/`||`, `not`/`!`, and parentheses
+///
+/// An operand used on its own is true unless it is `null`, `false`, `0`, or an empty string.
+#[derive(Debug, Clone)]
+pub struct Condition {
+    source: String, // Expression as given on the command line
+    expr: Expr,     // Parsed expression
+}
+
+impl Condition {
+    /// Whether the document satisfies the condition.
+    pub fn matches(&self, doc: &Value) -> bool {
+        truthy(&self.expr.eval(doc))
+    }
+
+    /// The expression as it was given.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s).map_err(|e| format!("Invalid condition '{}': {}", s, e))?;
+        let mut parser = Parser { tokens, pos: 0 };
+
+        let expr = parser
+            .parse_or()
+            .map_err(|e| format!("Invalid condition '{}': {}", s, e))?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("Invalid condition '{}': unexpected {:?}", s, token));
+        }
+
+        Ok(Condition {
+            source: s.to_string(),
+            expr,
+        })
+    }
+}
+
+/// Comparison operators.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// Parsed expression tree.
+#[derive(Debug, Clone)]
+enum Expr {
+    Literal(Value),
+    Field(Vec<String>),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Compare(Box<Expr>, CmpOp, Box<Expr>),
+}
+
+impl Expr {
+    /// Evaluates the expression against a document.
+    fn eval(&self, doc: &Value) -> Value {
+        match self {
+            Expr::Literal(value) => value.clone(),
+            Expr::Field(path) => path
+                .iter()
+                .try_fold(doc, |value, key| value.get(key))
+                .cloned()
+                .unwrap_or(Value::Null),
+            Expr::Not(expr) => Value::Bool(!truthy(&expr.eval(doc))),
+            Expr::And(left, right) => {
+                Value::Bool(truthy(&left.eval(doc)) && truthy(&right.eval(doc)))
+            }
+            Expr::Or(left, right) => {
+                Value::Bool(truthy(&left.eval(doc)) || truthy(&right.eval(doc)))
+            }
+            Expr::Compare(left, op, right) => {
+                Value::Bool(compare(&left.eval(doc), *op, &right.eval(doc)))
+            }
+        }
+    }
+}
+
+/// Whether a value counts as true when used as a condition.
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64() != Some(0.0),
+        Value::String(s) => !s.is_empty(),
+        Value::Array(_) | Value::Object(_) => true,
+    }
+}
+
+/// Compares two values; numbers are compared by value regardless of their JSON representation.
+fn compare(left: &Value, op: CmpOp, right: &Value) -> bool {
+    let ordering = match (left, right) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64().partial_cmp(&b.as_f64()),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    };
+
+    match op {
+        CmpOp::Eq => ordering.map_or(left == right, |o| o.is_eq()),
+        CmpOp::Ne => ordering.map_or(left != right, |o| o.is_ne()),
+        CmpOp::Lt => ordering.is_some_and(|o| o.is_lt()),
+        CmpOp::Le => ordering.is_some_and(|o| o.is_le()),
+        CmpOp::Gt => ordering.is_some_and(|o| o.is_gt()),
+        CmpOp::Ge => ordering.is_some_and(|o| o.is_ge()),
+    }
+}
+
+/// Lexical tokens of a condition.
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Str(String),
+    Ident(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+    Dot,
+}
+
+/// Splits a condition into tokens.
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    const OPERATORS: [&str; 10] = ["==", "!=", "<=", ">=", "&&", "||", "<", ">", "!", "="];
+
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = input.chars().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '(' {
+            tokens.push(Token::LParen);
+            i += 1;
+        } else if c == ')' {
+            tokens.push(Token::RParen);
+            i += 1;
+        } else if c == '.'
+            && (follows_path(&tokens) || !chars.get(i + 1).is_some_and(char::is_ascii_digit))
+        {
+            tokens.push(Token::Dot);
+            i += 1;
+        } else if c == '\'' || c == '"' {
+            // Quoted string, with backslash escapes
+            let mut value = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err("unterminated string".to_string()),
+                    Some('\\') => {
+                        value.extend(chars.get(i + 1));
+                        i += 2;
+                    }
+                    Some(ch) if *ch == c => {
+                        i += 1;
+                        break;
+                    }
+                    Some(ch) => {
+                        value.push(*ch);
+                        i += 1;
+                    }
+                }
+            }
+            tokens.push(Token::Str(value));
+        } else if c.is_ascii_digit()
+            || c == '.'
+            || (c == '-' && chars.get(i + 1).is_some_and(char::is_ascii_digit))
+        {
+            let start = i;
+            i += 1;
+            // A dot only continues the number if a digit follows (`items.0.name` is a path)
+            while i < chars.len()
+                && (chars[i].is_ascii_digit()
+                    || (chars[i] == '.' && chars.get(i + 1).is_some_and(char::is_ascii_digit)))
+            {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            let number = text
+                .parse()
+                .map_err(|_| format!("invalid number '{}'", text))?;
+            tokens.push(Token::Number(number));
+        } else if c.is_alphabetic() || c == '_' || c == '$' {
+            let start = i;
+            while i < chars.len()
+                && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '$')
+            {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else {
+            let rest: String = chars[i..].iter().take(2).collect();
+            let op = OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(**op))
+                .ok_or_else(|| format!("unexpected character '{}'", c))?;
+            if *op == "=" {
+                return Err("use '==' to compare values".to_string());
+            }
+            tokens.push(Token::Op(op));
+            i += op.len();
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Whether the last token ends a field path, so that a following `.` separates path segments
+/// rather than starting a number (as in `items.0.name`).
+fn follows_path(tokens: &[Token]) -> bool {
+    matches!(
+        tokens,
+        [.., Token::Ident(_)] | [.., Token::Dot, Token::Number(_)]
+    )
+}
+
+/// Recursive-descent parser over the tokens of a condition.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    /// Consumes the next token if it is one of the given operators or keywords.
+    fn eat(&mut self, op: &str, keyword: &str) -> bool {
+        let matched = match self.peek() {
+            Some(Token::Op(o)) => *o == op,
+            Some(Token::Ident(word)) => word == keyword,
+            _ => false,
+        };
+        if matched {
+            self.pos += 1;
+        }
+        matched
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_and()?;
+        while self.eat("||", "or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_not()?;
+        while self.eat("&&", "and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_not()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, String> {
+        if self.eat("!", "not") {
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, String> {
+        let left = self.parse_operand()?;
+
+        let op = match self.peek() {
+            Some(Token::Op("==")) => CmpOp::Eq,
+            Some(Token::Op("!=")) => CmpOp::Ne,
+            Some(Token::Op("<")) => CmpOp::Lt,
+            Some(Token::Op("<=")) => CmpOp::Le,
+            Some(Token::Op(">")) => CmpOp::Gt,
+            Some(Token::Op(">=")) => CmpOp::Ge,
+            _ => return Ok(left),
+        };
+        self.pos += 1;
+
+        let right = self.parse_operand()?;
+        Ok(Expr::Compare(Box::new(left), op, Box::new(right)))
+    }
+
+    fn parse_operand(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Expr::Literal(serde_json::json!(n))),
+            Some(Token::Str(s)) => Ok(Expr::Literal(Value::String(s))),
+            Some(Token::LParen) => {
+                let expr = self.parse_or()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(expr),
+                    _ => Err("missing ')'".to_string()),
+                }
+            }
+            Some(Token::Ident(word)) => match word.as_str() {
+                "true" => Ok(Expr::Literal(Value::Bool(true))),
+                "false" => Ok(Expr::Literal(Value::Bool(false))),
+                "null" => Ok(Expr::Literal(Value::Null)),
+                _ => {
+                    // Field path in dot notation
+                    let mut path = vec![word];
+                    while self.peek() == Some(&Token::Dot) {
+                        self.pos += 1;
+                        match self.next() {
+                            Some(Token::Ident(key)) => path.push(key),
+                            Some(Token::Number(n)) if n.fract() == 0.0 => path.push(n.to_string()),
+                            _ => return Err("expected a field name after '.'".to_string()),
+                        }
+                    }
+                    Ok(Expr::Field(path))
+                }
+            },
+            Some(token) => Err(format!("unexpected {:?}", token)),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn matches(condition: &str, doc: &Value) -> bool {
+        condition.parse::<Condition>().unwrap().matches(doc)
+    }
+
+    #[test]
+    fn test_condition_comparisons_and_logic() {
+        let doc = json!({ "amount": 150, "currency": "USD", "customer": { "vip": true } });
+
+        assert!(matches("amount > 100 and currency == 'USD'", &doc));
+        assert!(!matches("amount > 100 && currency == \"EUR\"", &doc));
+        assert!(matches("amount <= 99 or customer.vip", &doc));
+        assert!(matches("not (amount < 100) && !missing", &doc));
+        assert!(matches("amount == 150.0 and missing == null", &doc));
+        assert!(
+            !matches("currency > 5", &doc),
+            "Mixed types are not ordered"
+        );
+        assert!(matches(
+            "lines.0.qty >= 2",
+            &json!({ "lines": { "0": { "qty": 2 } } })
+        ));
+    }
+
+    #[test]
+    fn test_condition_parse_errors() {
+        assert!("amount >".parse::<Condition>().is_err());
+        assert!("amount = 5".parse::<Condition>().is_err());
+        assert!("(amount > 5".parse::<Condition>().is_err());
+        assert!("'open".parse::<Condition>().is_err());
+        assert!("a b".parse::<Condition>().is_err());
+    }
+}
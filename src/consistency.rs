@@ -0,0 +1,88 @@
+use crate::iam::{authorize, IamAuth};
+use reqwest::{Client, StatusCode};
+use serde_json::Value;
+
+/// Fetches the table's current `update_seq` from its metadata.
+/// The value is opaque: a number in CouchDB 1.x, a string such as `"42-g1AAAA..."` since 2.x.
+pub async fn fetch_update_seq(
+    client: &Client,
+    db_host: &str,
+    table_name: &str,
+    auth: Option<&IamAuth>,
+) -> Result<Value, String> {
+    let url = format!("{}/{}", db_host, table_name);
+    let response = authorize(auth, client.get(&url))
+        .await?
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if response.status() != StatusCode::OK {
+        return Err(format!(
+            "Failed to fetch table metadata: Status code {}",
+            response.status()
+        ));
+    }
+
+    let json: Value = response.json().await.map_err(|e| e.to_string())?;
+    match json.get("update_seq") {
+        Some(seq) if !seq.is_null() => Ok(seq.clone()),
+        _ => Err("Table metadata has no 'update_seq'".to_string()),
+    }
+}
+
+/// Extracts the number of changes recorded by an `update_seq`: the number itself,
+/// or the numeric prefix of a clustered sequence string.
+pub fn seq_number(seq: &Value) -> Option<u64> {
+    match seq {
+        Value::Number(n) => n.as_u64(),
+        Value::String(s) => s.split('-').next()?.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Number of changes recorded between two `update_seq` values, if both can be compared.
+pub fn changes_between(start: &Value, end: &Value) -> Option<u64> {
+    Some(seq_number(end)?.saturating_sub(seq_number(start)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn test_changes_between_sequences() {
+        assert_eq!(changes_between(&json!(10), &json!(25)), Some(15));
+        assert_eq!(
+            changes_between(&json!("7-g1AAAA"), &json!("12-g1AAAB")),
+            Some(5)
+        );
+        assert_eq!(
+            changes_between(&json!("7-g1AAAA"), &json!("7-g1AAAA")),
+            Some(0)
+        );
+        assert_eq!(changes_between(&json!("opaque"), &json!("12-g1AAAB")), None);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_update_seq() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/orders"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({ "doc_count": 3, "update_seq": "42-g1AAAA" })),
+            )
+            .mount(&server)
+            .await;
+
+        let seq = fetch_update_seq(&Client::new(), &server.uri(), "orders", None).await;
+        assert_eq!(seq, Ok(json!("42-g1AAAA")));
+
+        let missing = fetch_update_seq(&Client::new(), &server.uri(), "missing", None).await;
+        assert!(missing.is_err());
+    }
+}
@@ -0,0 +1,211 @@
+use serde_json::Value;
+
+/// A difference between two versions of a document, at a field path in dot notation
+/// (array elements as `[N]`, like `split_path` reads them).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Renamed {
+        from: String,
+        to: String,
+        value: Value,
+    }, // A value moved from one field to another
+    Removed {
+        path: String,
+        value: Value,
+    }, // A field only present before
+    Added {
+        path: String,
+        value: Value,
+    }, // A field only present after
+    Modified {
+        path: String,
+        before: Value,
+        after: Value,
+    }, // A field whose value changed
+}
+
+/// Lists the fields that differ between two versions of a document. Objects are compared key by
+/// key, and arrays of the same length element by element; other values are compared as a whole.
+/// A removed field and an added field holding the same value are reported as a rename.
+pub fn diff_documents(before: &Value, after: &Value) -> Vec<Change> {
+    let mut changes = Vec::new();
+    diff_values("", before, after, &mut changes);
+    pair_renames(changes)
+}
+
+/// Recursive worker for `diff_documents`, comparing the values found at `path`.
+fn diff_values(path: &str, before: &Value, after: &Value, changes: &mut Vec<Change>) {
+    match (before, after) {
+        (Value::Object(old), Value::Object(new)) => {
+            let child = |key: &str| match path {
+                "" => key.to_string(),
+                _ => format!("{}.{}", path, key),
+            };
+            for (key, value) in old {
+                match new.get(key) {
+                    Some(new_value) => diff_values(&child(key), value, new_value, changes),
+                    None => changes.push(Change::Removed {
+                        path: child(key),
+                        value: value.clone(),
+                    }),
+                }
+            }
+            for (key, value) in new.iter().filter(|(key, _)| !old.contains_key(*key)) {
+                changes.push(Change::Added {
+                    path: child(key),
+                    value: value.clone(),
+                });
+            }
+        }
+        (Value::Array(old), Value::Array(new)) if old.len() == new.len() => {
+            for (index, (old, new)) in old.iter().zip(new).enumerate() {
+                diff_values(&format!("{}[{}]", path, index), old, new, changes);
+            }
+        }
+        _ if before != after => changes.push(Change::Modified {
+            path: path.to_string(),
+            before: before.clone(),
+            after: after.clone(),
+        }),
+        _ => {}
+    }
+}
+
+/// Merges each removed field with the first added field holding the same value into a rename.
+fn pair_renames(changes: Vec<Change>) -> Vec<Change> {
+    let mut added: Vec<Option<Change>> = changes
+        .iter()
+        .map(|change| match change {
+            Change::Added { .. } => Some(change.clone()),
+            _ => None,
+        })
+        .collect();
+
+    let mut paired = Vec::new();
+    for change in changes {
+        match change {
+            Change::Removed { path, value } => {
+                let found = added.iter_mut().find(|candidate| {
+                    matches!(candidate, Some(Change::Added { value: added, .. }) if *added == value)
+                });
+                match found.and_then(Option::take) {
+                    Some(Change::Added { path: to, .. }) => paired.push(Change::Renamed {
+                        from: path,
+                        to,
+                        value,
+                    }),
+                    _ => paired.push(Change::Removed { path, value }),
+                }
+            }
+            Change::Added { .. } => {}
+            change => paired.push(change),
+        }
+    }
+
+    // The added fields left over are reported after the others
+    paired.extend(added.into_iter().flatten());
+    paired
+}
+
+/// Renders the changes one per line: `old -> new: value` for a rename, `- path: value`,
+/// `+ path: value`, and `~ path: before -> after`. Long values are cut short.
+pub fn render_diff(changes: &[Change]) -> Vec<String> {
+    changes
+        .iter()
+        .map(|change| match change {
+            Change::Renamed { from, to, value } => {
+                format!("{} -> {}: {}", from, to, preview(value))
+            }
+            Change::Removed { path, value } => format!("- {}: {}", path, preview(value)),
+            Change::Added { path, value } => format!("+ {}: {}", path, preview(value)),
+            Change::Modified {
+                path,
+                before,
+                after,
+            } => format!("~ {}: {} -> {}", path, preview(before), preview(after)),
+        })
+        .collect()
+}
+
+/// Renders both versions of a document in full, pretty-printed, one line of JSON per line.
+pub fn render_before_after(before: &Value, after: &Value) -> Vec<String> {
+    let pretty = |doc: &Value| serde_json::to_string_pretty(doc).unwrap_or_default();
+    let mut lines = vec!["before:".to_string()];
+    lines.extend(pretty(before).lines().map(String::from));
+    lines.push("after:".to_string());
+    lines.extend(pretty(after).lines().map(String::from));
+    lines
+}
+
+/// The JSON of a value, cut short past 60 characters.
+fn preview(value: &Value) -> String {
+    const MAX_CHARS: usize = 60;
+
+    let json = value.to_string();
+    match json.char_indices().nth(MAX_CHARS) {
+        Some((end, _)) => format!("{}...", &json[..end]),
+        None => json,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_diff_shows_removed_and_added_keys() {
+        let before = json!({
+            "_id": "a",
+            "_rev": "1-a",
+            "user": { "fname": "Ada", "tel": "123" },
+            "items": [{ "code": "x" }, { "code": "y", "qty": 1 }],
+        });
+        let after = json!({
+            "_id": "a",
+            "_rev": "1-a",
+            "user": { "first_name": "Ada", "phone": "0123" },
+            "items": [{ "sku": "x" }, { "sku": "y", "qty": 2 }],
+            "migrated": true,
+        });
+
+        assert_eq!(
+            render_diff(&diff_documents(&before, &after)),
+            vec![
+                "user.fname -> user.first_name: \"Ada\"",
+                "- user.tel: \"123\"",
+                "items[0].code -> items[0].sku: \"x\"",
+                "items[1].code -> items[1].sku: \"y\"",
+                "~ items[1].qty: 1 -> 2",
+                "+ user.phone: \"0123\"",
+                "+ migrated: true",
+            ]
+        );
+        assert!(diff_documents(&before, &before).is_empty());
+    }
+
+    #[test]
+    fn test_before_after_and_long_values() {
+        let before = json!({ "note": "a".repeat(100) });
+        let after = json!({ "text": "a".repeat(100) });
+
+        let lines = render_diff(&diff_documents(&before, &after));
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("note -> text: \"aaa"));
+        assert!(lines[0].ends_with("..."));
+
+        assert_eq!(
+            render_before_after(&json!({ "a": 1 }), &json!({ "b": 1 })),
+            vec![
+                "before:",
+                "{",
+                "  \"a\": 1",
+                "}",
+                "after:",
+                "{",
+                "  \"b\": 1",
+                "}"
+            ]
+        );
+    }
+}
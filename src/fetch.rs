@@ -1,17 +1,130 @@
-use reqwest::{Client, StatusCode};
+use crate::iam::{authorize, IamAuth};
+use crate::ratelimit::{ConcurrencyLimit, RateLimiter};
+use crate::retry::send_with_retry;
+use crate::source::DocumentSource;
+use crate::transform::Pipeline;
+use reqwest::header::HeaderMap;
+use reqwest::{Client, RequestBuilder, StatusCode};
 use serde_json::{from_str, Value};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+/// Strategy used to page through the documents of a table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pagination {
+    Bookmark, // Use the bookmark returned by CouchDB with each page
+    Id,       // Use the last seen `_id` as the exclusive lower bound of the next page
+}
+
+impl FromStr for Pagination {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bookmark" => Ok(Pagination::Bookmark),
+            "id" => Ok(Pagination::Id),
+            _ => Err(format!(
+                "Unknown pagination '{}'. Expected one of: id, bookmark.",
+                s
+            )),
+        }
+    }
+}
+
+/// Order in which the documents of a table are scanned, by `_id`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanOrder {
+    Asc,  // Lowest `_id` first
+    Desc, // Highest `_id` first
+}
+
+impl ScanOrder {
+    /// The Mango sort direction.
+    fn direction(self) -> &'static str {
+        match self {
+            ScanOrder::Asc => "asc",
+            ScanOrder::Desc => "desc",
+        }
+    }
+}
+
+impl FromStr for ScanOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "asc" => Ok(ScanOrder::Asc),
+            "desc" => Ok(ScanOrder::Desc),
+            _ => Err(format!(
+                "Unknown scan order '{}'. Expected one of: asc, desc.",
+                s
+            )),
+        }
+    }
+}
 
 /// A struct to fetch documents from a CouchDB database.
 /// It supports pagination, partitioned tables, and applying a callback to each document.
 pub struct FetchDocument<'a> {
-    client: Client,                          // HTTP client for making requests
-    db_host: String,                         // Base URL of the CouchDB instance
-    table_name: String,                      // Name of the database or table
-    is_partitioned: bool,                    // Indicates if the table is partitioned
-    callback: Box<dyn Fn(Value) -> () + 'a>, // Callback function to process each document
-    bookmark: Option<String>,                // Bookmark for pagination
-    limit: usize,                            // Maximum number of documents to fetch per request
-    doc_count: usize,                        // Total number of documents in the table
+    client: Client,                                  // HTTP client for making requests
+    db_host: String,                                 // Base URL of the CouchDB instance
+    table_name: String,                              // Name of the database or table
+    is_partitioned: bool,                            // Indicates if the table is partitioned
+    callback: Box<dyn Fn(Value) + Send + Sync + 'a>, // Callback function to process each document
+    batch_callback: Option<Box<dyn Fn(BatchInfo) + Send + Sync + 'a>>, // Called once each batch has been applied
+    bookmark: Option<String>,                                          // Bookmark for pagination
+    limit: usize,                  // Maximum number of documents to fetch per request
+    doc_count: usize,              // Total number of documents in the table
+    max_iterations: Option<usize>, // Optional cap on the number of batches to fetch
+    selector: Value,               // Mango selector used to query documents
+    auto_create_index: bool,       // Whether to create the recommended index when none matches
+    index_warning_handled: bool,   // Whether the missing-index warning was already acted upon
+    pagination: Pagination,        // Strategy used to page through the table
+    last_id: Option<String>,       // Last `_id` seen, used by `_id`-range pagination
+    id_prefix: Option<String>,     // Restrict the scan to `_id`s with this prefix via `_all_docs`
+    id_range: Option<IdRange>,     // Restrict the scan to a range of `_id`s via `_all_docs`
+    id_field: String,              // Name of the document ID field
+    prefetch: usize,               // Number of batches fetched ahead of the one being applied
+    fields: Option<Vec<String>>,   // Optional projection of the fields returned by `_find`
+    max_documents: Option<usize>,  // Optional cap on the total number of documents fetched
+    max_retries: usize,            // Number of times a transiently failing request is retried
+    scan_order: ScanOrder,         // Order in which documents are scanned by `_id`
+    auth: Option<IamAuth>,         // IAM authentication attached to every request, if configured
+    stop: Option<Arc<AtomicBool>>, // Signal raised by the caller to end the scan after the current batch
+    cancel: Option<CancellationToken>, // Token cancelled by an embedding application to end the scan after the current batch
+    headers: HeaderMap,                // Extra headers sent with every request
+    fetched: usize,                    // Number of documents fetched so far
+    page_rows: usize, // Number of rows the server returned for the last page, before filtering
+    bookmark_advanced: bool, // Whether the last `_find` page returned a bookmark different from the previous one
+    label: Option<String>,   // Prefix of the progress lines, telling concurrent scans apart
+    read_quorum: Option<usize>, // Number of replicas that must answer each `_find` page, if not the server default
+    pipeline: Option<Pipeline>, // Stages applied to each document before the callback, changed documents being written back
+    raw_id: bool, // Whether document IDs are put in the pipeline's write URLs as they are
+    write_limiter: Option<Arc<RateLimiter>>, // Token bucket shared by the pipeline's writes, if any
+    write_slots: Option<Arc<ConcurrencyLimit>>, // Bounds the pipeline's writes in flight, if given
+    partition: Option<String>, // Restrict the scan to a partition of a partitioned table
+    pages: usize, // Number of pages fetched so far
+    finished: bool, // Whether the last page of the scan was fetched
+}
+
+/// What is known about a batch once the callback has been applied to each of its documents.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BatchInfo {
+    pub iteration: usize,         // Number of the batch, starting at 1
+    pub documents: usize,         // Number of documents passed to the callback
+    pub fetch_duration: Duration, // Time taken by the request fetching the batch
+}
+
+/// A half-open range of `_id`s, `[start, end)`; a missing bound leaves that side open.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IdRange {
+    pub start: Option<String>, // Lowest `_id` of the range, inclusive
+    pub end: Option<String>,   // Highest `_id` of the range, exclusive
 }
 
 impl<'a> FetchDocument<'a> {
@@ -23,48 +136,353 @@ impl<'a> FetchDocument<'a> {
             table_name,
             is_partitioned: false,      // Default to not partitioned
             callback: Box::new(|_| ()), // Default callback does nothing
+            batch_callback: None,       // No per-batch reporting
             bookmark: None,             // No initial bookmark
             limit,
-            doc_count: 0, // Document count starts at 0
+            doc_count: 0,         // Document count starts at 0
+            max_iterations: None, // No cap on the number of batches
+            selector: serde_json::json!({
+                "_id": {
+                    "$gt": null // Fetch all documents with _id greater than null
+                }
+            }),
+            auto_create_index: false, // Only print the recommended index by default
+            index_warning_handled: false, // No warning seen yet
+            pagination: Pagination::Bookmark, // CouchDB bookmarks by default
+            last_id: None,            // No document seen yet
+            id_prefix: None,          // Scan the whole table
+            id_range: None,           // No `_id` bounds
+            id_field: "_id".to_string(), // CouchDB's ID field
+            prefetch: 0,              // Fetch and apply strictly in turn
+            fields: None,             // Return whole documents
+            max_documents: None,      // No cap on the number of documents
+            max_retries: 0,           // Transient failures are not retried
+            scan_order: ScanOrder::Asc, // Lowest `_id` first
+            auth: None,               // Credentials only come from the URL, if any
+            stop: None,               // Scan until the end of data or a cap
+            cancel: None,             // Not cancellable
+            headers: HeaderMap::new(), // No extra headers
+            fetched: 0,               // Nothing fetched yet
+            page_rows: 0,             // No page fetched yet
+            bookmark_advanced: false, // No bookmark returned yet
+            label: None,              // Unlabeled progress lines
+            read_quorum: None,        // Server's default read quorum
+            pipeline: None,           // Documents are only passed to the callback
+            raw_id: false,            // Percent-encode document IDs
+            write_limiter: None,      // Writes are not rate-limited
+            write_slots: None,        // The writes of a batch all run at once
+            partition: None,          // Scan every partition
+            pages: 0,                 // No page fetched yet
+            finished: false,          // Scan not started yet
         }
     }
 
     /// Sets the callback function to be applied to each fetched document.
-    pub fn with_callback(mut self, callback: Box<dyn Fn(Value) -> () + 'a>) -> Self {
+    pub fn with_callback(mut self, callback: Box<dyn Fn(Value) + Send + Sync + 'a>) -> Self {
         self.callback = callback; // Assign the provided callback
         self
     }
 
+    /// Runs each fetched document through `pipeline` before the callback. Documents changed by
+    /// any stage are written back to the table (keeping their `_rev`, so a document changed
+    /// meanwhile fails with a conflict), then passed to the callback as they were written.
+    /// The changed documents of a batch are written concurrently, within the limits set by
+    /// `with_write_limiter` and `with_write_slots`, before the batch is passed to the callback.
+    /// Failed writes are reported on stderr; `FetchSummary` counts the changed documents
+    /// (`matched`) and how many of them were `written` or `failed`.
+    pub fn with_pipeline(mut self, pipeline: Pipeline) -> Self {
+        self.pipeline = Some(pipeline);
+        self
+    }
+
+    /// Puts document IDs in the pipeline's write URLs as they are (see `encode_doc_id`).
+    pub fn with_raw_id(mut self, raw_id: bool) -> Self {
+        self.raw_id = raw_id;
+        self
+    }
+
+    /// Takes a token from `limiter` before each of the pipeline's writes, e.g. to share a
+    /// write budget with the caller's own writes.
+    pub fn with_write_limiter(mut self, limiter: Arc<RateLimiter>) -> Self {
+        self.write_limiter = Some(limiter);
+        self
+    }
+
+    /// Holds one of the `slots` during each of the pipeline's writes, e.g. to share a bound
+    /// on the writes in flight with the caller's own writes.
+    pub fn with_write_slots(mut self, slots: Arc<ConcurrencyLimit>) -> Self {
+        self.write_slots = Some(slots);
+        self
+    }
+
+    /// Sets a callback called after each batch has been applied, e.g. to report per-batch statistics.
+    pub fn with_batch_callback(
+        mut self,
+        callback: Box<dyn Fn(BatchInfo) + Send + Sync + 'a>,
+    ) -> Self {
+        self.batch_callback = Some(callback);
+        self
+    }
+
+    /// Caps the number of batches fetched, stopping early even if more documents remain.
+    pub fn with_max_iterations(mut self, max_iterations: usize) -> Self {
+        self.max_iterations = Some(max_iterations);
+        self
+    }
+
+    /// Caps the total number of documents fetched, independently of the batch size.
+    /// The batch that reaches the cap is cut short and the scan stops.
+    pub fn with_max_documents(mut self, max_documents: usize) -> Self {
+        self.max_documents = Some(max_documents);
+        self
+    }
+
+    /// Creates the recommended index automatically when CouchDB reports that no index matches the query.
+    /// Without this, the index-creation request is only printed.
+    pub fn with_auto_create_index(mut self, auto_create_index: bool) -> Self {
+        self.auto_create_index = auto_create_index;
+        self
+    }
+
+    /// Sets the strategy used to page through the table.
+    /// `_id`-range pagination does not rely on server bookmarks and never skips or repeats documents,
+    /// since each page is sorted by `_id` and starts strictly after the last `_id` seen.
+    pub fn with_pagination(mut self, pagination: Pagination) -> Self {
+        self.pagination = pagination;
+        self
+    }
+
+    /// Retries a page request up to `max_retries` times when it fails transiently
+    /// (see `send_with_retry`).
+    pub fn with_max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Sets the order in which documents are scanned by `_id`.
+    /// A descending scan sorts pages by `_id` in reverse and, with `_id`-range pagination,
+    /// bounds each page below the last `_id` seen instead of above it.
+    pub fn with_scan_order(mut self, scan_order: ScanOrder) -> Self {
+        self.scan_order = scan_order;
+        self
+    }
+
+    /// Restricts the scan to documents whose `_id` starts with `prefix`.
+    /// The documents are read from `_all_docs` using a key range, so no index or selector is needed.
+    pub fn with_id_prefix(mut self, prefix: String) -> Self {
+        self.id_prefix = Some(prefix);
+        self
+    }
+
+    /// Restricts the scan to one partition of a partitioned table. `_find` pages are then queried
+    /// through `/{table}/_partition/{partition}/_find`, which only reads that partition's index,
+    /// and progress is reported against the partition's document count.
+    /// On a table that is not partitioned, the documents whose `_id` starts with `partition:`
+    /// are scanned instead, as with `with_id_prefix`.
+    pub fn with_partition(mut self, partition: String) -> Self {
+        self.partition = Some(partition);
+        self
+    }
+
+    /// Restricts the scan to the documents whose `_id` falls in `range`, e.g. one shard of the table.
+    /// The documents are read in ascending order from `_all_docs`; design documents are skipped.
+    pub fn with_id_range(mut self, range: IdRange) -> Self {
+        self.id_range = Some(range);
+        self
+    }
+
+    /// Starts the scan right after the document `id` (exclusive), e.g. to restart a run that died.
+    /// Only meaningful when pages are ordered by `_id`: with `_id`-range pagination or an `_id` prefix.
+    pub fn with_resume_from_id(mut self, id: String) -> Self {
+        self.last_id = Some(id);
+        self
+    }
+
+    /// Prefixes the progress lines with `[label]`, e.g. to tell the shards of a scan apart.
+    pub fn with_label(mut self, label: String) -> Self {
+        self.label = Some(label);
+        self
+    }
+
+    /// Sets the name of the document ID field, for CouchDB-compatible stores that do not use `_id`.
+    /// The default selector and `_id`-range pagination use this field instead of `_id`.
+    pub fn with_id_field(mut self, id_field: String) -> Self {
+        let custom_selector = self.selector != self.default_selector();
+        self.id_field = id_field;
+        if !custom_selector {
+            self.selector = self.default_selector();
+        }
+        self
+    }
+
+    /// Replaces the default selector, which matches every document, with a Mango selector,
+    /// so that `_find` only returns the matching documents.
+    /// Scans of `_all_docs` (an `_id` prefix or range) are not filtered by the selector.
+    pub fn with_selector(mut self, selector: Value) -> Self {
+        self.selector = selector;
+        self
+    }
+
+    /// Authenticates every request with an IAM bearer token (e.g. for IBM Cloudant).
+    pub fn with_iam_auth(mut self, auth: IamAuth) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    /// Sends the given headers with every request, e.g. an API gateway key or tenant routing header.
+    pub fn with_headers(mut self, headers: HeaderMap) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    /// Asks `_find` to wait for `read_quorum` replicas to answer each page (the `r` parameter).
+    /// `_all_docs` scans ignore it, since CouchDB does not accept a read quorum there.
+    pub fn with_read_quorum(mut self, read_quorum: usize) -> Self {
+        self.read_quorum = Some(read_quorum);
+        self
+    }
+
+    /// Returns only these fields of each document, e.g. `_id` and `_rev` to fetch the bodies
+    /// separately. `_find` pages get a projection instead of `include_docs`, and `_all_docs`
+    /// pages are reduced to the ID of each row.
+    pub fn with_fields(mut self, fields: Vec<String>) -> Self {
+        self.fields = Some(fields);
+        self
+    }
+
+    /// Ends the scan after the current batch once `stop` is set, e.g. when the caller gives up.
+    pub fn with_stop_signal(mut self, stop: Arc<AtomicBool>) -> Self {
+        self.stop = Some(stop);
+        self
+    }
+
+    /// Makes the scan cancellable from another task, e.g. by a service embedding the migration.
+    /// Once `token` is cancelled, no new batch is fetched: the batch being applied (and, with
+    /// prefetching, the batches already fetched) are passed to the callback, and `execute`
+    /// returns the summary of what was fetched so far.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// Fetches up to `prefetch` batches ahead while the current batch is being applied.
+    /// Batches are still fetched one after another, so the pagination order is unchanged.
+    /// A value of 0 disables prefetching.
+    pub fn with_prefetch(mut self, prefetch: usize) -> Self {
+        self.prefetch = prefetch;
+        self
+    }
+
     /// Executes the document fetching process.
     /// - Fetches metadata about the table.
-    /// - Fetches documents in batches and applies the callback to each document.
-    pub async fn execute(mut self) {
+    /// - Fetches documents in batches and applies the callback to each document (see `Feed`).
+    /// - Returns a summary of the run, or the error that prevented reading the table metadata
+    ///   or one of its pages.
+    pub async fn execute(mut self) -> Result<FetchSummary, String> {
+        let started = Instant::now(); // Start time of the run, used for the summary duration
+
         // Fetch metadata about the table (e.g., partitioned status, document count)
-        self.get_metadata().await.unwrap();
+        self.get_metadata().await.map_err(|e| e.to_string())?;
 
-        let mut count = 1; // Counter for tracking the number of iterations
-        let mut total_record = 0; // Total number of records fetched so far
+        // Hand the callbacks and the pipeline over to a feed pulling the batches of this scan
+        let feed = Feed {
+            callback: std::mem::replace(&mut self.callback, Box::new(|_| ())),
+            batch_callback: self.batch_callback.take(),
+            pipeline: self.pipeline_writer(),
+            prefetch: self.prefetch,
+            label: self.label.clone(),
+            doc_count: self.doc_count,
+        };
+        let summary = feed.run(&mut self).await?;
 
-        loop {
-            // Fetch a batch of documents and apply the callback
-            let num_of_record = self.fetch_and_apply().await.unwrap();
-            total_record += num_of_record;
-
-            // Log progress
-            println!(
-                "Fetched {}/{} transactions. Iteration: {}",
-                total_record, self.doc_count, count
-            );
+        Ok(FetchSummary {
+            table_name: self.table_name.clone(),
+            doc_count: self.doc_count,
+            duration_secs: started.elapsed().as_secs_f64(),
+            ..summary
+        })
+    }
 
-            // Break the loop if fewer records than the limit are returned (end of data)
-            if num_of_record < self.limit {
+    /// Counts the documents matching the selector without passing them to the callback.
+    /// The unfiltered table is counted from its metadata; otherwise the matching documents
+    /// are paged through with a projection on the ID field only.
+    pub async fn count(mut self) -> Result<usize, String> {
+        self.get_metadata().await.map_err(|e| e.to_string())?;
+
+        if self.id_prefix.is_none()
+            && self.id_range.is_none()
+            && self.selector == self.default_selector()
+        {
+            return Ok(self.doc_count);
+        }
+
+        self.fields = Some(vec![self.id_field.clone()]);
+        let mut total_record = 0;
+        loop {
+            total_record += self.fetch_page().await?.len();
+            if self.is_end_of_data(self.page_rows) {
                 break;
             }
+        }
+
+        Ok(total_record)
+    }
+
+    /// The selector matching every document, used unless another one is given.
+    fn default_selector(&self) -> Value {
+        serde_json::json!({ &self.id_field: { "$gt": null } })
+    }
+
+    /// Hands the pipeline, if any, to a writer using this scan's connection settings.
+    fn pipeline_writer(&mut self) -> Option<Arc<PipelineWriter>> {
+        Some(Arc::new(PipelineWriter {
+            pipeline: self.pipeline.take()?,
+            client: self.client.clone(),
+            table_url: format!("{}/{}", self.db_host, self.table_name),
+            id_field: self.id_field.clone(),
+            raw_id: self.raw_id,
+            auth: self.auth.clone(),
+            headers: self.headers.clone(),
+            max_retries: self.max_retries,
+            limiter: self.write_limiter.clone(),
+            slots: self.write_slots.clone(),
+        }))
+    }
 
-            count += 1; // Increment the iteration counter
+    /// Whether the last page, of `num_of_record` rows, was the end of the data.
+    ///
+    /// `_find` may return a short page before the end, or a full page and an unchanged bookmark
+    /// at the end, so bookmark pagination ends on an empty page or a bookmark that stops advancing.
+    /// `_id` bounds and `_all_docs` key ranges only return fewer rows than the limit at the end.
+    fn is_end_of_data(&self, num_of_record: usize) -> bool {
+        let bookmarked = self.pagination == Pagination::Bookmark
+            && self.id_prefix.is_none()
+            && self.id_range.is_none();
+        match bookmarked {
+            true => num_of_record == 0 || !self.bookmark_advanced,
+            false => num_of_record < self.limit,
         }
     }
 
+    /// Whether the scan stops after a page of `num_of_record` rows fetched in iteration `count`.
+    fn is_last_page(&self, num_of_record: usize, count: usize) -> bool {
+        // The end of data or the optional batch and document caps end the scan
+        self.is_end_of_data(num_of_record)
+            || self.max_iterations.is_some_and(|max| count >= max)
+            || self.max_documents.is_some_and(|max| self.fetched >= max)
+    }
+
+    /// Whether the caller asked for the scan to end, through the stop signal or cancellation.
+    fn is_stopped(&self) -> bool {
+        self.stop
+            .as_ref()
+            .is_some_and(|stop| stop.load(Ordering::Relaxed))
+            || self
+                .cancel
+                .as_ref()
+                .is_some_and(CancellationToken::is_cancelled)
+    }
+
     /// Fetches metadata about the table, including whether it is partitioned and the total document count.
     async fn get_metadata(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         // Construct the URL for fetching table metadata
@@ -72,8 +490,8 @@ impl<'a> FetchDocument<'a> {
 
         // Send a GET request to fetch metadata
         let response = self
-            .client
-            .get(&url)
+            .prepare(self.client.get(&url))
+            .await?
             .send()
             .await
             .map_err(|e| e.to_string())?;
@@ -99,41 +517,113 @@ impl<'a> FetchDocument<'a> {
 
         // Log whether the table is partitioned
         if self.is_partitioned {
-            println!("Table '{}' is partitioned.", self.table_name);
+            info!("Table '{}' is partitioned.", self.table_name);
         } else {
-            println!("Table '{}' is not partitioned.", self.table_name);
+            info!("Table '{}' is not partitioned.", self.table_name);
+        }
+
+        // Scope the scan to the requested partition, if any
+        if let Some(partition) = self.partition.clone() {
+            if self.is_partitioned {
+                self.doc_count = self.partition_doc_count(&partition).await?;
+            } else {
+                warn!(
+                    "table '{}' is not partitioned; scanning the _ids starting with '{}:' instead.",
+                    self.table_name, partition
+                );
+                self.id_prefix.get_or_insert(format!("{}:", partition));
+            }
         }
 
         Ok(())
     }
 
-    /// Fetches a batch of documents and applies the callback to each document.
-    async fn fetch_and_apply(&mut self) -> Result<usize, String> {
-        // Construct the URL for fetching documents
+    /// Fetches the number of documents in a partition of the table.
+    async fn partition_doc_count(&self, partition: &str) -> Result<usize, String> {
         let url = format!(
-            "{}/{}/_find?include_docs=true",
-            self.db_host, self.table_name
+            "{}/{}/_partition/{}",
+            self.db_host,
+            self.table_name,
+            urlencoding::encode(partition)
         );
+        let response = self
+            .prepare(self.client.get(&url))
+            .await?
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if response.status() != StatusCode::OK {
+            return Err(format!(
+                "Failed to fetch partition '{}' metadata: Status code {}",
+                partition,
+                response.status()
+            ));
+        }
+
+        let json: Value = response.json().await.map_err(|e| e.to_string())?;
+        Ok(json["doc_count"].as_u64().unwrap_or(0) as usize)
+    }
+
+    /// Adds the extra headers and the IAM bearer token, if configured, to a request.
+    async fn prepare(&self, request: RequestBuilder) -> Result<RequestBuilder, String> {
+        authorize(self.auth.as_ref(), request.headers(self.headers.clone())).await
+    }
+
+    /// Fetches the next batch of documents.
+    async fn fetch_page(&mut self) -> Result<Vec<Value>, String> {
+        // Fetch the next page from `_all_docs` when scoped to a prefix or range, otherwise from `_find`
+        let mut rows = match (self.id_prefix.clone(), self.id_range.clone()) {
+            (Some(prefix), _) => self.fetch_prefix_page(&prefix).await?,
+            (None, Some(range)) => self.fetch_range_page(&range).await?,
+            (None, None) => self.fetch_find_page().await?,
+        };
+
+        // Drop the documents beyond the optional cap on the total number of documents
+        if let Some(max) = self.max_documents {
+            rows.truncate(max.saturating_sub(self.fetched));
+        }
+        self.fetched += rows.len();
+
+        // Remember the last `_id` for `_id`-range pagination
+        if let Some(last_id) = rows.last().and_then(|doc| doc[&self.id_field].as_str()) {
+            self.last_id = Some(last_id.to_string());
+        }
+
+        Ok(rows)
+    }
+
+    /// Fetches the next page of documents through the `_find` endpoint.
+    async fn fetch_find_page(&mut self) -> Result<Vec<Value>, String> {
+        // Construct the URL for fetching documents, within the partition if one is requested;
+        // whole documents are only asked for without a projection
+        let query = match self.fields {
+            Some(_) => "",
+            None => "?include_docs=true",
+        };
+        let url = match (&self.partition, self.is_partitioned) {
+            (Some(partition), true) => format!(
+                "{}/{}/_partition/{}/_find{}",
+                self.db_host,
+                self.table_name,
+                urlencoding::encode(partition),
+                query
+            ),
+            _ => format!("{}/{}/_find{}", self.db_host, self.table_name, query),
+        };
 
         // Create the query selector JSON
-        let selector = serde_json::to_string(&SelectorContent {
-            selector: serde_json::json!({
-                "_id": {
-                    "$gt": null // Fetch all documents with _id greater than null
-                }
-            }),
-            limit: self.limit as i32, // Limit the number of documents per request
-            bookmark: self.bookmark.clone(), // Use the bookmark for pagination
-        })
-        .map_err(|e| e.to_string())?;
+        let selector =
+            serde_json::to_string(&self.selector_content()).map_err(|e| e.to_string())?;
 
-        // Send the POST request to fetch documents
-        let client = reqwest::Client::new();
-        let response = client
+        // Send the POST request to fetch documents, through the pooled client
+        let request = self
+            .client
             .post(&url)
             .header("Content-Type", "application/json")
-            .body(selector)
-            .send()
+            .body(selector);
+        let request = self.prepare(request).await?;
+        let response = send_with_retry(request, self.max_retries)
             .await
             .map_err(|e| e.to_string())?;
 
@@ -147,31 +637,1804 @@ impl<'a> FetchDocument<'a> {
 
         // Parse the response body as JSON
         let body = response.text().await.map_err(|e| e.to_string())?;
-        let json: Value = from_str(&body).map_err(|e| e.to_string())?;
+        let mut json: Value = from_str(&body).map_err(|e| e.to_string())?;
+
+        // Act on the server's hint that the query is not backed by an index (once per run)
+        if !self.index_warning_handled
+            && json["warning"]
+                .as_str()
+                .is_some_and(|warning| warning.contains("No matching index"))
+        {
+            self.index_warning_handled = true;
+            self.handle_missing_index().await;
+        }
 
-        // Extract the bookmark for pagination
-        self.bookmark = json["bookmark"].as_str().map(String::from);
+        // Extract the bookmark for pagination, noting whether it moved past the previous page
+        let bookmark = json["bookmark"].as_str().map(String::from);
+        self.bookmark_advanced = bookmark.is_some() && bookmark != self.bookmark;
+        self.bookmark = bookmark;
 
         // Extract the "docs" array from the response
-        let rows = json["docs"]
-            .as_array()
-            .ok_or("No 'docs' field in response")?;
+        match json["docs"].take() {
+            Value::Array(docs) => {
+                self.page_rows = docs.len();
+                Ok(docs)
+            }
+            _ => Err("No 'docs' field in response".to_string()),
+        }
+    }
 
-        // Apply the callback to each document
-        let count = rows
-            .iter()
-            .map(|doc| (self.callback)(doc.clone())) // Call the callback for each document
-            .count(); // Count the number of documents processed
+    /// Fetches the next page of documents whose `_id` starts with `prefix` through `_all_docs`.
+    async fn fetch_prefix_page(&mut self, prefix: &str) -> Result<Vec<Value>, String> {
+        // A descending scan walks the key range from its end
+        let (startkey, endkey) = match (prefix_key_range(prefix), self.scan_order) {
+            ((startkey, endkey), ScanOrder::Asc) => (startkey, endkey),
+            ((startkey, endkey), ScanOrder::Desc) => (endkey, startkey),
+        };
+
+        let descending = self.scan_order == ScanOrder::Desc;
+        self.fetch_all_docs_page(Some(startkey), Some(endkey), true, descending)
+            .await
+    }
+
+    /// Fetches the next page of documents whose `_id` falls in `range` through `_all_docs`,
+    /// in ascending order. Design documents are left out.
+    async fn fetch_range_page(&mut self, range: &IdRange) -> Result<Vec<Value>, String> {
+        let encode = |key: &String| Value::String(key.clone()).to_string();
+        let startkey = range.start.as_ref().map(encode);
+        let endkey = range.end.as_ref().map(encode);
+
+        let rows = self
+            .fetch_all_docs_page(startkey, endkey, false, false)
+            .await?;
+        Ok(rows
+            .into_iter()
+            .filter(|doc| {
+                !doc[&self.id_field]
+                    .as_str()
+                    .is_some_and(|id| id.starts_with("_design/"))
+            })
+            .collect())
+    }
+
+    /// Fetches the next page of `_all_docs` between the JSON-encoded keys, each optional.
+    /// After the first page, the scan resumes at the last `_id` seen, skipping that document itself.
+    async fn fetch_all_docs_page(
+        &mut self,
+        startkey: Option<String>,
+        endkey: Option<String>,
+        inclusive_end: bool,
+        descending: bool,
+    ) -> Result<Vec<Value>, String> {
+        let url = format!("{}/{}/_all_docs", self.db_host, self.table_name);
+
+        // Resume after the last document seen, if any
+        let (startkey, skip) = match &self.last_id {
+            Some(last_id) => (Some(Value::String(last_id.clone()).to_string()), 1),
+            None => (startkey, 0),
+        };
+
+        let mut query = vec![
+            ("include_docs", self.fields.is_none().to_string()), // Only IDs are needed with a projection
+            ("limit", self.limit.to_string()),
+            ("skip", skip.to_string()),
+            ("descending", descending.to_string()),
+        ];
+        if let Some(startkey) = startkey {
+            query.push(("startkey", startkey));
+        }
+        if let Some(endkey) = endkey {
+            query.push(("endkey", endkey));
+        }
+        if !inclusive_end {
+            query.push(("inclusive_end", "false".to_string()));
+        }
+
+        let request = self.client.get(&url).query(&query);
+        let request = self.prepare(request).await?;
+        let response = send_with_retry(request, self.max_retries)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if response.status() != StatusCode::OK {
+            return Err(format!(
+                "Failed to fetch documents: Status code {}",
+                response.status()
+            ));
+        }
+
+        let body = response.text().await.map_err(|e| e.to_string())?;
+        let mut json: Value = from_str(&body).map_err(|e| e.to_string())?;
+        self.page_rows = json["rows"].as_array().map_or(0, Vec::len);
+
+        // Without documents, each row is reduced to its ID
+        if self.fields.is_some() {
+            return match json["rows"].take() {
+                Value::Array(rows) => Ok(rows
+                    .into_iter()
+                    .map(|mut row| serde_json::json!({ &self.id_field: row["id"].take() }))
+                    .collect()),
+                _ => Err("No 'rows' field in response".to_string()),
+            };
+        }
+
+        // Extract the documents from the "rows" array of the response
+        match json["rows"].take() {
+            Value::Array(rows) => Ok(rows
+                .into_iter()
+                .filter_map(|mut row| match row["doc"].take() {
+                    Value::Null => None, // Deleted documents have no body
+                    doc => Some(doc),
+                })
+                .collect()),
+            _ => Err("No 'rows' field in response".to_string()),
+        }
+    }
+
+    /// Builds the `_find` request body for the next page according to the pagination strategy.
+    fn selector_content(&self) -> SelectorContent {
+        match self.pagination {
+            Pagination::Bookmark => SelectorContent {
+                selector: self.selector.clone(),
+                limit: self.limit as i32, // Limit the number of documents per request
+                bookmark: self.bookmark.clone(), // Use the bookmark for pagination
+                // The server's natural order is ascending, so only a descending scan needs a sort
+                sort: (self.scan_order == ScanOrder::Desc)
+                    .then(|| serde_json::json!([{ &self.id_field: "desc" }])),
+                fields: self.fields.clone(),
+                r: self.read_quorum,
+            },
+            Pagination::Id => {
+                // Descending scans continue below the last `_id` seen instead of above it
+                let bound = match self.scan_order {
+                    ScanOrder::Asc => "$gt",
+                    ScanOrder::Desc => "$lt",
+                };
+
+                SelectorContent {
+                    // Restrict the selector to documents after the last `_id` seen
+                    selector: match &self.last_id {
+                        Some(last_id) => serde_json::json!({
+                            "$and": [self.selector, { &self.id_field: { bound: last_id } }]
+                        }),
+                        None => self.selector.clone(),
+                    },
+                    limit: self.limit as i32,
+                    bookmark: None,
+                    sort: Some(
+                        serde_json::json!([{ &self.id_field: self.scan_order.direction() }]),
+                    ), // Pages must be ordered by `_id`
+                    fields: self.fields.clone(),
+                    r: self.read_quorum,
+                }
+            }
+        }
+    }
+
+    /// Prints or creates the index recommended for the current selector.
+    /// Failures are reported but not fatal, since the scan still works without an index.
+    async fn handle_missing_index(&self) {
+        let Some(index) = recommended_index(&self.selector) else {
+            info!("CouchDB reported no matching index, but no index can be derived from the selector.");
+            return;
+        };
+
+        let url = format!("{}/{}/_index", self.db_host, self.table_name);
+
+        if !self.auto_create_index {
+            info!(
+                "CouchDB reported no matching index. To create one, POST the following to {}:\n{}",
+                url, index
+            );
+            return;
+        }
+
+        info!(
+            "CouchDB reported no matching index. Creating index: {}",
+            index
+        );
+        let request = match self.prepare(self.client.post(&url)).await {
+            Ok(request) => request,
+            Err(err) => {
+                warn!("Failed to create index: {}", err);
+                return;
+            }
+        };
+        match request.json(&index).send().await {
+            Ok(response) if response.status().is_success() => {
+                info!("Index created on table '{}'.", self.table_name);
+            }
+            Ok(response) => {
+                warn!("Failed to create index: Status code {}", response.status());
+            }
+            Err(err) => warn!("Failed to create index: {}", err),
+        }
+    }
+}
 
-        Ok(count) // Return the number of documents processed
+impl DocumentSource for FetchDocument<'_> {
+    /// Fetches pages until one holds documents or the scan ends. A page whose rows were all
+    /// dropped, e.g. design documents or documents beyond `max_documents`, does not end the scan.
+    /// No page is fetched once the stop signal is raised or the scan is cancelled.
+    /// The table metadata is read by `execute` beforehand.
+    async fn next_batch(&mut self) -> Result<Vec<Value>, String> {
+        while !self.finished && !self.is_stopped() {
+            self.pages += 1;
+            let rows = self.fetch_page().await?;
+            self.finished = self.is_last_page(self.page_rows, self.pages);
+            if !rows.is_empty() {
+                return Ok(rows);
+            }
+        }
+        Ok(Vec::new())
     }
 }
 
-/// Represents the structure of the query selector used for fetching documents.
-#[derive(Debug, serde::Serialize)]
-struct SelectorContent {
-    selector: serde_json::Value, // JSON object representing the query conditions
-    limit: i32,                  // Maximum number of records to fetch
-    #[serde(skip_serializing_if = "Option::is_none")]
-    bookmark: Option<String>, // Optional bookmark for pagination
+/// Passes the documents of any `DocumentSource` to a callback, batch after batch, until the source
+/// returns an empty batch. Each batch is reported to the batch callback and as a progress line.
+pub struct Feed<'a> {
+    callback: Box<dyn Fn(Value) + Send + Sync + 'a>, // Callback function to process each document
+    batch_callback: Option<Box<dyn Fn(BatchInfo) + Send + Sync + 'a>>, // Called once each batch has been applied
+    pipeline: Option<Arc<PipelineWriter>>, // Stages applied to each document before the callback, changed documents being written back
+    prefetch: usize,       // Number of batches fetched ahead of the one being applied
+    label: Option<String>, // Prefix of the progress lines
+    doc_count: usize,      // Total number of documents, reported in the progress lines
+}
+
+impl<'a> Feed<'a> {
+    /// Constructs a feed passing each document to `callback`, one batch at a time.
+    pub fn new(callback: Box<dyn Fn(Value) + Send + Sync + 'a>) -> Self {
+        Feed {
+            callback,
+            batch_callback: None, // No per-batch reporting
+            pipeline: None,       // Documents are only passed to the callback
+            prefetch: 0,          // Fetch and apply strictly in turn
+            label: None,          // Unlabeled progress lines
+            doc_count: 0,         // Total unknown
+        }
+    }
+
+    /// Sets a callback invoked once each batch has been applied, e.g. to report its timing.
+    pub fn with_batch_callback(
+        mut self,
+        batch_callback: Box<dyn Fn(BatchInfo) + Send + Sync + 'a>,
+    ) -> Self {
+        self.batch_callback = Some(batch_callback);
+        self
+    }
+
+    /// Fetches up to `prefetch` batches ahead while the current batch is being applied.
+    /// A value of 0 disables prefetching.
+    pub fn with_prefetch(mut self, prefetch: usize) -> Self {
+        self.prefetch = prefetch;
+        self
+    }
+
+    /// Sets the total number of documents, reported in the progress lines.
+    pub fn with_doc_count(mut self, doc_count: usize) -> Self {
+        self.doc_count = doc_count;
+        self
+    }
+
+    /// Applies every batch of `source`. Returns the number of batches and documents, and what
+    /// the pipeline did with them; the other fields of the summary are left to the caller.
+    pub async fn run<S: DocumentSource>(&self, source: &mut S) -> Result<FetchSummary, String> {
+        let started = Instant::now(); // Start of the run, from which the progress rate is measured
+        let mut summary = if self.prefetch > 0 {
+            self.run_prefetching(source, started).await?
+        } else {
+            self.run_sequential(source, started).await?
+        };
+        // The first batch is fetched even when it is empty, so it counts as an iteration
+        if summary.iterations == 0 {
+            summary.iterations = 1;
+            info!(
+                "{}",
+                progress_line(self.label.as_deref(), 0, self.doc_count, 1, Duration::ZERO)
+            );
+        }
+        Ok(summary)
+    }
+
+    /// Fetches a batch, applies it, and only then fetches the next one.
+    async fn run_sequential<S: DocumentSource>(
+        &self,
+        source: &mut S,
+        run_started: Instant,
+    ) -> Result<FetchSummary, String> {
+        let mut summary = FetchSummary::default();
+        loop {
+            let started = Instant::now();
+            let rows = source.next_batch().await?;
+            if rows.is_empty() {
+                return Ok(summary);
+            }
+            self.apply(&mut summary, rows, started.elapsed(), run_started)
+                .await;
+        }
+    }
+
+    /// Fetches batches into a bounded buffer while earlier ones are applied.
+    /// Batches are still fetched one after another, so their order is unchanged.
+    async fn run_prefetching<S: DocumentSource>(
+        &self,
+        source: &mut S,
+        run_started: Instant,
+    ) -> Result<FetchSummary, String> {
+        let (sender, mut receiver) =
+            tokio::sync::mpsc::channel::<(Vec<Value>, Duration)>(self.prefetch);
+
+        let producer = async move {
+            loop {
+                let started = Instant::now();
+                let rows = source.next_batch().await?;
+                if rows.is_empty() || sender.send((rows, started.elapsed())).await.is_err() {
+                    // Dropping the sender ends the consumer once the buffer is drained
+                    return Ok::<(), String>(());
+                }
+            }
+        };
+
+        let consumer = async {
+            let mut summary = FetchSummary::default();
+            while let Some((rows, fetch_duration)) = receiver.recv().await {
+                self.apply(&mut summary, rows, fetch_duration, run_started)
+                    .await;
+            }
+            summary
+        };
+
+        let (fetched, summary) = tokio::join!(producer, consumer);
+        fetched.map(|()| summary)
+    }
+
+    /// Runs a batch through the pipeline, if any, and the callback, then reports it along with
+    /// the progress of the run started at `run_started`.
+    async fn apply(
+        &self,
+        summary: &mut FetchSummary,
+        mut rows: Vec<Value>,
+        fetch_duration: Duration,
+        run_started: Instant,
+    ) {
+        if let Some(pipeline) = &self.pipeline {
+            let outcomes = pipeline.run(&mut rows).await;
+            summary.matched += outcomes.matched;
+            summary.written += outcomes.written;
+            summary.failed += outcomes.failed;
+        }
+        let documents = rows.into_iter().map(&self.callback).count();
+        summary.iterations += 1;
+        summary.total_fetched += documents;
+
+        if let Some(batch_callback) = &self.batch_callback {
+            batch_callback(BatchInfo {
+                iteration: summary.iterations,
+                documents,
+                fetch_duration,
+            });
+        }
+        info!(
+            "{}",
+            progress_line(
+                self.label.as_deref(),
+                summary.total_fetched,
+                self.doc_count,
+                summary.iterations,
+                run_started.elapsed(),
+            )
+        );
+    }
+}
+
+/// Describes the progress of a run after `count` batches, prefixed with `[label]` if given:
+/// the documents fetched so far out of `doc_count`, the rate since the start of the run, and
+/// the estimated time remaining at that rate, if the total is known.
+fn progress_line(
+    label: Option<&str>,
+    total_record: usize,
+    doc_count: usize,
+    count: usize,
+    elapsed: Duration,
+) -> String {
+    let prefix = label
+        .map(|label| format!("[{}] ", label))
+        .unwrap_or_default();
+    if count == 0 {
+        return format!("{}No documents matched; nothing to do.", prefix);
+    }
+
+    let line = format!(
+        "{}Fetched {}/{} transactions. Iteration: {}",
+        prefix, total_record, doc_count, count
+    );
+    let rate = total_record as f64 / elapsed.as_secs_f64();
+    if !rate.is_finite() || rate <= 0.0 {
+        return line;
+    }
+    match doc_count.checked_sub(total_record) {
+        Some(remaining) if remaining > 0 => format!(
+            "{} ({:.1} docs/s, ETA {})",
+            line,
+            rate,
+            format_eta(remaining as f64 / rate)
+        ),
+        _ => format!("{} ({:.1} docs/s)", line, rate),
+    }
+}
+
+/// Writes a number of seconds as hours, minutes and seconds, e.g. `1h02m`, `3m05s` or `42s`.
+fn format_eta(secs: f64) -> String {
+    let secs = secs.ceil() as u64;
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m{:02}s", m, s),
+        (h, m, _) => format!("{}h{:02}m", h, m),
+    }
+}
+
+/// Splits the `_id` space of a table into up to `shards` ranges holding about as many documents each.
+/// The boundaries are the `_id`s found at evenly spaced offsets of `_all_docs`; a table with fewer
+/// documents than shards yields fewer ranges. The ranges are returned in ascending order.
+pub async fn shard_id_ranges(
+    client: &Client,
+    db_host: &str,
+    table_name: &str,
+    shards: usize,
+    auth: Option<&IamAuth>,
+) -> Result<Vec<IdRange>, String> {
+    let url = format!("{}/{}/_all_docs", db_host, table_name);
+
+    // Reads the rows of `_all_docs` starting at `skip`
+    let fetch_rows = |skip: usize, limit: usize| {
+        let url = url.clone();
+        async move {
+            let request = client
+                .get(&url)
+                .query(&[("limit", limit.to_string()), ("skip", skip.to_string())]);
+            let response = authorize(auth, request)
+                .await?
+                .send()
+                .await
+                .map_err(|e| e.to_string())?;
+            if response.status() != StatusCode::OK {
+                return Err(format!(
+                    "Failed to list documents: Status code {}",
+                    response.status()
+                ));
+            }
+            response.json::<Value>().await.map_err(|e| e.to_string())
+        }
+    };
+
+    let total_rows = fetch_rows(0, 0).await?["total_rows"].as_u64().unwrap_or(0) as usize;
+
+    let mut boundaries: Vec<String> = Vec::new();
+    for shard in 1..shards {
+        let skip = shard * total_rows / shards;
+        if skip == 0 {
+            continue;
+        }
+        let json = fetch_rows(skip, 1).await?;
+        if let Some(id) = json["rows"][0]["id"].as_str() {
+            if boundaries.last().is_none_or(|last| last.as_str() < id) {
+                boundaries.push(id.to_string());
+            }
+        }
+    }
+
+    // Consecutive boundaries delimit the ranges, the first and last of which are open-ended
+    let mut ranges = Vec::new();
+    let mut start = None;
+    for boundary in boundaries {
+        ranges.push(IdRange {
+            start: start.take(),
+            end: Some(boundary.clone()),
+        });
+        start = Some(boundary);
+    }
+    ranges.push(IdRange { start, end: None });
+    Ok(ranges)
+}
+
+/// Encodes a document ID as a URL path segment.
+/// The `:` separating the partition of a partitioned ID (`partition:doc`) is kept as it is, as
+/// CouchDB expects in partitioned document URLs; everything else is percent-encoded, including
+/// further colons. With `raw`, the ID is used exactly as given (`--raw-id`).
+pub fn encode_doc_id(id: &str, raw: bool) -> String {
+    if raw {
+        return id.to_string();
+    }
+
+    match id.split_once(':') {
+        Some((partition, rest)) => format!(
+            "{}:{}",
+            urlencoding::encode(partition),
+            urlencoding::encode(rest)
+        ),
+        None => urlencoding::encode(id).into_owned(),
+    }
+}
+
+/// Fetches a single document by `_id`, returning `None` if it does not exist.
+/// With a `read_quorum`, the read waits for that many replicas to answer (the `r` parameter).
+/// See `encode_doc_id` for `raw_id`.
+pub async fn fetch_document_by_id(
+    client: &Client,
+    db_host: &str,
+    table_name: &str,
+    id: &str,
+    raw_id: bool,
+    read_quorum: Option<usize>,
+    auth: Option<&IamAuth>,
+) -> Result<Option<Value>, String> {
+    let url = format!("{}/{}/{}", db_host, table_name, encode_doc_id(id, raw_id));
+    let request = match read_quorum {
+        Some(r) => client.get(&url).query(&[("r", r)]),
+        None => client.get(&url),
+    };
+
+    let response = authorize(auth, request)
+        .await?
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    match response.status() {
+        StatusCode::OK => {
+            let body = response.text().await.map_err(|e| e.to_string())?;
+            from_str(&body).map(Some).map_err(|e| e.to_string())
+        }
+        StatusCode::NOT_FOUND => Ok(None),
+        status => Err(format!(
+            "Failed to fetch document {}: Status code {}",
+            id, status
+        )),
+    }
+}
+
+/// Builds the JSON-encoded `startkey`/`endkey` pair covering every `_id` that starts with `prefix`.
+pub fn prefix_key_range(prefix: &str) -> (String, String) {
+    let startkey = Value::String(prefix.to_string()).to_string();
+    let endkey = Value::String(format!("{}\u{fff0}", prefix)).to_string();
+    (startkey, endkey)
+}
+
+/// Derives a JSON index definition covering the fields referenced by a Mango selector.
+/// Operators (keys starting with `$`) are skipped, except `$and` whose clauses are inspected,
+/// and so is `_id`, which the primary index already covers.
+/// Returns `None` if the selector does not reference any other field, e.g. the default selector.
+pub fn recommended_index(selector: &Value) -> Option<Value> {
+    fn collect_fields(selector: &Value, fields: &mut Vec<String>) {
+        if let Value::Object(obj) = selector {
+            for (key, value) in obj {
+                if key == "$and" {
+                    for clause in value.as_array().into_iter().flatten() {
+                        collect_fields(clause, fields);
+                    }
+                } else if !key.starts_with('$') && key != "_id" && !fields.contains(key) {
+                    fields.push(key.clone());
+                }
+            }
+        }
+    }
+
+    let mut fields = Vec::new();
+    collect_fields(selector, &mut fields);
+
+    if fields.is_empty() {
+        return None;
+    }
+
+    Some(serde_json::json!({
+        "index": { "fields": fields },
+        "name": format!("refield-{}", fields.join("-")),
+        "type": "json"
+    }))
+}
+
+/// Summary of a completed fetch run, returned by `FetchDocument::execute` (and, in part, `Feed::run`).
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct FetchSummary {
+    pub table_name: String,   // Name of the table that was scanned
+    pub doc_count: usize,     // Total number of documents reported by the table metadata
+    pub total_fetched: usize, // Number of documents fetched and passed to the callback
+    pub iterations: usize,    // Number of batches fetched
+    pub duration_secs: f64,   // Wall-clock duration of the run in seconds
+    pub matched: usize,       // Number of documents changed by the pipeline or callback
+    pub written: usize,       // Number of changed documents written back
+    pub failed: usize,        // Number of changed documents that could not be written
+}
+
+/// What the pipeline did with the documents of a batch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct PipelineOutcomes {
+    matched: usize, // Documents changed by a stage
+    written: usize, // Changed documents written back
+    failed: usize,  // Changed documents whose write failed
+}
+
+/// Runs the documents of each batch through a `Pipeline` and writes the changed ones back.
+struct PipelineWriter {
+    pipeline: Pipeline,                   // Stages applied to each document
+    client: Client,                       // HTTP client for making requests
+    table_url: String,                    // URL of the table the documents are written to
+    id_field: String,                     // Name of the document ID field
+    raw_id: bool,                         // Whether document IDs are put in URLs as they are
+    auth: Option<IamAuth>, // IAM authentication attached to every request, if configured
+    headers: HeaderMap,    // Extra headers sent with every request
+    max_retries: usize,    // Number of times a transiently failing write is retried
+    limiter: Option<Arc<RateLimiter>>, // Token bucket each write waits for, if any
+    slots: Option<Arc<ConcurrencyLimit>>, // Bounds the writes in flight, if given
+}
+
+impl PipelineWriter {
+    /// Applies the pipeline to each document of a batch, in place, and writes back the documents
+    /// it changed, concurrently. Returns how many were changed, written, and failed.
+    async fn run(self: &Arc<Self>, rows: &mut [Value]) -> PipelineOutcomes {
+        let mut outcomes = PipelineOutcomes::default();
+        let mut writes = JoinSet::new();
+        for doc in rows.iter_mut() {
+            if !self.pipeline.apply(doc) {
+                continue;
+            }
+            outcomes.matched += 1;
+            let (writer, doc) = (self.clone(), doc.clone());
+            writes.spawn(async move { writer.write(&doc).await });
+        }
+
+        while let Some(written) = writes.join_next().await {
+            match written.map_err(|e| e.to_string()).and_then(|result| result) {
+                Ok(()) => outcomes.written += 1,
+                Err(err) => {
+                    error!("{}", err);
+                    outcomes.failed += 1;
+                }
+            }
+        }
+        outcomes
+    }
+
+    /// Writes a document back to the table under its ID, once a write slot and a token of
+    /// the rate limiter, if any, are available.
+    async fn write(&self, doc: &Value) -> Result<(), String> {
+        match &self.slots {
+            Some(slots) => slots.run(self.send(doc)).await,
+            None => self.send(doc).await,
+        }
+    }
+
+    /// Sends the request writing a document back.
+    async fn send(&self, doc: &Value) -> Result<(), String> {
+        let id = doc[&self.id_field]
+            .as_str()
+            .ok_or_else(|| format!("Document missing '{}' field", self.id_field))?;
+        let url = format!("{}/{}", self.table_url, encode_doc_id(id, self.raw_id));
+
+        if let Some(limiter) = &self.limiter {
+            limiter.acquire().await;
+        }
+        let request = self
+            .client
+            .put(&url)
+            .headers(self.headers.clone())
+            .json(doc);
+        let request = authorize(self.auth.as_ref(), request).await?;
+        let response = send_with_retry(request, self.max_retries)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        match response.status() {
+            StatusCode::OK | StatusCode::CREATED => Ok(()),
+            status => Err(format!(
+                "Failed to update document {}: Status code {}",
+                id, status
+            )),
+        }
+    }
+}
+
+/// Represents the structure of the query selector used for fetching documents.
+#[derive(Debug, serde::Serialize)]
+struct SelectorContent {
+    selector: serde_json::Value, // JSON object representing the query conditions
+    limit: i32,                  // Maximum number of records to fetch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bookmark: Option<String>, // Optional bookmark for pagination
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sort: Option<serde_json::Value>, // Optional sort order of the results
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fields: Option<Vec<String>>, // Optional projection of the returned fields
+    #[serde(skip_serializing_if = "Option::is_none")]
+    r: Option<usize>, // Optional read quorum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::netrc::Credentials;
+    use reqwest::header::AUTHORIZATION;
+    use serde_json::json;
+    use std::sync::Mutex;
+    use wiremock::matchers::{header, method, path, query_param};
+    use wiremock::{Mock, MockServer, Request, Respond, ResponseTemplate};
+
+    /// Serves `_find` requests from a fixed, `_id`-sorted set of documents.
+    /// It honors `limit`, an `_id` `$gt` bound (possibly nested in `$and`), and numeric bookmarks.
+    struct FakeFind {
+        ids: Vec<String>,
+    }
+
+    impl FakeFind {
+        fn lower_bound(selector: &Value) -> Option<String> {
+            if let Some(bound) = selector["_id"]["$gt"].as_str() {
+                return Some(bound.to_string());
+            }
+            selector["$and"]
+                .as_array()?
+                .iter()
+                .find_map(Self::lower_bound)
+        }
+    }
+
+    impl Respond for FakeFind {
+        fn respond(&self, request: &Request) -> ResponseTemplate {
+            let body: Value = serde_json::from_slice(&request.body).unwrap();
+            let limit = body["limit"].as_u64().unwrap() as usize;
+
+            let start = match Self::lower_bound(&body["selector"]) {
+                Some(bound) => self.ids.iter().take_while(|id| **id <= bound).count(),
+                None => body["bookmark"]
+                    .as_str()
+                    .map(|b| b.parse().unwrap())
+                    .unwrap_or(0),
+            };
+            let end = (start + limit).min(self.ids.len());
+
+            let docs: Vec<Value> = self.ids[start..end]
+                .iter()
+                .map(|id| json!({ "_id": id, "_rev": "1-a" }))
+                .collect();
+
+            ResponseTemplate::new(200).set_body_json(json!({
+                "docs": docs,
+                "bookmark": end.to_string()
+            }))
+        }
+    }
+
+    /// Serves scripted `_find` pages: the request with bookmark `n` (none for the first) gets
+    /// page `n`, as a number of documents and the bookmark returned with them.
+    struct ScriptedFind {
+        pages: Vec<(usize, &'static str)>,
+    }
+
+    impl Respond for ScriptedFind {
+        fn respond(&self, request: &Request) -> ResponseTemplate {
+            let body: Value = serde_json::from_slice(&request.body).unwrap();
+            let page = body["bookmark"].as_str().map_or(0, |b| b.parse().unwrap());
+            let (rows, bookmark) = self.pages[page];
+            let docs: Vec<Value> = (0..rows)
+                .map(|i| json!({ "_id": format!("p{}-{}", page, i), "_rev": "1-a" }))
+                .collect();
+            ResponseTemplate::new(200).set_body_json(json!({ "docs": docs, "bookmark": bookmark }))
+        }
+    }
+
+    /// Serves a table of `doc_count` documents whose `_find` pages are scripted (see `ScriptedFind`).
+    async fn scripted_couchdb(doc_count: usize, pages: Vec<(usize, &'static str)>) -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/db"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(json!({ "doc_count": doc_count })),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/db/_find"))
+            .respond_with(ScriptedFind { pages })
+            .mount(&server)
+            .await;
+        server
+    }
+
+    /// Serves `_all_docs` requests from a fixed, `_id`-sorted set of documents.
+    /// It honors `startkey`, `endkey`, `inclusive_end`, `skip`, and `limit`.
+    struct FakeAllDocs {
+        ids: Vec<String>,
+    }
+
+    impl Respond for FakeAllDocs {
+        fn respond(&self, request: &Request) -> ResponseTemplate {
+            let param = |name: &str| {
+                request
+                    .url
+                    .query_pairs()
+                    .find(|(key, _)| key == name)
+                    .map(|(_, value)| value.into_owned())
+            };
+            let key = |name: &str| param(name).map(|k| serde_json::from_str::<String>(&k).unwrap());
+            let startkey = key("startkey");
+            let endkey = key("endkey");
+            let inclusive_end = param("inclusive_end").is_none_or(|v| v == "true");
+            let skip: usize = param("skip").unwrap().parse().unwrap();
+            let limit: usize = param("limit").unwrap().parse().unwrap();
+
+            let rows: Vec<Value> = self
+                .ids
+                .iter()
+                .filter(|id| startkey.as_ref().is_none_or(|start| *id >= start))
+                .filter(|id| match &endkey {
+                    Some(end) if inclusive_end => *id <= end,
+                    Some(end) => *id < end,
+                    None => true,
+                })
+                .skip(skip)
+                .take(limit)
+                .map(|id| json!({ "id": id, "key": id, "doc": { "_id": id } }))
+                .collect();
+
+            ResponseTemplate::new(200)
+                .set_body_json(json!({ "total_rows": self.ids.len(), "rows": rows }))
+        }
+    }
+
+    /// Starts a mock CouchDB serving `doc_total` documents in table `db`.
+    async fn fake_couchdb(doc_total: usize) -> MockServer {
+        let server = MockServer::start().await;
+        let ids: Vec<String> = (0..doc_total).map(|i| format!("doc{:03}", i)).collect();
+
+        Mock::given(method("GET"))
+            .and(path("/db"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "doc_count": doc_total,
+                "props": {}
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/db/_find"))
+            .respond_with(FakeFind { ids })
+            .mount(&server)
+            .await;
+
+        server
+    }
+
+    #[tokio::test]
+    async fn test_id_pagination_visits_every_document_once() {
+        let server = fake_couchdb(25).await;
+        let seen = Mutex::new(Vec::new());
+
+        let summary = FetchDocument::new(Client::new(), server.uri(), "db".to_string(), 10)
+            .with_pagination(Pagination::Id)
+            .with_callback(Box::new(|doc: Value| {
+                seen.lock()
+                    .unwrap()
+                    .push(doc["_id"].as_str().unwrap().to_string());
+            }))
+            .execute()
+            .await
+            .unwrap();
+
+        let expected: Vec<String> = (0..25).map(|i| format!("doc{:03}", i)).collect();
+        assert_eq!(*seen.lock().unwrap(), expected);
+        assert_eq!(summary.total_fetched, 25);
+        assert_eq!(summary.iterations, 3);
+
+        // No request in `_id` mode should carry a bookmark
+        let requests = server.received_requests().await.unwrap();
+        assert!(requests
+            .iter()
+            .filter(|r| r.url.path() == "/db/_find")
+            .all(|r| serde_json::from_slice::<Value>(&r.body).unwrap()["bookmark"].is_null()));
+    }
+
+    #[tokio::test]
+    async fn test_bookmark_scan_ends_on_an_empty_page() {
+        // A full last page is followed by an empty one, which ends the scan
+        let server = scripted_couchdb(20, vec![(10, "1"), (10, "2"), (0, "2")]).await;
+        let summary = FetchDocument::new(Client::new(), server.uri(), "db".to_string(), 10)
+            .execute()
+            .await
+            .unwrap();
+        assert_eq!(summary.total_fetched, 20);
+        assert_eq!(summary.iterations, 2);
+
+        // A short page mid-stream does not end the scan
+        let server = scripted_couchdb(24, vec![(10, "1"), (4, "2"), (10, "3"), (0, "3")]).await;
+        let summary = FetchDocument::new(Client::new(), server.uri(), "db".to_string(), 10)
+            .execute()
+            .await
+            .unwrap();
+        assert_eq!(summary.total_fetched, 24);
+        assert_eq!(summary.iterations, 3);
+    }
+
+    #[tokio::test]
+    async fn test_bookmark_scan_ends_when_the_bookmark_stops_advancing() {
+        // The last page is full and returns the bookmark it was requested with
+        let server = scripted_couchdb(20, vec![(10, "1"), (10, "1")]).await;
+        let summary = FetchDocument::new(Client::new(), server.uri(), "db".to_string(), 10)
+            .execute()
+            .await
+            .unwrap();
+
+        assert_eq!(summary.total_fetched, 20);
+        assert_eq!(summary.iterations, 2);
+    }
+
+    #[tokio::test]
+    async fn test_missing_table_is_an_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/db"))
+            .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+                "error": "not_found",
+                "reason": "Database does not exist."
+            })))
+            .mount(&server)
+            .await;
+
+        let result = FetchDocument::new(Client::new(), server.uri(), "db".to_string(), 10)
+            .with_callback(Box::new(|_| panic!("No document should be processed")))
+            .execute()
+            .await;
+
+        assert_eq!(
+            result.unwrap_err(),
+            "Failed to fetch table metadata: Status code 404 Not Found"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_empty_table_never_calls_callback() {
+        let server = fake_couchdb(0).await;
+        let calls = Mutex::new(0);
+
+        let summary = FetchDocument::new(Client::new(), server.uri(), "db".to_string(), 10)
+            .with_callback(Box::new(|_| *calls.lock().unwrap() += 1))
+            .execute()
+            .await
+            .unwrap();
+
+        assert_eq!(*calls.lock().unwrap(), 0);
+        assert_eq!(summary.doc_count, 0);
+        assert_eq!(summary.total_fetched, 0);
+        assert_eq!(summary.iterations, 1);
+    }
+
+    #[tokio::test]
+    async fn test_batch_callback_follows_each_batch() {
+        let server = fake_couchdb(25).await;
+        let batches = Mutex::new(Vec::new());
+
+        FetchDocument::new(Client::new(), server.uri(), "db".to_string(), 10)
+            .with_prefetch(1)
+            .with_batch_callback(Box::new(|batch| {
+                batches
+                    .lock()
+                    .unwrap()
+                    .push((batch.iteration, batch.documents));
+            }))
+            .execute()
+            .await
+            .unwrap();
+
+        assert_eq!(*batches.lock().unwrap(), vec![(1, 10), (2, 10), (3, 5)]);
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_writes_back_changed_documents() {
+        let server = fake_couchdb(25).await;
+        Mock::given(method("PUT"))
+            .respond_with(ResponseTemplate::new(201).set_body_json(json!({ "ok": true })))
+            .mount(&server)
+            .await;
+        let seen = Mutex::new(Vec::new());
+
+        // Mark every fifth document
+        let pipeline = Pipeline::new().with_stage(|doc: &mut Value| {
+            let n: usize = doc["_id"].as_str().unwrap()[3..].parse().unwrap();
+            let marked = n.is_multiple_of(5);
+            if marked {
+                doc["migrated"] = json!(true);
+            }
+            marked
+        });
+        let summary = FetchDocument::new(Client::new(), server.uri(), "db".to_string(), 10)
+            .with_prefetch(1)
+            .with_pipeline(pipeline)
+            .with_callback(Box::new(|doc: Value| {
+                seen.lock().unwrap().push(doc["migrated"] == json!(true));
+            }))
+            .execute()
+            .await
+            .unwrap();
+
+        let writes: Vec<Value> = server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .filter(|request| request.method.as_str() == "PUT")
+            .map(|request| {
+                assert!(request.url.path().starts_with("/db/doc0"));
+                serde_json::from_slice(&request.body).unwrap()
+            })
+            .collect();
+        assert_eq!(summary.written, 5);
+        assert_eq!(writes.len(), 5);
+        assert!(writes.iter().all(|doc| doc["migrated"] == json!(true)));
+        assert_eq!(seen.lock().unwrap().iter().filter(|m| **m).count(), 5);
+        assert_eq!(summary.total_fetched, 25);
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_writes_share_the_write_slots() {
+        let server = fake_couchdb(10).await;
+        Mock::given(method("PUT"))
+            .respond_with(
+                ResponseTemplate::new(201)
+                    .set_body_json(json!({ "ok": true }))
+                    .set_delay(Duration::from_millis(100)),
+            )
+            .mount(&server)
+            .await;
+        let every_document = || Pipeline::new().with_stage(|_: &mut Value| true);
+
+        // The ten writes of the batch run at once by default
+        let started = Instant::now();
+        FetchDocument::new(Client::new(), server.uri(), "db".to_string(), 10)
+            .with_pipeline(every_document())
+            .execute()
+            .await
+            .unwrap();
+        assert!(started.elapsed() < Duration::from_millis(500));
+
+        // Two slots let them through two at a time
+        let started = Instant::now();
+        let summary = FetchDocument::new(Client::new(), server.uri(), "db".to_string(), 10)
+            .with_pipeline(every_document())
+            .with_write_slots(Arc::new(ConcurrencyLimit::new(2)))
+            .with_write_limiter(Arc::new(RateLimiter::new(1000.0)))
+            .execute()
+            .await
+            .unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(500));
+        assert_eq!(summary.written, 10);
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_writes_honor_raw_id() {
+        let server = fake_couchdb(1).await;
+        Mock::given(method("PUT"))
+            .respond_with(ResponseTemplate::new(201).set_body_json(json!({ "ok": true })))
+            .mount(&server)
+            .await;
+        let nested_id = || {
+            Pipeline::new().with_stage(|doc: &mut Value| {
+                doc["_id"] = json!("orders/o1");
+                true
+            })
+        };
+
+        for raw_id in [false, true] {
+            FetchDocument::new(Client::new(), server.uri(), "db".to_string(), 10)
+                .with_pipeline(nested_id())
+                .with_raw_id(raw_id)
+                .execute()
+                .await
+                .unwrap();
+        }
+
+        let paths: Vec<String> = server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .filter(|request| request.method.as_str() == "PUT")
+            .map(|request| request.url.path().to_string())
+            .collect();
+        assert_eq!(paths, vec!["/db/orders%2Fo1", "/db/orders/o1"]);
+    }
+
+    #[tokio::test]
+    async fn test_custom_selector_is_sent_to_find() {
+        let server = fake_couchdb(5).await;
+        let selector = json!({ "type": "order", "total": { "$gt": 100 } });
+
+        FetchDocument::new(Client::new(), server.uri(), "db".to_string(), 10)
+            .with_selector(selector.clone())
+            .with_id_field("_id".to_string())
+            .execute()
+            .await
+            .unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        let find = requests
+            .iter()
+            .find(|request| request.url.path() == "/db/_find")
+            .unwrap();
+        assert_eq!(find.body_json::<Value>().unwrap()["selector"], selector);
+    }
+
+    #[tokio::test]
+    async fn test_summary_counts_pipeline_outcomes() {
+        let server = fake_couchdb(15).await;
+        Mock::given(method("PUT"))
+            .and(path("/db/doc010"))
+            .respond_with(ResponseTemplate::new(409).set_body_json(json!({ "error": "conflict" })))
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .respond_with(ResponseTemplate::new(201).set_body_json(json!({ "ok": true })))
+            .mount(&server)
+            .await;
+
+        // Mark every fifth document: doc000 and doc005 on the first page, doc010 on the second
+        let pipeline = Pipeline::new().with_stage(|doc: &mut Value| {
+            let n: usize = doc["_id"].as_str().unwrap()[3..].parse().unwrap();
+            let marked = n.is_multiple_of(5);
+            if marked {
+                doc["migrated"] = json!(true);
+            }
+            marked
+        });
+        let summary = FetchDocument::new(Client::new(), server.uri(), "db".to_string(), 10)
+            .with_pipeline(pipeline)
+            .execute()
+            .await
+            .unwrap();
+
+        assert_eq!(summary.doc_count, 15);
+        assert_eq!(summary.total_fetched, 15);
+        assert_eq!(summary.iterations, 2);
+        assert_eq!(summary.matched, 3);
+        assert_eq!(summary.written, 2);
+        assert_eq!(summary.failed, 1);
+    }
+
+    #[tokio::test]
+    async fn test_every_page_goes_through_the_given_client() {
+        let server = fake_couchdb(25).await;
+
+        // Only requests sent by this client carry its default header
+        let mut headers = HeaderMap::new();
+        headers.insert("x-client", "pooled".parse().unwrap());
+        let client = Client::builder().default_headers(headers).build().unwrap();
+
+        let summary = FetchDocument::new(client, server.uri(), "db".to_string(), 10)
+            .execute()
+            .await
+            .unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        let pages: Vec<_> = requests
+            .iter()
+            .filter(|request| request.url.path() == "/db/_find")
+            .collect();
+        assert_eq!(summary.iterations, 3);
+        assert_eq!(
+            pages.len(),
+            4,
+            "Three batches and the empty page ending the scan"
+        );
+        assert!(requests.iter().all(|request| request
+            .headers
+            .get("x-client")
+            .is_some_and(|v| v == "pooled")));
+    }
+
+    #[tokio::test]
+    async fn test_prefetch_visits_every_document_in_order() {
+        let server = fake_couchdb(25).await;
+        let seen = Mutex::new(Vec::new());
+
+        let summary = FetchDocument::new(Client::new(), server.uri(), "db".to_string(), 10)
+            .with_prefetch(2)
+            .with_callback(Box::new(|doc: Value| {
+                seen.lock()
+                    .unwrap()
+                    .push(doc["_id"].as_str().unwrap().to_string());
+            }))
+            .execute()
+            .await
+            .unwrap();
+
+        let expected: Vec<String> = (0..25).map(|i| format!("doc{:03}", i)).collect();
+        assert_eq!(*seen.lock().unwrap(), expected);
+        assert_eq!(summary.total_fetched, 25);
+        assert_eq!(summary.iterations, 3);
+    }
+
+    #[tokio::test]
+    async fn test_prefetch_processes_each_document_once() {
+        for pagination in [Pagination::Bookmark, Pagination::Id] {
+            let server = fake_couchdb(30).await;
+            let seen = Mutex::new(Vec::new());
+
+            // A slow callback lets the fetcher run ahead by the whole buffer
+            FetchDocument::new(Client::new(), server.uri(), "db".to_string(), 7)
+                .with_pagination(pagination)
+                .with_prefetch(1)
+                .with_callback(Box::new(|doc: Value| {
+                    std::thread::sleep(Duration::from_millis(1));
+                    seen.lock()
+                        .unwrap()
+                        .push(doc["_id"].as_str().unwrap().to_string());
+                }))
+                .execute()
+                .await
+                .unwrap();
+
+            let expected: Vec<String> = (0..30).map(|i| format!("doc{:03}", i)).collect();
+            assert_eq!(*seen.lock().unwrap(), expected, "{:?}", pagination);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_max_documents_cuts_the_scan_short() {
+        let server = fake_couchdb(25).await;
+        let seen = Mutex::new(0);
+
+        let summary = FetchDocument::new(Client::new(), server.uri(), "db".to_string(), 10)
+            .with_max_documents(13)
+            .with_callback(Box::new(|_| *seen.lock().unwrap() += 1))
+            .execute()
+            .await
+            .unwrap();
+
+        assert_eq!(*seen.lock().unwrap(), 13);
+        assert_eq!(summary.total_fetched, 13);
+        assert_eq!(summary.iterations, 2);
+    }
+
+    #[tokio::test]
+    async fn test_stop_signal_ends_the_scan_after_the_batch() {
+        let server = fake_couchdb(25).await;
+        let stop = Arc::new(AtomicBool::new(false));
+        let seen = Mutex::new(0);
+
+        let summary = FetchDocument::new(Client::new(), server.uri(), "db".to_string(), 10)
+            .with_stop_signal(stop.clone())
+            .with_callback(Box::new(|_| {
+                *seen.lock().unwrap() += 1;
+                stop.store(true, Ordering::Relaxed);
+            }))
+            .execute()
+            .await
+            .unwrap();
+
+        assert_eq!(*seen.lock().unwrap(), 10);
+        assert_eq!(summary.iterations, 1);
+    }
+
+    #[tokio::test]
+    async fn test_cancellation_returns_a_partial_summary() {
+        let server = fake_couchdb(25).await;
+        let token = CancellationToken::new();
+        let seen = Mutex::new(0);
+
+        // Cancelled from elsewhere once the first batch has been handed over
+        let summary = FetchDocument::new(Client::new(), server.uri(), "db".to_string(), 10)
+            .with_cancellation(token.clone())
+            .with_callback(Box::new(|_| {
+                *seen.lock().unwrap() += 1;
+                if *seen.lock().unwrap() == 10 {
+                    token.cancel();
+                }
+            }))
+            .execute()
+            .await
+            .unwrap();
+
+        assert_eq!(*seen.lock().unwrap(), 10);
+        assert_eq!(summary.total_fetched, 10);
+        assert_eq!(summary.iterations, 1);
+        assert_eq!(summary.doc_count, 25);
+        assert!(token.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn test_count_unfiltered_table_uses_metadata() {
+        let server = fake_couchdb(25).await;
+
+        let count = FetchDocument::new(Client::new(), server.uri(), "db".to_string(), 10)
+            .count()
+            .await;
+
+        assert_eq!(count, Ok(25));
+        let requests = server.received_requests().await.unwrap();
+        assert!(requests.iter().all(|r| r.url.path() != "/db/_find"));
+    }
+
+    #[tokio::test]
+    async fn test_extra_headers_are_sent_with_every_request() {
+        let server = fake_couchdb(15).await;
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", "secret".parse().unwrap());
+
+        FetchDocument::new(Client::new(), server.uri(), "db".to_string(), 10)
+            .with_headers(headers)
+            .execute()
+            .await
+            .unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        assert_eq!(requests.len(), 4, "Metadata, two pages, and the empty page");
+        assert!(requests
+            .iter()
+            .all(|r| r.headers.get("x-api-key").is_some_and(|v| v == "secret")));
+    }
+
+    #[tokio::test]
+    async fn test_basic_auth_header_unlocks_a_secured_server() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/db"))
+            .and(header("authorization", "Basic YWRtaW46c2VjcmV0"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "doc_count": 3 })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+                "error": "unauthorized",
+                "reason": "You are not authorized to access this db."
+            })))
+            .mount(&server)
+            .await;
+
+        let anonymous = FetchDocument::new(Client::new(), server.uri(), "db".to_string(), 10)
+            .count()
+            .await;
+        assert!(anonymous.is_err());
+
+        let credentials = Credentials {
+            login: "admin".to_string(),
+            password: "secret".to_string(),
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, credentials.authorization().unwrap());
+        let authenticated = FetchDocument::new(Client::new(), server.uri(), "db".to_string(), 10)
+            .with_headers(headers)
+            .count()
+            .await;
+        assert_eq!(authenticated, Ok(3));
+    }
+
+    #[tokio::test]
+    async fn test_count_with_selector_pages_through_ids_only() {
+        let server = fake_couchdb(25).await;
+        let mut fd = FetchDocument::new(Client::new(), server.uri(), "db".to_string(), 10);
+        fd.selector = json!({ "type": "invoice" });
+
+        assert_eq!(fd.count().await, Ok(25));
+
+        let requests = server.received_requests().await.unwrap();
+        let finds: Vec<Value> = requests
+            .iter()
+            .filter(|r| r.url.path() == "/db/_find")
+            .map(|r| serde_json::from_slice(&r.body).unwrap())
+            .collect();
+        assert_eq!(finds.len(), 4);
+        assert!(finds.iter().all(|body| body["fields"] == json!(["_id"])));
+    }
+
+    #[tokio::test]
+    async fn test_projection_omits_include_docs() {
+        let server = fake_couchdb(5).await;
+
+        FetchDocument::new(Client::new(), server.uri(), "db".to_string(), 10)
+            .with_fields(vec!["_id".to_string(), "_rev".to_string()])
+            .execute()
+            .await
+            .unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        let find = requests
+            .iter()
+            .find(|request| request.url.path() == "/db/_find")
+            .unwrap();
+        let body = find.body_json::<Value>().unwrap();
+        assert_eq!(body["fields"], json!(["_id", "_rev"]));
+        assert!(body.get("include_docs").is_none());
+        assert!(!find.url.query_pairs().any(|(key, _)| key == "include_docs"));
+    }
+
+    #[test]
+    fn test_id_pagination_uses_custom_id_field() {
+        let mut fd = FetchDocument::new(
+            Client::new(),
+            "http://host".to_string(),
+            "db".to_string(),
+            10,
+        )
+        .with_id_field("key".to_string())
+        .with_pagination(Pagination::Id);
+        fd.last_id = Some("k5".to_string());
+
+        let content = serde_json::to_value(fd.selector_content()).unwrap();
+
+        assert_eq!(
+            content["selector"],
+            json!({ "$and": [{ "key": { "$gt": null } }, { "key": { "$gt": "k5" } }] })
+        );
+        assert_eq!(content["sort"], json!([{ "key": "asc" }]));
+    }
+
+    #[test]
+    fn test_resume_from_id_bounds_the_first_page() {
+        let fd = FetchDocument::new(
+            Client::new(),
+            "http://host".to_string(),
+            "db".to_string(),
+            10,
+        )
+        .with_pagination(Pagination::Id)
+        .with_resume_from_id("doc7".to_string());
+
+        let content = serde_json::to_value(fd.selector_content()).unwrap();
+
+        assert_eq!(
+            content["selector"]["$and"][1],
+            json!({ "_id": { "$gt": "doc7" } })
+        );
+    }
+
+    #[test]
+    fn test_descending_id_pagination_bounds_below_last_id() {
+        let mut fd = FetchDocument::new(
+            Client::new(),
+            "http://host".to_string(),
+            "db".to_string(),
+            10,
+        )
+        .with_pagination(Pagination::Id)
+        .with_scan_order(ScanOrder::Desc);
+        fd.last_id = Some("doc5".to_string());
+
+        let content = serde_json::to_value(fd.selector_content()).unwrap();
+
+        assert_eq!(
+            content["selector"]["$and"][1],
+            json!({ "_id": { "$lt": "doc5" } })
+        );
+        assert_eq!(content["sort"], json!([{ "_id": "desc" }]));
+    }
+
+    #[test]
+    fn test_recommended_index_from_selector_fields() {
+        let selector = json!({
+            "$and": [
+                { "type": "invoice" },
+                { "amount": { "$gt": 100 } },
+                { "type": "invoice" }
+            ]
+        });
+
+        let index = recommended_index(&selector).unwrap();
+
+        assert_eq!(
+            index,
+            json!({
+                "index": { "fields": ["type", "amount"] },
+                "name": "refield-type-amount",
+                "type": "json"
+            })
+        );
+    }
+
+    #[test]
+    fn test_progress_line_estimates_time_remaining() {
+        let elapsed = Duration::from_secs(4);
+        assert_eq!(
+            progress_line(None, 500, 2000, 5, elapsed),
+            "Fetched 500/2000 transactions. Iteration: 5 (125.0 docs/s, ETA 12s)"
+        );
+        assert_eq!(
+            progress_line(Some("shard 1/2"), 100, 100_100, 1, elapsed),
+            "[shard 1/2] Fetched 100/100100 transactions. Iteration: 1 (25.0 docs/s, ETA 1h06m)"
+        );
+        assert_eq!(
+            progress_line(None, 2000, 2000, 20, elapsed),
+            "Fetched 2000/2000 transactions. Iteration: 20 (500.0 docs/s)",
+            "No estimate once everything is fetched"
+        );
+        assert_eq!(
+            progress_line(None, 10, 20, 1, Duration::ZERO),
+            "Fetched 10/20 transactions. Iteration: 1"
+        );
+        assert_eq!(format_eta(185.2), "3m06s");
+    }
+
+    #[test]
+    fn test_recommended_index_without_fields() {
+        assert_eq!(recommended_index(&json!({ "$or": [] })), None);
+        assert_eq!(
+            recommended_index(&json!({ "_id": { "$gt": null } })),
+            None,
+            "The default selector only needs the primary index"
+        );
+        assert_eq!(
+            recommended_index(&json!({ "$and": [{ "_id": { "$gt": "a" } }, { "type": "x" }] })),
+            Some(json!({
+                "index": { "fields": ["type"] },
+                "name": "refield-type",
+                "type": "json"
+            }))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_document_by_id_distinguishes_missing_documents() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/db/a%20b"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "_id": "a b" })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/db/missing"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+
+        let client = Client::new();
+        let found =
+            fetch_document_by_id(&client, &server.uri(), "db", "a b", false, None, None).await;
+        let missing =
+            fetch_document_by_id(&client, &server.uri(), "db", "missing", false, None, None).await;
+
+        assert_eq!(found, Ok(Some(json!({ "_id": "a b" }))));
+        assert_eq!(missing, Ok(None));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_document_by_id_sends_read_quorum() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/db/a"))
+            .and(query_param("r", "2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "_id": "a" })))
+            .mount(&server)
+            .await;
+
+        let found = fetch_document_by_id(
+            &Client::new(),
+            &server.uri(),
+            "db",
+            "a",
+            false,
+            Some(2),
+            None,
+        )
+        .await;
+
+        assert_eq!(found, Ok(Some(json!({ "_id": "a" }))));
+    }
+
+    #[tokio::test]
+    async fn test_requests_go_under_the_url_prefix() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/couch/db/a"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "_id": "a" })))
+            .mount(&server)
+            .await;
+
+        let db_host = crate::args::join_url_prefix(&server.uri(), "/couch/");
+        let found =
+            fetch_document_by_id(&Client::new(), &db_host, "db", "a", false, None, None).await;
+
+        assert_eq!(found, Ok(Some(json!({ "_id": "a" }))));
+    }
+
+    #[test]
+    fn test_encode_doc_id() {
+        assert_eq!(
+            encode_doc_id("invoices:2024-001", false),
+            "invoices:2024-001"
+        );
+        assert_eq!(encode_doc_id("a:b:c", false), "a:b%3Ac");
+        assert_eq!(
+            encode_doc_id("users/ada lovelace", false),
+            "users%2Fada%20lovelace"
+        );
+        assert_eq!(encode_doc_id("part one:x/y", false), "part%20one:x%2Fy");
+        assert_eq!(encode_doc_id("_design/app", false), "_design%2Fapp");
+        assert_eq!(encode_doc_id("a:b c/d", true), "a:b c/d");
+    }
+
+    #[tokio::test]
+    async fn test_partition_scan_queries_the_partition_find() {
+        let server = MockServer::start().await;
+        let ids: Vec<String> = (0..12).map(|i| format!("sensor-a:{:03}", i)).collect();
+        Mock::given(method("GET"))
+            .and(path("/db"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "doc_count": 40,
+                "props": { "partitioned": true }
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/db/_partition/sensor-a"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({ "partition": "sensor-a", "doc_count": 12 })),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/db/_partition/sensor-a/_find"))
+            .respond_with(FakeFind { ids: ids.clone() })
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/db/_find"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(0)
+            .mount(&server)
+            .await;
+        let seen = Mutex::new(Vec::new());
+
+        let summary = FetchDocument::new(Client::new(), server.uri(), "db".to_string(), 10)
+            .with_partition("sensor-a".to_string())
+            .with_callback(Box::new(|doc: Value| {
+                seen.lock()
+                    .unwrap()
+                    .push(doc["_id"].as_str().unwrap().to_string());
+            }))
+            .execute()
+            .await
+            .unwrap();
+
+        assert_eq!(*seen.lock().unwrap(), ids);
+        assert_eq!(summary.doc_count, 12);
+        assert_eq!(summary.iterations, 2);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_document_by_id_keeps_partition_separator() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/db/orders:a%2Fb"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "_id": "orders:a/b" })))
+            .mount(&server)
+            .await;
+
+        let found = fetch_document_by_id(
+            &Client::new(),
+            &server.uri(),
+            "db",
+            "orders:a/b",
+            false,
+            None,
+            None,
+        )
+        .await;
+
+        assert_eq!(found, Ok(Some(json!({ "_id": "orders:a/b" }))));
+    }
+
+    #[test]
+    fn test_prefix_key_range() {
+        let (startkey, endkey) = prefix_key_range("invoice:");
+
+        assert_eq!(startkey, "\"invoice:\"");
+        assert_eq!(endkey, "\"invoice:\u{fff0}\"");
+    }
+
+    #[tokio::test]
+    async fn test_id_prefix_scan_pages_within_range() {
+        let server = MockServer::start().await;
+        let mut ids: Vec<String> = (0..7).map(|i| format!("invoice:{}", i)).collect();
+        ids.extend(["order:1".to_string(), "invoicez".to_string()]);
+        ids.sort();
+
+        Mock::given(method("GET"))
+            .and(path("/db"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "doc_count": 9 })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/db/_all_docs"))
+            .respond_with(FakeAllDocs { ids })
+            .mount(&server)
+            .await;
+
+        let seen = Mutex::new(Vec::new());
+        let summary = FetchDocument::new(Client::new(), server.uri(), "db".to_string(), 3)
+            .with_id_prefix("invoice:".to_string())
+            .with_callback(Box::new(|doc: Value| {
+                seen.lock()
+                    .unwrap()
+                    .push(doc["_id"].as_str().unwrap().to_string());
+            }))
+            .execute()
+            .await
+            .unwrap();
+
+        let expected: Vec<String> = (0..7).map(|i| format!("invoice:{}", i)).collect();
+        assert_eq!(*seen.lock().unwrap(), expected);
+        assert_eq!(summary.iterations, 3);
+    }
+
+    #[tokio::test]
+    async fn test_shards_cover_every_document_once() {
+        let server = MockServer::start().await;
+        let mut ids: Vec<String> = (0..23).map(|i| format!("doc{:03}", i)).collect();
+        ids.push("_design/views".to_string());
+        ids.sort();
+
+        Mock::given(method("GET"))
+            .and(path("/db"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "doc_count": 23 })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/db/_all_docs"))
+            .respond_with(FakeAllDocs { ids })
+            .mount(&server)
+            .await;
+
+        let client = Client::new();
+        let ranges = shard_id_ranges(&client, &server.uri(), "db", 4, None)
+            .await
+            .unwrap();
+        assert_eq!(ranges.len(), 4);
+        assert_eq!(ranges[0].start, None);
+        assert_eq!(ranges[3].end, None);
+
+        let seen = Mutex::new(Vec::new());
+        for range in ranges {
+            FetchDocument::new(client.clone(), server.uri(), "db".to_string(), 3)
+                .with_id_range(range)
+                .with_callback(Box::new(|doc: Value| {
+                    seen.lock()
+                        .unwrap()
+                        .push(doc["_id"].as_str().unwrap().to_string());
+                }))
+                .execute()
+                .await
+                .unwrap();
+        }
+
+        let mut seen = seen.into_inner().unwrap();
+        seen.sort();
+        let expected: Vec<String> = (0..23).map(|i| format!("doc{:03}", i)).collect();
+        assert_eq!(seen, expected, "Design documents are skipped");
+    }
+
+    #[tokio::test]
+    async fn test_small_table_yields_fewer_shards() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/db/_all_docs"))
+            .respond_with(FakeAllDocs {
+                ids: vec!["a".to_string(), "b".to_string()],
+            })
+            .mount(&server)
+            .await;
+
+        let ranges = shard_id_ranges(&Client::new(), &server.uri(), "db", 4, None)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            ranges,
+            vec![
+                IdRange {
+                    start: None,
+                    end: Some("b".to_string())
+                },
+                IdRange {
+                    start: Some("b".to_string()),
+                    end: None
+                },
+            ]
+        );
+    }
 }
@@ -0,0 +1,188 @@
+use reqwest::{Client, RequestBuilder, StatusCode};
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+/// IBM Cloud IAM endpoint exchanging an API key for a bearer token
+pub const IAM_TOKEN_URL: &str = "https://iam.cloud.ibm.com/identity/token";
+
+/// Longest time before expiry at which a token is refreshed
+const REFRESH_MARGIN: Duration = Duration::from_secs(300);
+
+/// IBM Cloud IAM authentication, as used by Cloudant: the API key is exchanged for a bearer token,
+/// which is cached and refreshed shortly before it expires.
+/// Clones share the same cached token.
+#[derive(Debug, Clone)]
+pub struct IamAuth {
+    client: Client,                   // HTTP client used for the token exchange
+    apikey: String,                   // IBM Cloud API key
+    token_url: String,                // Endpoint of the token exchange
+    token: Arc<Mutex<Option<Token>>>, // Cached bearer token, if one was obtained
+}
+
+/// A bearer token and the time it should be replaced.
+#[derive(Debug)]
+struct Token {
+    access_token: String, // Value of the `Authorization: Bearer` header
+    refresh_at: Instant,  // Time after which a new token is requested
+}
+
+impl IamAuth {
+    /// Creates the authentication for an API key, exchanged at the IBM Cloud IAM endpoint.
+    /// The exchange uses its own client, so the `--header` values meant for the database are
+    /// never sent to the IAM endpoint.
+    pub fn new(apikey: String) -> Self {
+        IamAuth {
+            client: Client::new(),
+            apikey,
+            token_url: IAM_TOKEN_URL.to_string(),
+            token: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Sets the endpoint of the token exchange (e.g. a private IAM endpoint).
+    pub fn with_token_url(mut self, token_url: String) -> Self {
+        self.token_url = token_url;
+        self
+    }
+
+    /// Attaches a valid bearer token to a request.
+    pub async fn authorize(&self, request: RequestBuilder) -> Result<RequestBuilder, String> {
+        Ok(request.bearer_auth(self.bearer().await?))
+    }
+
+    /// Returns the cached bearer token, exchanging the API key for a new one if it is about to expire.
+    /// Concurrent callers wait for a single exchange.
+    pub async fn bearer(&self) -> Result<String, String> {
+        let mut token = self.token.lock().await;
+
+        if let Some(token) = token.as_ref().filter(|t| Instant::now() < t.refresh_at) {
+            return Ok(token.access_token.clone());
+        }
+
+        let fresh = self.exchange().await?;
+        let access_token = fresh.access_token.clone();
+        *token = Some(fresh);
+        Ok(access_token)
+    }
+
+    /// Exchanges the API key for a new bearer token.
+    async fn exchange(&self) -> Result<Token, String> {
+        let body = format!(
+            "grant_type={}&apikey={}",
+            urlencoding::encode("urn:ibm:params:oauth:grant-type:apikey"),
+            urlencoding::encode(&self.apikey)
+        );
+        let response = self
+            .client
+            .post(&self.token_url)
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Accept", "application/json")
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| format!("IAM token request failed: {}", e))?;
+
+        if response.status() != StatusCode::OK {
+            return Err(format!(
+                "IAM token request failed: Status code {}",
+                response.status()
+            ));
+        }
+
+        let json: Value = response.json().await.map_err(|e| e.to_string())?;
+        let access_token = json["access_token"]
+            .as_str()
+            .ok_or("IAM token response has no 'access_token'")?
+            .to_string();
+        let expires_in = Duration::from_secs(json["expires_in"].as_u64().unwrap_or(0));
+
+        // Refresh well before expiry, but never later than half way through short-lived tokens
+        let margin = REFRESH_MARGIN.min(expires_in / 2);
+        Ok(Token {
+            access_token,
+            refresh_at: Instant::now() + expires_in - margin,
+        })
+    }
+}
+
+/// Attaches the IAM bearer token to a request if IAM authentication is configured.
+pub async fn authorize(
+    auth: Option<&IamAuth>,
+    request: RequestBuilder,
+) -> Result<RequestBuilder, String> {
+    match auth {
+        Some(auth) => auth.authorize(request).await,
+        None => Ok(request),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::matchers::{body_string_contains, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    async fn iam_server(expires_in: u64) -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/identity/token"))
+            .and(body_string_contains("apikey=secret"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "access_token": "token-1",
+                "expires_in": expires_in,
+            })))
+            .mount(&server)
+            .await;
+        server
+    }
+
+    fn auth(server: &MockServer) -> IamAuth {
+        IamAuth::new("secret".to_string())
+            .with_token_url(format!("{}/identity/token", server.uri()))
+    }
+
+    #[tokio::test]
+    async fn test_bearer_token_is_cached() {
+        let server = iam_server(3600).await;
+        let auth = auth(&server);
+
+        assert_eq!(auth.bearer().await, Ok("token-1".to_string()));
+        assert_eq!(auth.clone().bearer().await, Ok("token-1".to_string()));
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_expiring_token_is_refreshed() {
+        let server = iam_server(0).await;
+        let auth = auth(&server);
+
+        auth.bearer().await.unwrap();
+        auth.bearer().await.unwrap();
+        assert_eq!(server.received_requests().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_authorize_attaches_bearer_header() {
+        let server = iam_server(3600).await;
+        Mock::given(method("GET"))
+            .and(path("/orders"))
+            .and(header("Authorization", "Bearer token-1"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        let auth = auth(&server);
+
+        let request = Client::new().get(format!("{}/orders", server.uri()));
+        let response = authorize(Some(&auth), request)
+            .await
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
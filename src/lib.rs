@@ -1,3 +1,24 @@
 pub mod args;
+pub mod audit;
+pub mod bulk;
+pub mod compute;
+pub mod condition;
+pub mod consistency;
+pub mod diff;
 pub mod fetch;
+pub mod iam;
+pub mod log;
+pub mod mapping;
+pub mod metrics;
+pub mod netrc;
+pub mod preflight;
+pub mod ratelimit;
 pub mod rename;
+pub mod report;
+pub mod retry;
+pub mod schema;
+pub mod source;
+pub mod summary;
+pub mod transform;
+pub mod validate;
+pub mod verify;
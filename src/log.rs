@@ -0,0 +1,353 @@
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle;
+use tracing::{Event, Level, Metadata, Subscriber};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::writer::{EitherWriter, MakeWriter};
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::EnvFilter;
+
+/// Target of the events about a single document, indented under the progress lines in text logs
+pub const DOCUMENT: &str = "refield::document";
+
+/// Whether stdout is reserved for data, see `reserve_stdout`
+static STDOUT_RESERVED: AtomicBool = AtomicBool::new(false);
+
+/// Reserves stdout for data lines (e.g. documents emitted by `--emit-updated`):
+/// from then on, every log event goes to stderr so that stdout can be piped.
+pub fn reserve_stdout() {
+    STDOUT_RESERVED.store(true, Ordering::Relaxed);
+}
+
+/// Whether stdout is reserved for data lines.
+pub fn stdout_reserved() -> bool {
+    STDOUT_RESERVED.load(Ordering::Relaxed)
+}
+
+/// Format of the log events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text, // One human-readable line per event
+    Json, // One JSON object per event, with its timestamp, level, target, and fields
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!(
+                "Unknown log format '{}'. Expected one of: text, json.",
+                s
+            )),
+        }
+    }
+}
+
+/// Installs the global subscriber printing the log events. Events above `level` are discarded;
+/// without it, `RUST_LOG` selects them (e.g. `RUST_LOG=warn`), printing everything down to `info`
+/// by default.
+pub fn init(level: Option<&str>, format: LogFormat) -> Result<(), String> {
+    let filter = match level {
+        Some(level) => EnvFilter::try_new(level).map_err(|e| e.to_string())?,
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(Console);
+    match format {
+        LogFormat::Text => builder.event_format(Plain).try_init(),
+        LogFormat::Json => builder.json().try_init(),
+    }
+    .map_err(|e| format!("Failed to set up logging: {}", e))
+}
+
+/// Output of the log events: warnings and errors go to stderr, other events to stdout
+/// (to stderr too if stdout is reserved for data).
+struct Console;
+
+impl<'a> MakeWriter<'a> for Console {
+    type Writer = EitherWriter<std::io::Stdout, std::io::Stderr>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        EitherWriter::A(std::io::stdout())
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        if *meta.level() <= Level::WARN || stdout_reserved() {
+            EitherWriter::B(std::io::stderr())
+        } else {
+            EitherWriter::A(std::io::stdout())
+        }
+    }
+}
+
+/// The human-readable text format: the message alone, prefixed with `Error:` or `Warning:`
+/// for errors and warnings. Events about a document are indented instead, their messages
+/// telling what happened to the document.
+pub struct Plain;
+
+impl<S, N> FormatEvent<S, N> for Plain
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let meta = event.metadata();
+        match *meta.level() {
+            _ if meta.target() == DOCUMENT => write!(writer, "\t")?,
+            Level::ERROR => write!(writer, "Error: ")?,
+            Level::WARN => write!(writer, "Warning: ")?,
+            _ => {}
+        }
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
+
+/// Kind of a line sent to the writer task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Info,  // Progress and results, logged as `info` events
+    Warn,  // Skipped documents, e.g. without the field to rename, logged as `warn` events
+    Error, // Failures, logged as `error` events
+    Data,  // Machine-readable output, always written as is on stdout
+}
+
+/// A message handled by the writer task.
+#[derive(Debug)]
+enum LogEvent {
+    Lines(Vec<(Stream, String)>), // Lines written together, without lines of other events in between
+    Shutdown,                     // Stop the writer once the preceding events are written
+}
+
+/// Handle for sending log lines to the single writer task started by `start_writer`.
+/// Cloning it is cheap; every clone feeds the same writer.
+/// Lines starting with a tab are about a single document and logged under the `DOCUMENT` target.
+#[derive(Debug, Clone)]
+pub struct Logger {
+    sender: UnboundedSender<LogEvent>,
+}
+
+impl Logger {
+    /// Logs a single `info` line.
+    pub fn info(&self, line: impl Into<String>) {
+        self.send(vec![(Stream::Info, line.into())]);
+    }
+
+    /// Logs a single `warn` line.
+    pub fn warn(&self, line: impl Into<String>) {
+        self.send(vec![(Stream::Warn, line.into())]);
+    }
+
+    /// Logs a single `error` line.
+    pub fn error(&self, line: impl Into<String>) {
+        self.send(vec![(Stream::Error, line.into())]);
+    }
+
+    /// Starts collecting the lines about a single document, written together once it is dropped.
+    pub fn document(&self) -> DocumentLog {
+        DocumentLog {
+            logger: self.clone(),
+            lines: Vec::new(),
+        }
+    }
+
+    fn send(&self, lines: Vec<(Stream, String)>) {
+        // Lines logged after the writer has shut down are dropped
+        let _ = self.sender.send(LogEvent::Lines(lines));
+    }
+}
+
+/// The log lines about a single document, sent to the writer as one group when dropped,
+/// so that the lines of concurrently processed documents never interleave.
+#[derive(Debug)]
+pub struct DocumentLog {
+    logger: Logger,
+    lines: Vec<(Stream, String)>,
+}
+
+impl DocumentLog {
+    /// Adds an `info` line.
+    pub fn info(&mut self, line: impl Into<String>) {
+        self.lines.push((Stream::Info, line.into()));
+    }
+
+    /// Adds a `warn` line.
+    pub fn warn(&mut self, line: impl Into<String>) {
+        self.lines.push((Stream::Warn, line.into()));
+    }
+
+    /// Adds an `error` line.
+    pub fn error(&mut self, line: impl Into<String>) {
+        self.lines.push((Stream::Error, line.into()));
+    }
+
+    /// Adds a data line, always written to stdout.
+    pub fn data(&mut self, line: impl Into<String>) {
+        self.lines.push((Stream::Data, line.into()));
+    }
+}
+
+impl Drop for DocumentLog {
+    fn drop(&mut self) {
+        if !self.lines.is_empty() {
+            self.logger.send(std::mem::take(&mut self.lines));
+        }
+    }
+}
+
+/// The running writer task, to be shut down with `finish` once nothing logs anymore.
+pub struct LogWriter {
+    sender: UnboundedSender<LogEvent>,
+    task: JoinHandle<()>,
+}
+
+impl LogWriter {
+    /// Writes the pending lines, flushes the output, and stops the writer task.
+    pub async fn finish(self) {
+        let _ = self.sender.send(LogEvent::Shutdown);
+        let _ = self.task.await;
+    }
+}
+
+/// Starts the task logging every line, data lines being written to stdout.
+/// See `start_writer_to` for the meaning of `buffered` and `quiet`.
+pub fn start_writer(buffered: bool, quiet: bool) -> (Logger, LogWriter) {
+    start_writer_to(std::io::stdout(), buffered, quiet)
+}
+
+/// Starts the task logging every line as an event, and writing the data lines to `data`.
+/// Unbuffered, the data output is flushed after every group of lines; buffered, only once every
+/// pending group has been written, trading latency for fewer writes. Quiet, only the errors are
+/// logged, e.g. when only counting the documents that would change.
+pub fn start_writer_to<O>(data: O, buffered: bool, quiet: bool) -> (Logger, LogWriter)
+where
+    O: Write + Send + 'static,
+{
+    let (sender, receiver) = unbounded_channel();
+    let task = tokio::spawn(run_writer(receiver, data, buffered, quiet));
+
+    (
+        Logger {
+            sender: sender.clone(),
+        },
+        LogWriter { sender, task },
+    )
+}
+
+/// Writer loop: logs each event's lines in order until shut down or every sender is gone.
+async fn run_writer<O: Write>(
+    mut receiver: UnboundedReceiver<LogEvent>,
+    data: O,
+    buffered: bool,
+    quiet: bool,
+) {
+    let mut data = std::io::BufWriter::new(data);
+
+    while let Some(event) = receiver.recv().await {
+        let LogEvent::Lines(lines) = event else {
+            break;
+        };
+        for (stream, line) in lines {
+            match stream {
+                Stream::Data => {
+                    let _ = writeln!(data, "{}", line);
+                }
+                Stream::Info | Stream::Warn if quiet => {}
+                _ => emit(stream, &line),
+            }
+        }
+
+        if !buffered || receiver.is_empty() {
+            let _ = data.flush();
+        }
+    }
+
+    let _ = data.flush();
+}
+
+/// Logs a line as an event of the level of its stream, under the `DOCUMENT` target if it starts
+/// with a tab (which is dropped).
+fn emit(stream: Stream, line: &str) {
+    match (line.strip_prefix('\t'), stream) {
+        (Some(line), Stream::Error) => tracing::error!(target: DOCUMENT, "{}", line),
+        (Some(line), Stream::Warn) => tracing::warn!(target: DOCUMENT, "{}", line),
+        (Some(line), _) => tracing::info!(target: DOCUMENT, "{}", line),
+        (None, Stream::Error) => tracing::error!(target: "refield", "{}", line),
+        (None, Stream::Warn) => tracing::warn!(target: "refield", "{}", line),
+        (None, _) => tracing::info!(target: "refield", "{}", line),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// An in-memory output shared with the test
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_document_lines_are_not_interleaved() {
+        let (logs, data) = (SharedBuffer::default(), SharedBuffer::default());
+        let output = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .event_format(Plain)
+            .with_writer(move || output.clone())
+            .finish();
+        let _default = tracing::subscriber::set_default(subscriber);
+        let (logger, writer) = start_writer_to(data.clone(), true, false);
+
+        let mut first = logger.document();
+        let mut second = logger.document();
+        first.info("\ta1");
+        second.warn("\tb1");
+        logger.info("progress");
+        second.error("\tb2");
+        second.data("{}");
+        first.info("\ta2");
+        drop(second);
+        drop(first);
+        logger.document(); // Nothing logged, nothing written
+        logger.warn("slow");
+        logger.error("failed");
+
+        writer.finish().await;
+
+        assert_eq!(
+            logs.contents(),
+            "progress\n\tb1\n\tb2\n\ta1\n\ta2\nWarning: slow\nError: failed\n"
+        );
+        assert_eq!(data.contents(), "{}\n");
+    }
+}
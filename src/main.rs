@@ -1,9 +1,214 @@
-use refield::fetch::FetchDocument;
+use refield::args::Args;
+use refield::audit::{AuditLog, Outcome};
+use refield::bulk::{bulk_update, BulkOutcome};
+use refield::compute::{ComputeError, ComputeTemplate};
+use refield::diff::{diff_documents, render_before_after, render_diff};
+use refield::fetch::{
+    encode_doc_id, fetch_document_by_id, BatchInfo, Feed, FetchDocument, FetchSummary, IdRange,
+};
+use refield::iam::IamAuth;
+use refield::log::{DocumentLog, Logger};
+use refield::mapping::RenameRule;
+use refield::metrics::{MetricsPusher, MetricsSnapshot};
+use refield::ratelimit::{ConcurrencyLimit, RateLimiter};
+use refield::rename::{
+    rename_key_anywhere, rename_matching_fields, OnConflict, OnEmpty, RenameOptions, RenameStats,
+};
+use refield::report::{ReportEntry, Reporter};
+use refield::retry::{send_with_retry, write_resolving_conflicts, Resolution, WriteError};
+use refield::schema::SchemaDiff;
+use refield::source::FileSource;
+use refield::summary::{CountMode, WritePlan};
+use refield::validate::{MissingFieldGuard, ValidationReport};
+use refield::verify::Expectation;
 use reqwest::{Client, StatusCode};
 use serde_json::Value;
-use std::time::Duration;
-use tokio::time::sleep;
+use std::collections::{BTreeSet, HashMap};
+use std::future::Future;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn, Instrument};
 
+/// Number of batches sampled when estimating the runtime
+const ESTIMATE_BATCHES: usize = 3;
+
+/// Number of times an update rejected because of a revision conflict is rebased on the latest revision
+const CONFLICT_RETRIES: usize = 3;
+
+/// Outcomes of the documents of a batch, for `--batch-report`.
+#[derive(Debug, Default)]
+struct BatchStats {
+    changed: AtomicUsize,          // Documents updated (or that would be in dry-run)
+    failed: AtomicUsize,           // Documents whose update failed
+    first_task: OnceLock<Instant>, // Start of the first processing task of the batch
+}
+
+tokio::task_local! {
+    /// Statistics of the batch the current processing task belongs to, with `--batch-report`
+    static BATCH: Arc<BatchStats>;
+    /// The document as fetched, before any change, with `--dry-run` for the diff of what would be
+    /// saved, and with `--prune-empty` to tell what the change emptied
+    static ORIGINAL: Option<Value>;
+}
+
+/// Shared state for processing documents, handed to every spawned task.
+struct Context {
+    client: Client,                                  // HTTP client for making requests
+    auth: Option<IamAuth>, // IAM authentication attached to every CouchDB request, if configured
+    args: Args,            // Parsed command-line arguments
+    old_field_paths: Vec<Vec<String>>, // Old field paths split into components
+    mapping_rules: Vec<RenameRule>, // Rename rules from --mapping-file, the schemas or repeated --old/--new pairs, applied in one pass
+    rule_match_counts: Vec<AtomicUsize>, // Number of documents in which each mapping rule renamed a field
+    rename_options: RenameOptions,       // Options controlling how fields are matched and renamed
+    matched_counts: Vec<AtomicUsize>,    // Number of documents in which each old field was renamed
+    deleted_count: AtomicUsize, // Number of documents soft-deleted (or that would be in dry-run)
+    renamed_count: AtomicUsize, // Number of documents with at least one plain rename
+    merged_count: AtomicUsize, // Number of documents with at least one merge into an existing object
+    merge_conflict_count: AtomicUsize, // Number of documents skipped because of merge conflicts
+    ambiguous_count: AtomicUsize, // Number of documents skipped because several case variants coexist
+    collision_count: AtomicUsize, // Number of documents skipped because the new field already exists, with `--on-conflict`
+    backup_count: AtomicUsize,    // Number of documents in which an original value was backed up
+    condition_skipped_count: AtomicUsize, // Number of documents not satisfying the `--when` condition
+    untouched_value_count: AtomicUsize, // Number of renamed values the value transform did not apply to
+    empty_string_count: AtomicUsize, // Number of renamed values that were empty strings, with `--on-empty`
+    null_value_count: AtomicUsize,   // Number of renamed values that were `null`, with `--on-empty`
+    transformed_value_count: AtomicUsize, // Number of values changed in place by `--transform` or `--replace-value`
+    occurrence_count: AtomicUsize, // Number of keys renamed by `--recursive-any`, across all documents
+    malformed_count: AtomicUsize,  // Number of fetched documents that are not JSON objects
+    oversized_count: AtomicUsize,  // Number of documents skipped for exceeding `--max-doc-bytes`
+    conflict_refetch_count: AtomicUsize, // Number of times a document was re-fetched after an update conflict
+    promoted_count: AtomicUsize, // Number of values moved up by `--promote`, across all documents
+    deleted_field_count: AtomicUsize, // Number of documents from which `--delete` removed fields
+    pruned_count: AtomicUsize,   // Number of empty parent objects removed by `--prune-empty`
+    promote_conflict_count: AtomicUsize, // Number of documents skipped because the promotion's destination exists
+    computed_count: AtomicUsize,         // Number of documents whose `--compute` target was set
+    compute_missing_count: AtomicUsize, // Number of documents skipped because `--compute` could not be resolved
+    pruned_doc_count: AtomicUsize, // Number of modified documents from which `--prune-empty` removed empties
+    processed_count: AtomicUsize,  // Number of documents processed
+    updated_count: AtomicUsize,    // Number of documents written to the database
+    error_count: AtomicUsize,      // Number of documents that failed to be written
+    validation: Mutex<ValidationReport>, // Presence and type distribution of the old field, also counted by `--count-only field`
+    rejected_count: AtomicUsize, // Number of dry-run updates the server's validation rejected
+    scratch_write_count: AtomicUsize, // Number of changes made by the scratch copies of `--validate-on-server`
+    changed_ids: Mutex<BTreeSet<String>>, // IDs of the documents modified (or that would be in dry-run)
+    tasks: Mutex<Vec<JoinHandle<()>>>,    // Spawned processing tasks, awaited before reporting
+    failed_task_count: AtomicUsize,       // Number of processing tasks that panicked
+    batch: Mutex<Arc<BatchStats>>, // Statistics of the batch being handed over, with `--batch-report`
+    batch_tasks: Mutex<Vec<JoinHandle<()>>>, // Processing tasks of that batch, awaited by its report
+    log: Logger, // Sends the log lines of the processing tasks to the single writer task
+    write_limiter: Option<Arc<RateLimiter>>, // Token bucket shared by every write, from `--max-writes-per-sec`
+    write_slots: Arc<ConcurrencyLimit>, // Bounds the writes in flight across all tasks, from `--concurrency`
+    read_slots: ConcurrencyLimit, // Bounds the documents fetched one by one with `--include-docs false`, from `--concurrency`
+    bulk_buffer: Mutex<Vec<Value>>, // Updated documents waiting to be written together, with `--bulk-size`
+    file_updates: Mutex<HashMap<String, Value>>, // Updated documents of `--input-file` by ID, for `--output-file`
+    missing_guard: Option<Mutex<MissingFieldGuard>>, // Stops the scan when too many documents lack the old field
+    stop: Arc<AtomicBool>, // Raised to end the scan early; documents fetched afterwards are skipped
+    audit: Option<AuditLog>, // Audit database receiving the outcome of every document, from `--audit-db`
+    audit_rule: String,      // Description of the operation recorded with every audit row
+    report: Option<Reporter>, // Collector of the outcome of every document, from `--report`
+}
+
+impl Context {
+    /// Constructs the state of a run from its arguments, with no authentication, mapping rules,
+    /// audit database or report; `main` fills those in as configured.
+    fn new(args: Args, client: Client, log: Logger) -> Self {
+        // Split the old field paths into components (e.g., "a.b.c" -> ["a", "b", "c"])
+        let old_field_paths: Vec<Vec<String>> = args
+            .old_fields
+            .iter()
+            .map(|old_field| {
+                refield::rename::split_path(old_field)
+                    .into_iter()
+                    .map(|s| s.to_string())
+                    .collect()
+            })
+            .collect();
+        let matched_counts = args
+            .old_fields
+            .iter()
+            .map(|_| AtomicUsize::new(0))
+            .collect();
+
+        let rename_options = RenameOptions {
+            max_array_depth: args.max_array_depth,
+            merge: args.merge,
+            ignore_case: args.ignore_case,
+            case_conflict: args.case_conflict,
+            backup_suffix: args.backup_suffix.clone(),
+            include_attachments: args.include_attachments,
+            on_conflict: args.on_conflict,
+        };
+
+        let write_limiter = args
+            .max_writes_per_sec
+            .map(|rate| Arc::new(RateLimiter::new(rate)));
+        let write_slots = Arc::new(ConcurrencyLimit::new(args.concurrency));
+        let read_slots = ConcurrencyLimit::new(args.concurrency);
+        let missing_guard = args
+            .stop_on_missing_ratio
+            .map(|ratio| Mutex::new(MissingFieldGuard::new(ratio, args.missing_window)));
+        let audit_rule = format!("{} -> {}", old_field_label(&args), new_field_label(&args));
+
+        Context {
+            client,
+            auth: None,
+            old_field_paths,
+            mapping_rules: Vec::new(),
+            rule_match_counts: Vec::new(),
+            rename_options,
+            matched_counts,
+            deleted_count: AtomicUsize::new(0),
+            renamed_count: AtomicUsize::new(0),
+            merged_count: AtomicUsize::new(0),
+            merge_conflict_count: AtomicUsize::new(0),
+            ambiguous_count: AtomicUsize::new(0),
+            collision_count: AtomicUsize::new(0),
+            backup_count: AtomicUsize::new(0),
+            condition_skipped_count: AtomicUsize::new(0),
+            untouched_value_count: AtomicUsize::new(0),
+            empty_string_count: AtomicUsize::new(0),
+            null_value_count: AtomicUsize::new(0),
+            transformed_value_count: AtomicUsize::new(0),
+            occurrence_count: AtomicUsize::new(0),
+            malformed_count: AtomicUsize::new(0),
+            oversized_count: AtomicUsize::new(0),
+            conflict_refetch_count: AtomicUsize::new(0),
+            promoted_count: AtomicUsize::new(0),
+            deleted_field_count: AtomicUsize::new(0),
+            pruned_count: AtomicUsize::new(0),
+            promote_conflict_count: AtomicUsize::new(0),
+            computed_count: AtomicUsize::new(0),
+            compute_missing_count: AtomicUsize::new(0),
+            pruned_doc_count: AtomicUsize::new(0),
+            processed_count: AtomicUsize::new(0),
+            updated_count: AtomicUsize::new(0),
+            error_count: AtomicUsize::new(0),
+            validation: Mutex::new(ValidationReport::default()),
+            rejected_count: AtomicUsize::new(0),
+            scratch_write_count: AtomicUsize::new(0),
+            changed_ids: Mutex::new(BTreeSet::new()),
+            tasks: Mutex::new(Vec::new()),
+            failed_task_count: AtomicUsize::new(0),
+            batch: Mutex::new(Arc::default()),
+            batch_tasks: Mutex::new(Vec::new()),
+            log,
+            write_limiter,
+            write_slots,
+            read_slots,
+            bulk_buffer: Mutex::new(Vec::new()),
+            file_updates: Mutex::new(HashMap::new()),
+            missing_guard,
+            stop: Arc::new(AtomicBool::new(false)),
+            audit: None,
+            audit_rule,
+            report: None,
+            args,
+        }
+    }
+}
 
 #[tokio::main]
 async fn main() {
@@ -12,148 +217,2853 @@ async fn main() {
         Ok(args) => args,
         Err(err) => {
             eprintln!("Error: {}", err);
-            return;
+            std::process::exit(1);
         }
     };
 
-    // Extract arguments for convenience
-    let db_host = args.db_url.clone();
-    let table_name = args.table_name.clone();
-    let old_field = args.old_field.clone();
-    let new_field = args.new_field.clone();
-    let dry_run = args.dry_run;
-    let limit = args.limit;
+    // Print the log events from here on, as configured
+    if let Err(err) = refield::log::init(args.log_level.as_deref(), args.log_format) {
+        eprintln!("Error: {}", err);
+        std::process::exit(1);
+    }
+
+    // Emitted documents get stdout to themselves; everything else is logged to stderr
+    if args.emit_updated {
+        refield::log::reserve_stdout();
+    }
+
+    // Load the rename rules of the mapping file, or derive them from the schemas, if any
+    let mapping_rules = match (&args.mapping_file, &args.schema_files) {
+        (Some(path), _) => refield::mapping::load_mapping_file(path),
+        (None, Some((from, to))) => refield::schema::load_schema_renames(from, to).map(|diff| {
+            preview_schema_renames(&diff);
+            diff.renames
+        }),
+        (None, None) => Ok(args
+            .rename_pairs
+            .iter()
+            .map(|(old_field, new_field)| RenameRule {
+                old_field: old_field.clone(),
+                new_field: new_field.clone(),
+            })
+            .collect()),
+    };
+    let mapping_rules = mapping_rules.and_then(|rules| {
+        if !args.include_attachments {
+            for rule in &rules {
+                refield::args::validate_attachments(&rule.old_field, &rule.new_field)?;
+            }
+        }
+        Ok(rules)
+    });
+    let mapping_rules = match mapping_rules {
+        Ok(rules) => rules,
+        Err(err) => {
+            error!("{}", err);
+            std::process::exit(1);
+        }
+    };
 
     // Initialize an HTTP client for making requests
-    let client = Client::new();
+    let client = match build_client(&args) {
+        Ok(client) => client,
+        Err(err) => {
+            error!("{}", err);
+            std::process::exit(1);
+        }
+    };
 
     // Print the operation details
-    println!(
-        "Starting field rename operation: '{}' -> '{}' in table '{}'",
-        old_field, new_field, table_name
-    );
+    if args.count_only == Some(CountMode::Matching) {
+        info!("Counting matching documents in table '{}'", args.table_name);
+    } else if args.count_only == Some(CountMode::Field) {
+        info!(
+            "Counting documents containing '{}' in table '{}'",
+            args.old_fields.join("' | '"),
+            args.table_name
+        );
+    } else if let Some(path) = &args.mapping_file {
+        info!(
+            "Starting field rename operation: {} rules from '{}' in table '{}'",
+            mapping_rules.len(),
+            path,
+            args.table_name
+        );
+    } else if let Some((from, to)) = &args.schema_files {
+        info!(
+            "Starting field rename operation: {} rules derived from '{}' -> '{}' in table '{}'",
+            mapping_rules.len(),
+            from,
+            to,
+            args.table_name
+        );
+    } else if !args.rename_pairs.is_empty() {
+        let pairs: Vec<String> = args
+            .rename_pairs
+            .iter()
+            .map(|(old_field, new_field)| format!("'{}' -> '{}'", old_field, new_field))
+            .collect();
+        info!(
+            "Starting field rename operation: {} in table '{}'",
+            pairs.join(", "),
+            args.table_name
+        );
+    } else if let Some(old_key) = &args.recursive_any {
+        info!(
+            "Starting recursive field rename operation: every '{}' -> '{}' in table '{}'",
+            old_key,
+            args.new_field.as_deref().unwrap_or_default(),
+            args.table_name
+        );
+    } else if let Some(pattern) = &args.key_pattern {
+        info!(
+            "Starting pattern field rename operation: every key matching '{}' -> '{}' in table '{}'",
+            pattern,
+            args.new_field.as_deref().unwrap_or_default(),
+            args.table_name
+        );
+    } else if args.move_field {
+        info!(
+            "Starting field move operation: '{}' -> '{}' in table '{}'",
+            args.old_fields[0],
+            args.new_field.as_deref().unwrap_or_default(),
+            args.table_name
+        );
+    } else if args.delete {
+        info!(
+            "Starting field deletion operation: '{}' in table '{}'",
+            args.old_fields.join("', '"),
+            args.table_name
+        );
+    } else if args.copy {
+        info!(
+            "Starting field copy operation: '{}' -> '{}' in table '{}'",
+            args.old_fields[0],
+            args.new_field.as_deref().unwrap_or_default(),
+            args.table_name
+        );
+    } else if let Some(compute) = &args.compute {
+        info!(
+            "Starting computed field operation: {} in table '{}'",
+            compute.source(),
+            args.table_name
+        );
+    } else if args.validate_only {
+        info!(
+            "Starting field validation: '{}' in table '{}'",
+            args.old_fields.join("' | '"),
+            args.table_name
+        );
+    } else if let Some(value) = &args.delete_doc_when_equals {
+        info!(
+            "Starting soft-delete operation: documents where '{}' == {} in table '{}'",
+            args.old_fields.join("' | '"),
+            value,
+            args.table_name
+        );
+    } else {
+        info!(
+            "Starting field rename operation: '{}' -> '{}' in table '{}'",
+            args.old_fields.join("' | '"),
+            args.new_field.as_deref().unwrap_or_default(),
+            args.table_name
+        );
+    }
 
     // Inform the user about the dry-run mode
-    if dry_run {
-        println!("Dry-run mode enabled. No changes will be made to the database.");
-    } else {
-        println!("Dry-run mode disabled. Changes will be applied to the database.");
-    }
-
-    // Split the old field path into components (e.g., "a.b.c" -> ["a", "b", "c"])
-    let old_field_path: Vec<String> = old_field.split('.').map(|s| s.to_string()).collect();
-
-    // Create a FetchDocument instance to fetch documents from the database
-    let fd = FetchDocument::new(client.clone(), db_host.clone(), table_name.clone(), limit);
-
-    // Define a callback to process each fetched document
-    fd.with_callback(Box::new(move |doc: Value| {
-        // Clone necessary variables to ensure they live long enough in the closure
-        let client = client.clone();
-        let db_url = db_host.clone();
-        let table_name = table_name.clone();
-        let old_field_path = old_field_path.clone();
-        let new_field = new_field.clone();
-        let dry_run = dry_run;
-
-        // Spawn a new asynchronous task to process the document
-        tokio::spawn(async move {
-            process_document(
-                client,
-                db_url,
-                table_name,
-                old_field_path,
-                new_field,
-                dry_run,
-                doc,
-            )
-            .await;
-        });
-    }))
-    .execute()
-    .await;
+    if args.dry_run {
+        info!("Dry-run mode enabled. No changes will be made to the database.");
+    } else if let Some(output_file) = &args.output_file {
+        info!(
+            "Reading documents from '{}'. Changes will be written to '{}'.",
+            args.table_name, output_file
+        );
+    } else {
+        info!("Dry-run mode disabled. Changes will be applied to the database.");
+    }
 
-    // Indicate that the operation is complete
-    println!("Operation completed.");
-}
+    // Destructive operations need the table name typed back unless nothing will be written
+    if let Some(operation) = destructive_operation(&args) {
+        if !args.dry_run
+            && !args.yes
+            && args.input_file.is_none()
+            && !confirm_table_name(operation, &args.table_name)
+        {
+            info!("Aborted.");
+            return;
+        }
+    }
 
-/// Used as a callback to process a single document fetched from the database.
-async fn process_document(
-    client: Client,
-    db_url: String,
-    table_name: String,
-    old_field_path: Vec<String>,
-    new_field: String,
-    dry_run: bool,
-    mut doc: Value,
-) {
-    let id = doc["_id"].as_str().unwrap_or("<unknown>");
-    let idclone = id.to_string();
+    // Exchange the IAM API key for bearer tokens as needed (IBM Cloudant)
+    let auth = args.iam_apikey.clone().map(IamAuth::new);
 
-    // Convert the old field path into a slice of string slices for processing
-    let old_field_path: &[&str] = &old_field_path
-        .iter()
-        .map(|s| s.as_str())
-        .collect::<Vec<&str>>();
+    // Abort early if the server, table, or write permission is not available
+    if args.input_file.is_none() {
+        if let Err(err) = refield::preflight::preflight_check(
+            &client,
+            &args.db_url,
+            &args.table_name,
+            !args.dry_run,
+            auth.as_ref(),
+        )
+        .await
+        {
+            error!("Pre-flight check failed: {}", err);
+            std::process::exit(1);
+        }
+    }
+
+    // A mistyped resume point would silently skip documents, so it must exist
+    if let Some(id) = &args.resume_from_id {
+        match fetch_document_by_id(
+            &client,
+            &args.db_url,
+            &args.table_name,
+            id,
+            args.raw_id,
+            args.read_quorum,
+            auth.as_ref(),
+        )
+        .await
+        {
+            Ok(Some(_)) => info!("Resuming the scan after document ID: {}", id),
+            Ok(None) => {
+                error!(
+                    "--resume-from-id: no document '{}' in table '{}'",
+                    id, args.table_name
+                );
+                std::process::exit(1);
+            }
+            Err(err) => {
+                error!("--resume-from-id: {}", err);
+                std::process::exit(1);
+            }
+        }
+    }
 
-    // Attempt to rename the nested field in the document
-    let renamed = refield::rename::rename_nested_field(&mut doc, old_field_path, &new_field);
+    let rule_match_counts = mapping_rules.iter().map(|_| AtomicUsize::new(0)).collect();
 
-    if renamed {
-        if !dry_run {
-            // Update the document in CouchDB
-            if let Err(err) = update_document(&client, &db_url, &table_name, &doc).await {
-                eprintln!("\tError updating document {}: {}", idclone, err);
-            } else {
-                println!("\tupdated document ID: {}", idclone);
+    // Open the audit database, creating its schema on first use; its rows are written by a task
+    // of their own
+    let (audit, audit_writer) = match &args.audit_db {
+        Some(path) => match AuditLog::open(path, refield::audit::new_run_id()) {
+            Ok((audit, writer)) => (Some(audit), Some(writer)),
+            Err(err) => {
+                error!("{}", err);
+                std::process::exit(1);
             }
-            sleep(Duration::from_millis(200)).await;
-        } else {
-            // Dry-run mode: Log what would have been updated
-            println!(
-                "\tDry-run: Document ID {} would have been updated.",
-                idclone
-            );
+        },
+        None => (None, None),
+    };
+
+    // Outcomes for the report are sent by the processing tasks to a single collector
+    let (report, report_collector) = match &args.report {
+        Some(_) => {
+            let (reporter, collector) = refield::report::start_collector();
+            (Some(reporter), Some(collector))
+        }
+        None => (None, None),
+    };
+
+    // Every line logged while documents are processed goes through a single writer task;
+    // when only counting the changes, or with `--quiet`, the per-document lines (but not errors)
+    // are discarded
+    let (log, log_writer) = match args.count_changed {
+        true => refield::log::start_writer(true, true),
+        false => refield::log::start_writer(args.log_buffered, args.quiet),
+    };
+    let ctx = Arc::new(Context {
+        auth,
+        mapping_rules,
+        rule_match_counts,
+        audit,
+        report,
+        ..Context::new(args, client, log)
+    });
+
+    // Only count the matching documents, without processing them
+    if ctx.args.count_only == Some(CountMode::Matching) {
+        let counted = new_fetcher(&ctx).count().await;
+        match &counted {
+            Ok(count) => ctx.log.info(format!(
+                "{} documents match in table '{}'.",
+                count, ctx.args.table_name
+            )),
+            Err(err) => ctx.log.error(err.clone()),
+        }
+        log_writer.finish().await;
+        exit_if_failed(counted.is_err());
+        return;
+    }
+
+    // Push progress metrics to a Prometheus pushgateway in the background
+    let pushgateway = match start_pushgateway(&ctx) {
+        Ok(pushgateway) => pushgateway,
+        Err(err) => {
+            error!("{}", err);
+            std::process::exit(1);
         }
+    };
+
+    // Remember where the table's change sequence stood, to detect concurrent changes afterwards
+    let start_seq = match &ctx.args.input_file {
+        Some(_) => None, // Nobody else writes to the input file
+        None => match refield::consistency::fetch_update_seq(
+            &ctx.client,
+            &ctx.args.db_url,
+            &ctx.args.table_name,
+            ctx.auth.as_ref(),
+        )
+        .await
+        {
+            Ok(seq) => Some(seq),
+            Err(err) => {
+                ctx.log.warn(format!("consistency check disabled: {}", err));
+                None
+            }
+        },
+    };
+
+    let summary = if let Some(ids_file) = &ctx.args.ids_file {
+        // Fetch only the listed documents, bypassing the `_find` scan
+        process_ids_file(&ctx, ids_file).await
+    } else if let Some(input_file) = &ctx.args.input_file {
+        // Read the documents from a local file instead of the database
+        process_input_file(&ctx, input_file).await
+    } else if ctx.args.workers > 1 {
+        // Scan `_id` ranges of the table concurrently
+        process_shards(&ctx).await
     } else {
-        // Field not found in the document
-        println!(
-            "\tfield '{}' not found in document ID: {}",
-            old_field_path.join("."),
-            idclone
+        // Create a FetchDocument instance to fetch documents from the database
+        let mut fd = new_fetcher(&ctx);
+
+        // Only a small sample is processed when estimating the runtime
+        if ctx.args.estimate {
+            fd = fd.with_max_iterations(ESTIMATE_BATCHES);
+        }
+
+        // Report each batch once its updates are done
+        if ctx.args.batch_report {
+            let batch_ctx = ctx.clone();
+            fd = fd.with_batch_callback(Box::new(move |batch| report_batch(&batch_ctx, batch)));
+        }
+
+        // Define a callback to process each fetched document
+        let callback_ctx = ctx.clone();
+        fd.with_callback(Box::new(move |doc: Value| {
+            spawn_scanned(&callback_ctx, doc)
+        }))
+        .execute()
+        .await
+    };
+    // A page that fails mid-scan still lets the documents already fetched be written and
+    // reported; the run exits with an error once that is done
+    let (mut summary, scan_failed) = match summary {
+        Ok(summary) => (summary, false),
+        Err(err) => {
+            ctx.log.error(err);
+            let summary = FetchSummary {
+                table_name: ctx.args.table_name.clone(),
+                ..FetchSummary::default()
+            };
+            (summary, true)
+        }
+    };
+
+    // Let the processing tasks finish so that the counters and changed IDs are complete
+    join_tasks(&ctx).await;
+    if scan_failed {
+        summary.total_fetched = ctx.processed_count.load(Ordering::Relaxed);
+    }
+    let pending = std::mem::take(&mut *ctx.bulk_buffer.lock().unwrap());
+    flush_bulk(&ctx, pending).await;
+
+    // The documents are processed by the callback rather than a pipeline, so their outcomes
+    // come from the counters
+    summary.matched = ctx.changed_ids.lock().unwrap().len();
+    summary.written = ctx.updated_count.load(Ordering::Relaxed);
+    summary.failed = ctx.error_count.load(Ordering::Relaxed);
+    let output_failed = match write_output_file(&ctx) {
+        Ok(()) => false,
+        Err(err) => {
+            ctx.log.error(err);
+            true
+        }
+    };
+    if let (Some(path), Some(collector)) = (&ctx.args.report, report_collector) {
+        let entries = collector.finish().await;
+        let count = entries.len();
+        match refield::report::write_report(path, entries) {
+            Ok(()) => ctx.log.info(format!(
+                "Wrote the report of {} documents to '{}'.",
+                count, path
+            )),
+            Err(err) => ctx.log.error(err),
+        }
+    }
+    if let Some(writer) = audit_writer {
+        if let Err(err) = writer.finish().await {
+            ctx.log.error(err);
+        }
+    }
+    let failed_tasks = ctx.failed_task_count.load(Ordering::Relaxed);
+    if failed_tasks > 0 {
+        ctx.log.error(format!(
+            "{} processing tasks panicked; their documents may not have been processed.",
+            failed_tasks
+        ));
+    }
+
+    // Re-fetch the updated documents to confirm that the rename was persisted
+    let verification = match ctx.args.verify {
+        true => Some(verify_changed_documents(&ctx).await),
+        false => None,
+    };
+    log_writer.finish().await;
+    let failed = scan_failed || output_failed;
+
+    // Push the final values of the metrics
+    if let Some(pushgateway) = pushgateway {
+        pushgateway.finish(metrics_snapshot(&ctx)).await;
+    }
+
+    if ctx.args.estimate {
+        let writes = WritePlan {
+            docs_per_request: ctx.args.bulk_size.unwrap_or(1),
+            in_flight: ctx.args.concurrency,
+        };
+        info!("{}", refield::summary::render_estimate(&summary, &writes));
+        exit_if_failed(failed);
+        return;
+    }
+
+    if ctx.args.count_changed {
+        info!(
+            "{} of {} documents fetched would be changed.",
+            ctx.changed_ids.lock().unwrap().len(),
+            summary.total_fetched
+        );
+        exit_if_failed(failed);
+        return;
+    }
+
+    if ctx.args.count_only == Some(CountMode::Field) {
+        let count = ctx.validation.lock().unwrap().present_docs;
+        let share = match summary.doc_count {
+            0 => 0.0,
+            doc_count => count as f64 * 100.0 / doc_count as f64,
+        };
+        info!(
+            "{} of {} documents ({:.1}%) contain '{}'.",
+            count,
+            summary.doc_count,
+            share,
+            ctx.args.old_fields.join("' | '")
+        );
+        exit_if_failed(failed);
+        return;
+    }
+
+    if ctx.args.validate_only {
+        info!("{}", ctx.validation.lock().unwrap().render());
+    }
+
+    if let Some(path) = &ctx.args.dump_changed_ids {
+        let changed_ids = ctx.changed_ids.lock().unwrap();
+        match write_changed_ids(path, &changed_ids) {
+            Ok(()) => info!(
+                "Wrote {} changed document IDs to '{}'.",
+                changed_ids.len(),
+                path
+            ),
+            Err(err) => error!("{}", err),
+        }
+    }
+
+    if let Some((verified, failed)) = verification {
+        info!(
+            "Documents verified: {}, failed verification: {}",
+            verified, failed
+        );
+    }
+
+    if ctx.args.delete_doc_when_equals.is_some() {
+        let deleted = ctx.deleted_count.load(Ordering::Relaxed);
+        if ctx.args.dry_run {
+            info!("{} documents would have been deleted.", deleted);
+        } else {
+            info!("Deleted {} documents.", deleted);
+        }
+    }
+
+    if let Some(rate) = ctx.args.max_writes_per_sec {
+        let writes =
+            ctx.updated_count.load(Ordering::Relaxed) + ctx.error_count.load(Ordering::Relaxed);
+        let achieved = if summary.duration_secs > 0.0 {
+            writes as f64 / summary.duration_secs
+        } else {
+            0.0
+        };
+        info!(
+            "Achieved write rate: {:.2} writes/sec (limit: {} writes/sec).",
+            achieved, rate
+        );
+    }
+
+    if ctx.args.validate_on_server {
+        info!(
+            "{} documents would be rejected by the server's validation.",
+            ctx.rejected_count.load(Ordering::Relaxed)
+        );
+    }
+
+    // Report merges separately from plain renames
+    if ctx.args.merge.is_some() {
+        info!(
+            "Plain renames: {}, merges: {}, skipped due to merge conflicts: {}",
+            ctx.renamed_count.load(Ordering::Relaxed),
+            ctx.merged_count.load(Ordering::Relaxed),
+            ctx.merge_conflict_count.load(Ordering::Relaxed)
+        );
+    }
+
+    if let Some(when) = &ctx.args.when {
+        info!(
+            "Documents skipped for not satisfying '{}': {}",
+            when.source(),
+            ctx.condition_skipped_count.load(Ordering::Relaxed)
+        );
+    }
+
+    if ctx.args.value_transform.is_some() {
+        info!(
+            "Values left unchanged by the value transform: {}",
+            ctx.untouched_value_count.load(Ordering::Relaxed)
+        );
+    }
+
+    if ctx.args.on_empty != OnEmpty::Keep {
+        let (strings, nulls) = match ctx.args.on_empty {
+            OnEmpty::Null => ("renamed as null", "renamed as they are"),
+            _ => ("dropped", "dropped"),
+        };
+        info!(
+            "Empty strings {}: {}",
+            strings,
+            ctx.empty_string_count.load(Ordering::Relaxed)
+        );
+        info!(
+            "Nulls {}: {}",
+            nulls,
+            ctx.null_value_count.load(Ordering::Relaxed)
+        );
+    }
+
+    if let Some(suffix) = &ctx.args.backup_suffix {
+        info!(
+            "Documents with a '{}' backup of the original values: {}",
+            suffix,
+            ctx.backup_count.load(Ordering::Relaxed)
+        );
+    }
+
+    if let Some(old_key) = anywhere_key(&ctx.args) {
+        info!(
+            "Occurrences of '{}' renamed: {}",
+            old_key,
+            ctx.occurrence_count.load(Ordering::Relaxed)
+        );
+    }
+
+    if ctx.args.promote {
+        info!(
+            "Values promoted: {}, empty parents pruned: {}, documents skipped because the destination exists: {}",
+            ctx.promoted_count.load(Ordering::Relaxed),
+            ctx.pruned_count.load(Ordering::Relaxed),
+            ctx.promote_conflict_count.load(Ordering::Relaxed)
+        );
+    }
+
+    if ctx.args.delete {
+        info!(
+            "Documents with fields deleted: {}",
+            ctx.deleted_field_count.load(Ordering::Relaxed)
+        );
+    }
+
+    if ctx.args.compute.is_some() {
+        info!(
+            "Documents with the field computed: {}, skipped for missing referenced fields: {}",
+            ctx.computed_count.load(Ordering::Relaxed),
+            ctx.compute_missing_count.load(Ordering::Relaxed)
+        );
+    }
+
+    if ctx.args.prune_empty {
+        info!(
+            "Documents with empty objects or arrays pruned: {}",
+            ctx.pruned_doc_count.load(Ordering::Relaxed)
+        );
+    }
+
+    if ctx.args.transform.is_some() {
+        info!(
+            "Values changed by the transform: {}",
+            ctx.transformed_value_count.load(Ordering::Relaxed)
+        );
+    }
+
+    if ctx.args.replace_value.is_some() {
+        info!(
+            "Values replaced: {}",
+            ctx.transformed_value_count.load(Ordering::Relaxed)
+        );
+    }
+
+    if ctx.args.on_conflict != OnConflict::Overwrite {
+        info!(
+            "Skipped because the new field already exists: {}",
+            ctx.collision_count.load(Ordering::Relaxed)
+        );
+    }
+
+    if ctx.args.ignore_case {
+        info!(
+            "Skipped due to ambiguous case variants: {}",
+            ctx.ambiguous_count.load(Ordering::Relaxed)
+        );
+    }
+
+    // Corrupt documents are reported apart from documents lacking the field
+    let malformed = ctx.malformed_count.load(Ordering::Relaxed);
+    if malformed > 0 {
+        info!(
+            "Malformed documents skipped (not JSON objects): {}",
+            malformed
+        );
+    }
+
+    if let Some(max_doc_bytes) = ctx.args.max_doc_bytes {
+        info!(
+            "Oversized documents skipped (over {} bytes): {}",
+            max_doc_bytes,
+            ctx.oversized_count.load(Ordering::Relaxed)
+        );
+    }
+
+    let refetched = ctx.conflict_refetch_count.load(Ordering::Relaxed);
+    if refetched > 0 {
+        info!(
+            "Documents re-fetched after an update conflict: {}",
+            refetched
         );
     }
+
+    // Report which mapping rules actually matched data
+    if !ctx.mapping_rules.is_empty() {
+        info!("Mapping rule matches:");
+        for (rule, count) in ctx.mapping_rules.iter().zip(&ctx.rule_match_counts) {
+            info!(
+                "\t'{}' -> '{}': {}",
+                rule.old_field,
+                rule.new_field,
+                count.load(Ordering::Relaxed)
+            );
+        }
+    }
+
+    // Report which of several candidate old fields was found
+    if ctx.args.old_fields.len() > 1 && !ctx.args.delete {
+        info!("Matched old field distribution:");
+        for (old_field, count) in ctx.args.old_fields.iter().zip(&ctx.matched_counts) {
+            info!("\t'{}': {}", old_field, count.load(Ordering::Relaxed));
+        }
+    }
+
+    // Print the end-of-run summary in the requested format
+    info!(
+        "{}",
+        refield::summary::render_summary(
+            &summary,
+            &old_field_label(&ctx.args),
+            new_field_label(&ctx.args),
+            ctx.args.summary_format
+        )
+    );
+
+    // Warn about documents changed by others during the run, failing past the threshold
+    if let Some(start_seq) = start_seq {
+        if let Err(err) = check_consistency(&ctx, &start_seq).await {
+            error!("{}", err);
+            std::process::exit(1);
+        }
+    }
+
+    if ctx.stop.load(Ordering::Relaxed) {
+        error!("the scan was stopped early because too many documents lack the old field.");
+        std::process::exit(1);
+    }
+
+    exit_if_failed(failed);
+
+    if failed_tasks > 0 {
+        std::process::exit(1);
+    }
+
+    // Scripts and schedulers rely on the exit status to notice failed updates
+    if let Some(failures) =
+        refield::summary::render_update_failures(ctx.error_count.load(Ordering::Relaxed))
+    {
+        error!("{}.", failures);
+        std::process::exit(1);
+    }
+
+    if verification.is_some_and(|(_, failed)| failed > 0) {
+        error!("some updated documents do not hold the renamed field as written.");
+        std::process::exit(1);
+    }
 }
 
-/// Persists changes to a document in CouchDB when the dry-run mode is disabled.
-async fn update_document(
-    client: &Client,
-    db_host: &str,
-    table_name: &str,
-    doc: &Value,
-) -> Result<(), String> {
-    let id = doc["_id"].as_str().ok_or("Document missing '_id' field")?;
-    let rev = doc["_rev"]
-        .as_str()
-        .ok_or("Document missing '_rev' field")?;
-    let idencoded = urlencoding::encode(id);
-    let url = format!("{}/{}/{}", db_host, table_name, idencoded);
-
-    let response = client
-        .put(&url)
-        .json(doc)
-        .header("If-Match", rev)
-        .send()
-        .await
-        .map_err(|e| e.to_string())?;
+/// Exits with an error status if a page failed mid-scan or the output file could not be written,
+/// once the documents fetched have been processed and reported.
+fn exit_if_failed(failed: bool) {
+    if failed {
+        std::process::exit(1);
+    }
+}
 
-    if response.status() != StatusCode::OK && response.status() != StatusCode::CREATED {
-        return Err(format!(
-            "Failed to update document {}: Status code {}",
+/// Re-fetches every updated document and checks it against what `--verify` expects,
+/// logging each discrepancy. Returns the number of documents checked and of those that failed.
+/// Catches writes that were lost, or rewritten by the server's validation.
+async fn verify_changed_documents(ctx: &Context) -> (usize, usize) {
+    let expectation = verify_expectation(&ctx.args);
+    let ids: Vec<String> = ctx.changed_ids.lock().unwrap().iter().cloned().collect();
+    ctx.log
+        .info(format!("Verifying {} updated documents.", ids.len()));
+
+    let mut failed = 0;
+    for id in &ids {
+        let discrepancies = match fetch_document_by_id(
+            &ctx.client,
+            &ctx.args.db_url,
+            &ctx.args.table_name,
             id,
-            response.status()
-        ));
+            ctx.args.raw_id,
+            ctx.args.read_quorum,
+            ctx.auth.as_ref(),
+        )
+        .await
+        {
+            Ok(Some(doc)) => expectation.discrepancies(&doc),
+            Ok(None) => vec!["the document no longer exists".to_string()],
+            Err(err) => vec![err],
+        };
+        if !discrepancies.is_empty() {
+            failed += 1;
+            ctx.log.error(format!(
+                "\tVerification failed for document ID {}: {}",
+                id,
+                discrepancies.join("; ")
+            ));
+        }
     }
 
-    Ok(())
+    (ids.len(), failed)
+}
+
+/// What `--verify` expects of every updated document: the new field is present and the old one gone.
+/// Of several candidate old fields, only the one found is renamed, so the others are only expected
+/// to be gone with `--delete-others`.
+fn verify_expectation(args: &Args) -> Expectation {
+    let present = args.new_field.clone().unwrap_or_default();
+    let gone = match args.old_fields.len() == 1 || args.delete_others {
+        true => args
+            .old_fields
+            .iter()
+            .filter(|old_field| **old_field != present)
+            .cloned()
+            .collect(),
+        false => Vec::new(),
+    };
+    Expectation { present, gone }
+}
+
+/// Compares the table's `update_seq` with the one captured before the scan.
+/// Changes beyond the documents written by this run mean the scan may have missed documents
+/// or processed them twice; more than `--snapshot-warn-threshold` of them is an error.
+async fn check_consistency(ctx: &Context, start_seq: &Value) -> Result<(), String> {
+    let end_seq = refield::consistency::fetch_update_seq(
+        &ctx.client,
+        &ctx.args.db_url,
+        &ctx.args.table_name,
+        ctx.auth.as_ref(),
+    )
+    .await?;
+
+    let Some(changes) = refield::consistency::changes_between(start_seq, &end_seq) else {
+        warn!(
+            "cannot compare update sequences {} and {}; consistency not checked.",
+            start_seq, end_seq
+        );
+        return Ok(());
+    };
+    let own =
+        ctx.updated_count.load(Ordering::Relaxed) + ctx.scratch_write_count.load(Ordering::Relaxed);
+    let external = changes.saturating_sub(own as u64);
+    if external == 0 {
+        return Ok(());
+    }
+
+    warn!(
+        "table '{}' recorded {} changes during the run besides this run's updates; \
+         some documents may have been missed or processed twice. Consider re-running.",
+        ctx.args.table_name, external
+    );
+    match ctx.args.snapshot_warn_threshold {
+        Some(threshold) if external > threshold => Err(format!(
+            "{} concurrent changes exceed the --snapshot-warn-threshold of {}",
+            external, threshold
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Builds the HTTP client of the database requests, with the `--header` values and the
+/// connection tuning options. Options that are not given keep reqwest's defaults.
+fn build_client(args: &Args) -> Result<Client, String> {
+    let mut builder = Client::builder().default_headers(args.headers.clone());
+
+    if args.http2_prior_knowledge {
+        builder = builder.http2_prior_knowledge();
+    }
+    if let Some(max_idle) = args.pool_max_idle {
+        builder = builder.pool_max_idle_per_host(max_idle);
+    }
+    if let Some(secs) = args.pool_idle_timeout {
+        // A zero timeout keeps idle connections open indefinitely
+        let timeout = (secs > 0).then(|| Duration::from_secs(secs));
+        builder = builder.pool_idle_timeout(timeout);
+    }
+    if let Some(secs) = args.timeout {
+        builder = builder.timeout(Duration::from_secs(secs));
+    }
+    if let Some(secs) = args.connect_timeout {
+        builder = builder.connect_timeout(Duration::from_secs(secs));
+    }
+
+    builder
+        .build()
+        .map_err(|e| format!("Failed to build HTTP client: {}", e))
+}
+
+/// Creates a FetchDocument configured from the command-line arguments, without a callback.
+fn new_fetcher(ctx: &Context) -> FetchDocument<'static> {
+    let fd = FetchDocument::new(
+        ctx.client.clone(),
+        ctx.args.db_url.clone(),
+        ctx.args.table_name.clone(),
+        ctx.args.limit,
+    )
+    .with_id_field(ctx.args.id_field.clone())
+    // A dry-run only prints the recommended index: creating it would change the server
+    .with_auto_create_index(ctx.args.auto_create_index && !ctx.args.dry_run)
+    .with_pagination(ctx.args.paginate_by)
+    .with_scan_order(ctx.args.scan_order)
+    .with_max_retries(ctx.args.max_retries)
+    .with_prefetch(ctx.args.prefetch)
+    .with_stop_signal(ctx.stop.clone())
+    .with_headers(ctx.args.headers.clone())
+    .with_raw_id(ctx.args.raw_id)
+    .with_write_slots(ctx.write_slots.clone());
+
+    // Writes made by a pipeline share the budget of the other writes
+    let fd = match &ctx.write_limiter {
+        Some(limiter) => fd.with_write_limiter(limiter.clone()),
+        None => fd,
+    };
+
+    // Require a read quorum on `_find` pages, if given
+    let fd = match ctx.args.read_quorum {
+        Some(r) => fd.with_read_quorum(r),
+        None => fd,
+    };
+
+    // Authenticate every request with IAM bearer tokens, if configured
+    let fd = match &ctx.auth {
+        Some(auth) => fd.with_iam_auth(auth.clone()),
+        None => fd,
+    };
+
+    // Scan only the `_id` range of the prefix, if given
+    let fd = match &ctx.args.id_prefix {
+        Some(prefix) => fd.with_id_prefix(prefix.clone()),
+        None => fd,
+    };
+
+    // Scan only the IDs and revisions, fetching each document on its own afterwards
+    let fd = match ctx.args.include_docs {
+        true => fd,
+        false => fd.with_fields(vec![ctx.args.id_field.clone(), ctx.args.rev_field.clone()]),
+    };
+
+    // Scan only the documents matching the selector, if given
+    let fd = match &ctx.args.selector {
+        Some(selector) => fd.with_selector(selector.clone()),
+        None => fd,
+    };
+
+    // Scan only the given partition, if any
+    let fd = match &ctx.args.partition {
+        Some(partition) => fd.with_partition(partition.clone()),
+        None => fd,
+    };
+
+    // Skip the documents up to the resume point, if given
+    let fd = match &ctx.args.resume_from_id {
+        Some(id) => fd.with_resume_from_id(id.clone()),
+        None => fd,
+    };
+
+    // Cap the number of documents examined in dry-run mode
+    match ctx.args.dry_run_limit {
+        Some(max) => fd.with_max_documents(max),
+        None => fd,
+    }
+}
+
+/// Starts pushing metrics to the pushgateway given with `--pushgateway`, if any.
+fn start_pushgateway(ctx: &Arc<Context>) -> Result<Option<MetricsPusher>, String> {
+    let Some(gateway_url) = &ctx.args.pushgateway else {
+        return Ok(None);
+    };
+
+    // The `--header` values are meant for the database only, so the gateway gets a plain client
+    let snapshot_ctx = ctx.clone();
+    refield::metrics::start_pushgateway(
+        Client::new(),
+        gateway_url,
+        &ctx.args.table_name,
+        Duration::from_secs(ctx.args.pushgateway_interval),
+        move || metrics_snapshot(&snapshot_ctx),
+    )
+    .map(Some)
+}
+
+/// Reads the current values of the counters exported as metrics.
+fn metrics_snapshot(ctx: &Context) -> MetricsSnapshot {
+    MetricsSnapshot {
+        docs_processed: ctx.processed_count.load(Ordering::Relaxed),
+        docs_updated: ctx.updated_count.load(Ordering::Relaxed),
+        errors: ctx.error_count.load(Ordering::Relaxed),
+    }
+}
+
+/// Prints the rename rules derived from the schemas, and the fields that could not be paired.
+fn preview_schema_renames(diff: &SchemaDiff) {
+    info!("Renames derived from the schemas:");
+    for rule in &diff.renames {
+        info!("\t'{}' -> '{}'", rule.old_field, rule.new_field);
+    }
+    for field in &diff.unmatched_old {
+        info!(
+            "\tRemoved without a counterpart (left untouched): '{}'",
+            field
+        );
+    }
+    for field in &diff.unmatched_new {
+        info!("\tAdded without a counterpart (not filled in): '{}'", field);
+    }
+}
+
+/// Describes the source of the operation for the summary: the old fields, the mapping file, the schemas,
+/// or the fields referenced by `--compute`.
+fn old_field_label(args: &Args) -> String {
+    if let Some(compute) = &args.compute {
+        return format!("<compute:{}>", compute.fields().join("|"));
+    }
+    if let Some(pattern) = &args.key_pattern {
+        return format!("<regex:{}>", pattern);
+    }
+
+    match (&args.mapping_file, &args.schema_files, &args.recursive_any) {
+        (Some(path), _, _) => format!("<mapping:{}>", path),
+        (None, Some((from, to)), _) => format!("<schema:{}->{}>", from, to),
+        (None, None, Some(old_key)) => format!("<any:{}>", old_key),
+        (None, None, None) if !args.rename_pairs.is_empty() => {
+            let old_fields: Vec<&str> = args
+                .rename_pairs
+                .iter()
+                .map(|(old_field, _)| old_field.as_str())
+                .collect();
+            old_fields.join("|")
+        }
+        (None, None, None) => args.old_fields.join("|"),
+    }
+}
+
+/// Describes the target of the operation for the summary: the new field, or the mode if nothing is renamed.
+fn new_field_label(args: &Args) -> &str {
+    if args.validate_only {
+        "<validate>"
+    } else if args.mapping_file.is_some()
+        || args.schema_files.is_some()
+        || !args.rename_pairs.is_empty()
+    {
+        "<mapping>"
+    } else if let Some(compute) = &args.compute {
+        compute.target()
+    } else if args.transform.is_some() {
+        "<transform>"
+    } else if args.replace_value.is_some() {
+        "<replace>"
+    } else {
+        args.new_field.as_deref().unwrap_or("<deleted>")
+    }
+}
+
+/// Spawns a new asynchronous task to process a fetched document according to the selected mode.
+fn spawn_processing(ctx: &Arc<Context>, doc: Value) {
+    // Nothing more is processed once the scan has been stopped
+    if ctx.stop.load(Ordering::Relaxed) {
+        return;
+    }
+
+    // CouchDB documents are objects; anything else is corrupt data that no mode can handle
+    if !doc.is_object() {
+        ctx.malformed_count.fetch_add(1, Ordering::Relaxed);
+        ctx.log.warn(format!(
+            "\tData anomaly: skipped a document that is not a JSON object: {}",
+            preview(&doc)
+        ));
+        return;
+    }
+
+    // Giant documents are left for separate handling rather than rewritten in bulk
+    if let Some(max_doc_bytes) = ctx.args.max_doc_bytes {
+        let size = serde_json::to_vec(&doc).map_or(0, |bytes| bytes.len());
+        if size > max_doc_bytes {
+            ctx.oversized_count.fetch_add(1, Ordering::Relaxed);
+            ctx.log.warn(format!(
+                "\tOversized document ID {}: {} bytes exceed --max-doc-bytes {}; skipped.",
+                doc[&ctx.args.id_field].as_str().unwrap_or("<unknown>"),
+                size,
+                max_doc_bytes
+            ));
+            return;
+        }
+    }
+
+    // Documents not satisfying the `--when` condition are left alone
+    if let Some(when) = &ctx.args.when {
+        if !when.matches(&doc) {
+            ctx.condition_skipped_count.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+    }
+
+    // Kept for the diff of what a dry-run would save, and to prune only what the change emptied
+    let original = (ctx.args.dry_run || ctx.args.prune_empty).then(|| doc.clone());
+    if ctx.args.validate_only || ctx.args.count_only == Some(CountMode::Field) {
+        // Validating and counting only inspect the document, so it is recorded right away
+        let old_field_paths: Vec<Vec<&str>> = ctx
+            .old_field_paths
+            .iter()
+            .map(|path| path.iter().map(|s| s.as_str()).collect())
+            .collect();
+        let paths: Vec<&[&str]> = old_field_paths.iter().map(|p| p.as_slice()).collect();
+        ctx.validation.lock().unwrap().record(&doc, &paths);
+    } else if !ctx.mapping_rules.is_empty() {
+        spawn_task(ctx, original, process_mapping_document(ctx.clone(), doc));
+    } else if anywhere_key(&ctx.args).is_some() {
+        spawn_task(ctx, original, process_recursive_document(ctx.clone(), doc));
+    } else if ctx.args.promote {
+        spawn_task(ctx, original, process_promoted_document(ctx.clone(), doc));
+    } else if ctx.args.move_field {
+        spawn_task(ctx, original, process_moved_document(ctx.clone(), doc));
+    } else if ctx.args.copy {
+        spawn_task(ctx, original, process_copied_document(ctx.clone(), doc));
+    } else if ctx.args.delete {
+        spawn_task(ctx, original, process_deleted_document(ctx.clone(), doc));
+    } else if ctx.args.compute.is_some() {
+        spawn_task(ctx, original, process_computed_document(ctx.clone(), doc));
+    } else if ctx.args.transform.is_some() || ctx.args.replace_value.is_some() {
+        spawn_task(ctx, original, transform_document(ctx.clone(), doc));
+    } else if ctx.args.delete_doc_when_equals.is_some() {
+        spawn_task(ctx, original, soft_delete_document(ctx.clone(), doc));
+    } else {
+        spawn_task(ctx, original, process_document(ctx.clone(), doc));
+    }
+}
+
+/// Processes a document of the table scan: right away, or once it is fetched on its own if the
+/// scan only returned its ID and revision (`--include-docs false`).
+fn spawn_scanned(ctx: &Arc<Context>, doc: Value) {
+    if ctx.args.include_docs {
+        spawn_processing(ctx, doc);
+        return;
+    }
+    let task = tokio::spawn(fetch_and_process(ctx.clone(), doc));
+    ctx.tasks.lock().unwrap().push(task);
+}
+
+/// Fetches the whole document of a scanned ID and revision, then processes it.
+async fn fetch_and_process(ctx: Arc<Context>, stub: Value) {
+    if ctx.stop.load(Ordering::Relaxed) {
+        return;
+    }
+    let Some(id) = stub[&ctx.args.id_field].as_str() else {
+        ctx.malformed_count.fetch_add(1, Ordering::Relaxed);
+        ctx.log.warn(format!(
+            "\tData anomaly: skipped a scanned row without an ID: {}",
+            preview(&stub)
+        ));
+        return;
+    };
+
+    let fetched = ctx
+        .read_slots
+        .run(fetch_document_by_id(
+            &ctx.client,
+            &ctx.args.db_url,
+            &ctx.args.table_name,
+            id,
+            ctx.args.raw_id,
+            ctx.args.read_quorum,
+            ctx.auth.as_ref(),
+        ))
+        .await;
+    match fetched {
+        Ok(Some(doc)) => spawn_processing(&ctx, doc),
+        Ok(None) => ctx.log.warn(format!(
+            "\tdocument ID {} was deleted after the scan; skipped.",
+            id
+        )),
+        Err(err) => {
+            ctx.error_count.fetch_add(1, Ordering::Relaxed);
+            ctx.log
+                .error(format!("\tError fetching document {}: {}", id, err));
+        }
+    }
+}
+
+/// Spawns a processing task, awaited before the final report, with the document as fetched in
+/// dry-run. With `--batch-report`, the task is attributed to the current batch and awaited by its report.
+fn spawn_task(
+    ctx: &Arc<Context>,
+    original: Option<Value>,
+    task: impl Future<Output = ()> + Send + 'static,
+) {
+    let task = ORIGINAL.scope(original, task);
+    if !ctx.args.batch_report {
+        ctx.tasks.lock().unwrap().push(tokio::spawn(task));
+        return;
+    }
+
+    let batch = ctx.batch.lock().unwrap().clone();
+    batch.first_task.get_or_init(Instant::now);
+    let task = tokio::spawn(BATCH.scope(batch, task));
+    ctx.batch_tasks.lock().unwrap().push(task);
+}
+
+/// Awaits every processing task, including those of a batch not yet reported, counting the
+/// tasks that panicked. Batch reports are tasks too, so the loop runs until none is left.
+async fn join_tasks(ctx: &Context) {
+    loop {
+        let mut tasks = std::mem::take(&mut *ctx.tasks.lock().unwrap());
+        tasks.append(&mut ctx.batch_tasks.lock().unwrap());
+        if tasks.is_empty() {
+            return;
+        }
+        for task in tasks {
+            if task.await.is_err() {
+                ctx.failed_task_count.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Counts an outcome in the statistics of the current task's batch, with `--batch-report`.
+fn count_in_batch(counter: fn(&BatchStats) -> &AtomicUsize) {
+    let _ = BATCH.try_with(|batch| counter(batch).fetch_add(1, Ordering::Relaxed));
+}
+
+/// Prints the statistics of a batch once all its processing tasks are done (`--batch-report`).
+/// The write time runs from the start of the batch's first task to the end of its last one.
+fn report_batch(ctx: &Arc<Context>, info: BatchInfo) {
+    let batch = std::mem::take(&mut *ctx.batch.lock().unwrap());
+    let tasks = std::mem::take(&mut *ctx.batch_tasks.lock().unwrap());
+    let report_ctx = ctx.clone();
+
+    let report = tokio::spawn(async move {
+        for task in tasks {
+            if task.await.is_err() {
+                report_ctx.failed_task_count.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        let changed = batch.changed.load(Ordering::Relaxed);
+        let failed = batch.failed.load(Ordering::Relaxed);
+        let write_ms = batch
+            .first_task
+            .get()
+            .map_or(0, |started| started.elapsed().as_millis());
+        report_ctx.log.info(format!(
+            "Batch {}: fetched {}, changed {}, failed {}, skipped {}, fetch {} ms, writes {} ms",
+            info.iteration,
+            info.documents,
+            changed,
+            failed,
+            info.documents.saturating_sub(changed + failed),
+            info.fetch_duration.as_millis(),
+            write_ms
+        ));
+    });
+    ctx.tasks.lock().unwrap().push(report);
+}
+
+/// Renders a value as compact JSON for a log line, truncated to a readable length.
+fn preview(value: &Value) -> String {
+    const MAX_CHARS: usize = 80;
+
+    let json = value.to_string();
+    match json.char_indices().nth(MAX_CHARS) {
+        Some((end, _)) => format!("{}...", &json[..end]),
+        None => json,
+    }
+}
+
+/// Writes the changed document IDs to a file, one per line.
+fn write_changed_ids(path: &str, ids: &BTreeSet<String>) -> Result<(), String> {
+    let mut content = String::new();
+    for id in ids {
+        content.push_str(id);
+        content.push('\n');
+    }
+    std::fs::write(path, content)
+        .map_err(|e| format!("Failed to write changed IDs to '{}': {}", path, e))
+}
+
+/// Fetches and processes each document listed in an IDs file (one `_id` per line).
+/// IDs that do not exist are reported separately from documents lacking the old field.
+async fn process_ids_file(ctx: &Arc<Context>, ids_file: &str) -> Result<FetchSummary, String> {
+    let started = Instant::now();
+    let content = std::fs::read_to_string(ids_file)
+        .map_err(|e| format!("Failed to read IDs file '{}': {}", ids_file, e))?;
+
+    // Skip blank lines and `#` comments
+    let mut ids: Vec<&str> = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect();
+
+    // Only examine the first IDs when the dry run is capped
+    if let Some(max) = ctx.args.dry_run_limit {
+        ids.truncate(max);
+    }
+    ctx.log.info(format!(
+        "Processing {} document IDs from '{}'.",
+        ids.len(),
+        ids_file
+    ));
+
+    let mut found = 0; // Number of documents fetched successfully
+    let mut not_found = Vec::new(); // IDs that returned 404
+
+    for id in &ids {
+        if ctx.stop.load(Ordering::Relaxed) {
+            break;
+        }
+        match fetch_document_by_id(
+            &ctx.client,
+            &ctx.args.db_url,
+            &ctx.args.table_name,
+            id,
+            ctx.args.raw_id,
+            ctx.args.read_quorum,
+            ctx.auth.as_ref(),
+        )
+        .await
+        {
+            Ok(Some(doc)) => {
+                found += 1;
+                spawn_processing(ctx, doc);
+            }
+            Ok(None) => not_found.push(*id),
+            Err(err) => ctx
+                .log
+                .error(format!("\tError fetching document {}: {}", id, err)),
+        }
+    }
+
+    if !not_found.is_empty() {
+        let mut log = ctx.log.document();
+        log.info(format!("{} document IDs were not found:", not_found.len()));
+        for id in &not_found {
+            log.info(format!("\t{}", id));
+        }
+    }
+
+    Ok(FetchSummary {
+        table_name: ctx.args.table_name.clone(),
+        doc_count: ids.len(),
+        total_fetched: found,
+        iterations: 1,
+        duration_secs: started.elapsed().as_secs_f64(),
+        matched: 0,
+        written: 0,
+        failed: 0,
+    })
+}
+
+/// Runs every document of `--input-file` through the processing tasks, as a single batch.
+async fn process_input_file(ctx: &Arc<Context>, path: &str) -> Result<FetchSummary, String> {
+    let started = Instant::now();
+    let mut source = FileSource::open(path)?;
+    let doc_count = source.len();
+
+    let callback_ctx = ctx.clone();
+    let summary = Feed::new(Box::new(move |doc: Value| {
+        spawn_processing(&callback_ctx, doc)
+    }))
+    .with_doc_count(doc_count)
+    .run(&mut source)
+    .await?;
+
+    Ok(FetchSummary {
+        table_name: path.to_string(),
+        doc_count,
+        duration_secs: started.elapsed().as_secs_f64(),
+        ..summary
+    })
+}
+
+/// Writes the documents of `--input-file` to `--output-file`, in their original order, each
+/// replaced with its updated version if it was changed. Does nothing without `--output-file` or
+/// in dry-run mode.
+fn write_output_file(ctx: &Context) -> Result<(), String> {
+    let (Some(input_file), Some(output_file)) = (&ctx.args.input_file, &ctx.args.output_file)
+    else {
+        return Ok(());
+    };
+    if ctx.args.dry_run {
+        return Ok(());
+    }
+
+    let mut updates = ctx.file_updates.lock().unwrap();
+    let documents: Vec<Value> = refield::source::read_documents(input_file)?
+        .into_iter()
+        .map(|doc| {
+            doc[&ctx.args.id_field]
+                .as_str()
+                .and_then(|id| updates.remove(id))
+                .unwrap_or(doc)
+        })
+        .collect();
+    refield::source::write_documents(output_file, &documents)?;
+    ctx.log.info(format!(
+        "Wrote {} documents to '{}'.",
+        documents.len(),
+        output_file
+    ));
+    Ok(())
+}
+
+/// Splits the table into `--workers` `_id` ranges and scans them concurrently, each on its own task,
+/// combining their summaries.
+async fn process_shards(ctx: &Arc<Context>) -> Result<FetchSummary, String> {
+    let started = Instant::now();
+    let ranges = refield::fetch::shard_id_ranges(
+        &ctx.client,
+        &ctx.args.db_url,
+        &ctx.args.table_name,
+        ctx.args.workers,
+        ctx.auth.as_ref(),
+    )
+    .await?;
+    // List the shards as one group, before they start logging their progress
+    let shard_count = ranges.len();
+    let mut log = ctx.log.document();
+    log.info(format!("Scanning {} shards concurrently.", shard_count));
+    for (index, range) in ranges.iter().enumerate() {
+        log.info(format!(
+            "\tShard {}/{}: {}",
+            index + 1,
+            shard_count,
+            describe_range(range)
+        ));
+    }
+    drop(log);
+
+    let shards: Vec<JoinHandle<Result<FetchSummary, String>>> = ranges
+        .into_iter()
+        .enumerate()
+        .map(|(index, range)| {
+            let callback_ctx = ctx.clone();
+            let fd = new_fetcher(ctx)
+                .with_id_range(range)
+                .with_label(format!("shard {}/{}", index + 1, shard_count))
+                .with_callback(Box::new(move |doc: Value| {
+                    spawn_scanned(&callback_ctx, doc)
+                }));
+            // The events of the scan carry the shard it belongs to
+            let span = tracing::info_span!("shard", index = index + 1, count = shard_count);
+            tokio::spawn(fd.execute().instrument(span))
+        })
+        .collect();
+
+    let mut summary = FetchSummary {
+        table_name: ctx.args.table_name.clone(),
+        doc_count: 0,
+        total_fetched: 0,
+        iterations: 0,
+        duration_secs: 0.0,
+        matched: 0,
+        written: 0,
+        failed: 0,
+    };
+    for shard in shards {
+        let shard = shard
+            .await
+            .map_err(|e| format!("A shard failed: {}", e))??;
+        summary.doc_count = shard.doc_count; // Every shard reports the whole table
+        summary.total_fetched += shard.total_fetched;
+        summary.iterations += shard.iterations;
+        summary.matched += shard.matched;
+        summary.written += shard.written;
+        summary.failed += shard.failed;
+    }
+    summary.duration_secs = started.elapsed().as_secs_f64();
+    Ok(summary)
+}
+
+/// Describes an `_id` range for the log, e.g. `["a", "m")`.
+fn describe_range(range: &IdRange) -> String {
+    format!(
+        "[{}, {})",
+        range
+            .start
+            .as_deref()
+            .map_or("start".to_string(), |id| format!("{:?}", id)),
+        range
+            .end
+            .as_deref()
+            .map_or("end".to_string(), |id| format!("{:?}", id))
+    )
+}
+
+/// Used as a callback to process a single document fetched from the database.
+async fn process_document(ctx: Arc<Context>, mut doc: Value) {
+    let args = &ctx.args;
+    ctx.processed_count.fetch_add(1, Ordering::Relaxed);
+    let new_field = args.new_field.as_deref().unwrap_or_default();
+    let id = doc[&args.id_field].as_str().unwrap_or("<unknown>");
+    let idclone = id.to_string();
+    let mut log = ctx.log.document();
+
+    // Convert the old field paths into slices of string slices for processing
+    let old_field_paths: Vec<Vec<&str>> = ctx
+        .old_field_paths
+        .iter()
+        .map(|path| path.iter().map(|s| s.as_str()).collect())
+        .collect();
+    let candidates: Vec<&[&str]> = old_field_paths.iter().map(|p| p.as_slice()).collect();
+
+    // Attempt to rename the first old field present in the document
+    let untouched = AtomicUsize::new(0);
+    let before = before_rename(&ctx, &doc);
+    let matched = refield::rename::rename_first_match_with_transform(
+        &mut doc,
+        &candidates,
+        new_field,
+        &ctx.rename_options,
+        &|value| transform_value(&ctx, value, &untouched),
+    );
+    report_untouched_values(&ctx, &mut log, &idclone, &untouched);
+
+    record_field_presence(&ctx, matched.is_some());
+
+    if let Some((index, stats)) = matched {
+        ctx.matched_counts[index].fetch_add(1, Ordering::Relaxed);
+
+        // Refused merges leave the document for manual review
+        if stats.conflicts > 0 {
+            ctx.merge_conflict_count.fetch_add(1, Ordering::Relaxed);
+            log.warn(format!(
+                "\tMerge conflict in document ID {}: '{}' and '{}' share keys; skipped.",
+                idclone, args.old_fields[index], new_field
+            ));
+            audit(&ctx, &doc, &idclone, Outcome::Conflict);
+            return;
+        }
+
+        // Coexisting case variants leave the document for manual review
+        if stats.ambiguous > 0 {
+            ctx.ambiguous_count.fetch_add(1, Ordering::Relaxed);
+            log.warn(format!(
+                "\tAmbiguous field in document ID {}: several case variants of '{}' exist; skipped.",
+                idclone, args.old_fields[index]
+            ));
+            audit(&ctx, &doc, &idclone, Outcome::Ambiguous);
+            return;
+        }
+
+        // An existing new field leaves the document untouched, with `--on-conflict skip|error`
+        if stats.collisions > 0 {
+            report_collision(&ctx, &mut log, &doc, &idclone, new_field);
+            return;
+        }
+        if stats.renamed > 0 {
+            ctx.renamed_count.fetch_add(1, Ordering::Relaxed);
+        }
+        if stats.merged > 0 {
+            ctx.merged_count.fetch_add(1, Ordering::Relaxed);
+        }
+        if stats.backups > 0 {
+            report_backup(&ctx, &mut log, &idclone);
+        }
+
+        drop_empty_field(&mut doc, before.as_ref(), new_field);
+        delete_other_candidates(&ctx, &mut doc, &candidates, index);
+
+        save_document(&ctx, &mut log, &mut doc, &idclone).await;
+    } else {
+        // Field not found in the document
+        log.warn(format!(
+            "\tfield '{}' not found in document ID: {}",
+            args.old_fields.join("' | '"),
+            idclone
+        ));
+        audit(&ctx, &doc, &idclone, Outcome::Missing);
+    }
+}
+
+/// Feeds the `--stop-on-missing-ratio` safety valve with whether a document had the old field,
+/// stopping the scan if too many of the first documents lacked it.
+fn record_field_presence(ctx: &Context, found: bool) {
+    let Some(guard) = &ctx.missing_guard else {
+        return;
+    };
+
+    if let Some(ratio) = guard.lock().unwrap().record(found) {
+        ctx.stop.store(true, Ordering::Relaxed);
+        ctx.log.error(format!(
+            "{:.1}% of the first {} documents lack the old field; stopping the scan. Check the field path.",
+            ratio * 100.0,
+            ctx.args.missing_window
+        ));
+    }
+}
+
+/// Used as a callback to apply every mapping rule to a single document in one pass.
+async fn process_mapping_document(ctx: Arc<Context>, mut doc: Value) {
+    ctx.processed_count.fetch_add(1, Ordering::Relaxed);
+    let id = doc[&ctx.args.id_field]
+        .as_str()
+        .unwrap_or("<unknown>")
+        .to_string();
+
+    let mut log = ctx.log.document();
+    let mut changed = false;
+    let mut backed_up = false;
+    let untouched = AtomicUsize::new(0);
+    for (rule, count) in ctx.mapping_rules.iter().zip(&ctx.rule_match_counts) {
+        let old_path = refield::rename::split_path(&rule.old_field);
+        let before = before_rename(&ctx, &doc);
+        let stats = refield::rename::rename_nested_field_with_transform(
+            &mut doc,
+            &old_path,
+            &rule.new_field,
+            &ctx.rename_options,
+            &|value| transform_value(&ctx, value, &untouched),
+        );
+
+        // Refused merges leave the whole document for manual review
+        if stats.conflicts > 0 {
+            ctx.merge_conflict_count.fetch_add(1, Ordering::Relaxed);
+            log.warn(format!(
+                "\tMerge conflict in document ID {}: '{}' and '{}' share keys; skipped.",
+                id, rule.old_field, rule.new_field
+            ));
+            audit(&ctx, &doc, &id, Outcome::Conflict);
+            record_field_presence(&ctx, true);
+            return;
+        }
+        if stats.ambiguous > 0 {
+            ctx.ambiguous_count.fetch_add(1, Ordering::Relaxed);
+            log.warn(format!(
+                "\tAmbiguous field in document ID {}: several case variants of '{}' exist; skipped.",
+                id, rule.old_field
+            ));
+            audit(&ctx, &doc, &id, Outcome::Ambiguous);
+            record_field_presence(&ctx, true);
+            return;
+        }
+        if stats.collisions > 0 {
+            report_collision(&ctx, &mut log, &doc, &id, &rule.new_field);
+            record_field_presence(&ctx, true);
+            return;
+        }
+        if stats.changed() {
+            count.fetch_add(1, Ordering::Relaxed);
+            changed = true;
+            drop_empty_field(&mut doc, before.as_ref(), &rule.new_field);
+        }
+        backed_up |= stats.backups > 0;
+    }
+    record_field_presence(&ctx, changed);
+
+    report_untouched_values(&ctx, &mut log, &id, &untouched);
+
+    if changed && backed_up {
+        report_backup(&ctx, &mut log, &id);
+    }
+    if changed {
+        save_document(&ctx, &mut log, &mut doc, &id).await;
+    } else {
+        log.warn(format!("\tno mapped field found in document ID: {}", id));
+        audit(&ctx, &doc, &id, Outcome::Missing);
+    }
+}
+
+/// With `--delete-others`, deletes the candidate old fields other than the one renamed (`index`),
+/// taking care not to delete the freshly renamed field.
+fn delete_other_candidates(ctx: &Context, doc: &mut Value, candidates: &[&[&str]], index: usize) {
+    if !ctx.args.delete_others {
+        return;
+    }
+
+    let new_field = ctx.args.new_field.as_deref().unwrap_or_default();
+    for (i, candidate) in candidates.iter().enumerate() {
+        if i != index && ctx.args.old_fields[i] != new_field {
+            refield::rename::delete_nested_field(doc, candidate);
+        }
+    }
+}
+
+/// The key renamed wherever it occurs (`--recursive-any`), or the pattern of those keys (`--regex`).
+fn anywhere_key(args: &Args) -> Option<&str> {
+    match (&args.recursive_any, &args.key_pattern) {
+        (Some(old_key), _) => Some(old_key),
+        (None, Some(pattern)) => Some(pattern.as_str()),
+        (None, None) => None,
+    }
+}
+
+/// Renames every occurrence of the `--recursive-any` key, or of the keys matching the `--regex`
+/// pattern, at any depth of the document.
+fn rename_anywhere(
+    ctx: &Context,
+    doc: &mut Value,
+    value_fn: &dyn Fn(Value) -> Value,
+) -> RenameStats {
+    let new_key = ctx.args.new_field.as_deref().unwrap_or_default();
+    match (&ctx.args.recursive_any, &ctx.args.key_pattern) {
+        (Some(old_key), _) => {
+            rename_key_anywhere(doc, old_key, new_key, &ctx.rename_options, value_fn)
+        }
+        (None, Some(pattern)) => {
+            rename_matching_fields(doc, pattern, new_key, &ctx.rename_options, value_fn)
+        }
+        (None, None) => RenameStats::default(),
+    }
+}
+
+/// Used as a callback to rename every occurrence of the `--recursive-any` key, or of the keys
+/// matching the `--regex` pattern, at any depth.
+async fn process_recursive_document(ctx: Arc<Context>, mut doc: Value) {
+    let Some(old_key) = anywhere_key(&ctx.args) else {
+        return;
+    };
+    ctx.processed_count.fetch_add(1, Ordering::Relaxed);
+    let new_key = ctx.args.new_field.as_deref().unwrap_or_default();
+    let id = doc[&ctx.args.id_field]
+        .as_str()
+        .unwrap_or("<unknown>")
+        .to_string();
+    let mut log = ctx.log.document();
+
+    let untouched = AtomicUsize::new(0);
+    let stats = rename_anywhere(&ctx, &mut doc, &|value| {
+        transform_value(&ctx, value, &untouched)
+    });
+    report_untouched_values(&ctx, &mut log, &id, &untouched);
+    record_field_presence(&ctx, stats.found());
+
+    // Refused merges and ambiguous case variants leave the whole document for manual review
+    if stats.conflicts > 0 {
+        ctx.merge_conflict_count.fetch_add(1, Ordering::Relaxed);
+        log.warn(format!(
+            "\tMerge conflict in document ID {}: '{}' and '{}' share keys; skipped.",
+            id, old_key, new_key
+        ));
+        audit(&ctx, &doc, &id, Outcome::Conflict);
+        return;
+    }
+    if stats.ambiguous > 0 {
+        ctx.ambiguous_count.fetch_add(1, Ordering::Relaxed);
+        log.warn(format!(
+            "\tAmbiguous field in document ID {}: several case variants of '{}' exist; skipped.",
+            id, old_key
+        ));
+        audit(&ctx, &doc, &id, Outcome::Ambiguous);
+        return;
+    }
+    if stats.collisions > 0 {
+        report_collision(&ctx, &mut log, &doc, &id, new_key);
+        return;
+    }
+    if !stats.changed() {
+        log.warn(format!(
+            "\tfield '{}' not found in document ID: {}",
+            old_key, id
+        ));
+        audit(&ctx, &doc, &id, Outcome::Missing);
+        return;
+    }
+
+    if stats.renamed > 0 {
+        ctx.renamed_count.fetch_add(1, Ordering::Relaxed);
+    }
+    if stats.merged > 0 {
+        ctx.merged_count.fetch_add(1, Ordering::Relaxed);
+    }
+    if stats.backups > 0 {
+        report_backup(&ctx, &mut log, &id);
+    }
+    ctx.occurrence_count
+        .fetch_add(stats.renamed + stats.merged, Ordering::Relaxed);
+    log.info(format!(
+        "\trenamed {} occurrences of '{}' in document ID: {}",
+        stats.renamed + stats.merged,
+        old_key,
+        id
+    ));
+
+    save_document(&ctx, &mut log, &mut doc, &id).await;
+}
+
+/// Used as a callback to move the old field up to the new path (`--promote`).
+async fn process_promoted_document(ctx: Arc<Context>, mut doc: Value) {
+    ctx.processed_count.fetch_add(1, Ordering::Relaxed);
+    let old_field = &ctx.args.old_fields[0];
+    let new_field = ctx.args.new_field.as_deref().unwrap_or_default();
+    let id = doc[&ctx.args.id_field]
+        .as_str()
+        .unwrap_or("<unknown>")
+        .to_string();
+    let mut log = ctx.log.document();
+
+    let old_path: Vec<&str> = ctx.old_field_paths[0].iter().map(|s| s.as_str()).collect();
+    let new_path: Vec<&str> = new_field.split('.').collect();
+    let stats =
+        refield::rename::promote_nested_field(&mut doc, &old_path, &new_path, ctx.args.prune_empty);
+    record_field_presence(&ctx, stats.promoted + stats.conflicts > 0);
+
+    // An existing destination leaves the whole document for manual review
+    if stats.conflicts > 0 {
+        ctx.promote_conflict_count.fetch_add(1, Ordering::Relaxed);
+        log.warn(format!(
+            "\tCannot promote '{}' in document ID {}: '{}' already exists; skipped.",
+            old_field, id, new_field
+        ));
+        audit(&ctx, &doc, &id, Outcome::Conflict);
+        return;
+    }
+    if stats.promoted == 0 {
+        log.warn(format!(
+            "\tfield '{}' not found in document ID: {}",
+            old_field, id
+        ));
+        audit(&ctx, &doc, &id, Outcome::Missing);
+        return;
+    }
+
+    ctx.promoted_count
+        .fetch_add(stats.promoted, Ordering::Relaxed);
+    ctx.pruned_count.fetch_add(stats.pruned, Ordering::Relaxed);
+    save_document(&ctx, &mut log, &mut doc, &id).await;
+}
+
+/// Used as a callback to move the old field to the new path, under any parent (`--move`).
+async fn process_moved_document(ctx: Arc<Context>, mut doc: Value) {
+    ctx.processed_count.fetch_add(1, Ordering::Relaxed);
+    let old_field = &ctx.args.old_fields[0];
+    let new_field = ctx.args.new_field.as_deref().unwrap_or_default();
+    let id = doc[&ctx.args.id_field]
+        .as_str()
+        .unwrap_or("<unknown>")
+        .to_string();
+    let mut log = ctx.log.document();
+
+    let old_path: Vec<&str> = ctx.old_field_paths[0].iter().map(|s| s.as_str()).collect();
+    let found = !refield::rename::find_nested_values(&doc, &old_path).is_empty();
+    record_field_presence(&ctx, found);
+
+    let stats = move_old_field(&ctx, &mut doc);
+    if stats.collisions > 0 {
+        // An existing destination leaves the document untouched, with `--on-conflict skip|error`
+        report_collision(&ctx, &mut log, &doc, &id, new_field);
+    } else if stats.changed() {
+        log.info(format!(
+            "\tmoved '{}' to '{}' in document ID: {}",
+            old_field, new_field, id
+        ));
+        save_document(&ctx, &mut log, &mut doc, &id).await;
+    } else if found {
+        log.warn(format!(
+            "\tCannot move '{}' in document ID {}: a field on the way to '{}' is not an object; skipped.",
+            old_field, id, new_field
+        ));
+        audit(&ctx, &doc, &id, Outcome::Conflict);
+    } else {
+        log.warn(format!(
+            "\tfield '{}' not found in document ID: {}",
+            old_field, id
+        ));
+        audit(&ctx, &doc, &id, Outcome::Missing);
+    }
+}
+
+/// Moves the old field to the `--move` destination, following `--on-conflict`.
+fn move_old_field(ctx: &Context, doc: &mut Value) -> RenameStats {
+    let old_path: Vec<&str> = ctx.old_field_paths[0].iter().map(|s| s.as_str()).collect();
+    let new_field = ctx.args.new_field.as_deref().unwrap_or_default();
+    let new_path: Vec<&str> = new_field.split('.').collect();
+    refield::rename::move_nested_field(doc, &old_path, &new_path, ctx.args.on_conflict)
+}
+
+/// Used as a callback to copy the old field to the new name, keeping the original (`--copy`).
+async fn process_copied_document(ctx: Arc<Context>, mut doc: Value) {
+    ctx.processed_count.fetch_add(1, Ordering::Relaxed);
+    let old_field = &ctx.args.old_fields[0];
+    let new_field = ctx.args.new_field.as_deref().unwrap_or_default();
+    let id = doc[&ctx.args.id_field]
+        .as_str()
+        .unwrap_or("<unknown>")
+        .to_string();
+    let mut log = ctx.log.document();
+
+    let old_path: Vec<&str> = ctx.old_field_paths[0].iter().map(|s| s.as_str()).collect();
+    let found = !refield::rename::find_nested_values(&doc, &old_path).is_empty();
+    record_field_presence(&ctx, found);
+
+    let stats =
+        refield::rename::copy_nested_field(&mut doc, &old_path, new_field, ctx.args.on_conflict);
+    if stats.collisions > 0 {
+        // A new field holding another value leaves the document untouched, with `--on-conflict`
+        report_collision(&ctx, &mut log, &doc, &id, new_field);
+    } else if stats.changed() {
+        log.info(format!(
+            "\tcopied '{}' to '{}' in document ID: {}",
+            old_field, new_field, id
+        ));
+        save_document(&ctx, &mut log, &mut doc, &id).await;
+    } else if found {
+        log.info(format!(
+            "\tfield '{}' already up to date in document ID: {}",
+            new_field, id
+        ));
+        audit(&ctx, &doc, &id, Outcome::Unchanged);
+    } else {
+        log.warn(format!(
+            "\tfield '{}' not found in document ID: {}",
+            old_field, id
+        ));
+        audit(&ctx, &doc, &id, Outcome::Missing);
+    }
+}
+
+/// Used as a callback to delete the old fields from the document (`--delete`).
+async fn process_deleted_document(ctx: Arc<Context>, mut doc: Value) {
+    ctx.processed_count.fetch_add(1, Ordering::Relaxed);
+    let id = doc[&ctx.args.id_field]
+        .as_str()
+        .unwrap_or("<unknown>")
+        .to_string();
+    let mut log = ctx.log.document();
+
+    let deleted = delete_old_fields(&ctx, &mut doc);
+    record_field_presence(&ctx, deleted);
+
+    if deleted {
+        ctx.deleted_field_count.fetch_add(1, Ordering::Relaxed);
+        log.info(format!(
+            "\tdeleted '{}' from document ID: {}",
+            ctx.args.old_fields.join("', '"),
+            id
+        ));
+        save_document(&ctx, &mut log, &mut doc, &id).await;
+    } else {
+        log.warn(format!(
+            "\tfield '{}' not found in document ID: {}",
+            ctx.args.old_fields.join("' | '"),
+            id
+        ));
+        audit(&ctx, &doc, &id, Outcome::Missing);
+    }
+}
+
+/// Deletes every old field from a document, returning whether any was present.
+fn delete_old_fields(ctx: &Context, doc: &mut Value) -> bool {
+    ctx.old_field_paths.iter().fold(false, |deleted, path| {
+        let path: Vec<&str> = path.iter().map(|s| s.as_str()).collect();
+        refield::rename::delete_nested_field(doc, &path) | deleted
+    })
+}
+
+/// Used as a callback to set the `--compute` field from the document's other fields.
+async fn process_computed_document(ctx: Arc<Context>, mut doc: Value) {
+    let Some(compute) = &ctx.args.compute else {
+        return;
+    };
+    ctx.processed_count.fetch_add(1, Ordering::Relaxed);
+    let id = doc[&ctx.args.id_field]
+        .as_str()
+        .unwrap_or("<unknown>")
+        .to_string();
+    let mut log = ctx.log.document();
+
+    let result = compute.apply(&mut doc);
+    record_field_presence(&ctx, !matches!(result, Err(ComputeError::Missing(_))));
+
+    let changed = match result {
+        Ok(changed) => changed,
+        Err(ComputeError::Missing(fields)) => {
+            ctx.compute_missing_count.fetch_add(1, Ordering::Relaxed);
+            log.warn(format!(
+                "\tfield '{}' not found in document ID: {}",
+                fields.join("', '"),
+                id
+            ));
+            audit(&ctx, &doc, &id, Outcome::Missing);
+            return;
+        }
+        Err(ComputeError::Blocked(parent)) => {
+            ctx.compute_missing_count.fetch_add(1, Ordering::Relaxed);
+            log.warn(format!(
+                "\tCannot set '{}' in document ID {}: '{}' is not an object; skipped.",
+                compute.target(),
+                id,
+                parent
+            ));
+            audit(&ctx, &doc, &id, Outcome::Conflict);
+            return;
+        }
+    };
+
+    let deleted = delete_compute_sources(&ctx, compute, &mut doc);
+    if changed || deleted {
+        ctx.computed_count.fetch_add(1, Ordering::Relaxed);
+        save_document(&ctx, &mut log, &mut doc, &id).await;
+    } else {
+        log.info(format!(
+            "\tfield '{}' already up to date in document ID: {}",
+            compute.target(),
+            id
+        ));
+        audit(&ctx, &doc, &id, Outcome::Unchanged);
+    }
+}
+
+/// With `--delete-sources`, deletes the fields the computed value was built from once the target
+/// holds it, except the target itself. Returns whether any was deleted.
+fn delete_compute_sources(ctx: &Context, compute: &ComputeTemplate, doc: &mut Value) -> bool {
+    let mut deleted = false;
+    if ctx.args.delete_sources {
+        for field in compute.fields() {
+            if field != compute.target() {
+                let path: Vec<&str> = field.split('.').collect();
+                deleted |= refield::rename::delete_nested_field(doc, &path);
+            }
+        }
+    }
+    deleted
+}
+
+/// Used as a callback to transform (`--transform`) or replace (`--replace-value`) the values
+/// of the old fields in place, without renaming them.
+async fn transform_document(ctx: Arc<Context>, mut doc: Value) {
+    ctx.processed_count.fetch_add(1, Ordering::Relaxed);
+    let id = doc[&ctx.args.id_field]
+        .as_str()
+        .unwrap_or("<unknown>")
+        .to_string();
+    let mut log = ctx.log.document();
+
+    let (found, changed) = transform_values(&ctx, &mut doc);
+    record_field_presence(&ctx, found);
+
+    if changed > 0 {
+        ctx.transformed_value_count
+            .fetch_add(changed, Ordering::Relaxed);
+        save_document(&ctx, &mut log, &mut doc, &id).await;
+    } else {
+        log.warn(format!("\tno value to transform in document ID: {}", id));
+        audit(&ctx, &doc, &id, Outcome::Missing);
+    }
+}
+
+/// Transforms (`--transform`) or replaces (`--replace-value`) the values of the old fields in place.
+/// Values the transform does not apply to (e.g. non-strings) or not matching the replacement
+/// are kept as they are. Returns whether any old field was found, and the number of values changed.
+fn transform_values(ctx: &Context, doc: &mut Value) -> (bool, usize) {
+    let value_fn = |value: Value| -> Value {
+        let result = match (&ctx.args.transform, &ctx.args.replace_value) {
+            (Some(transform), _) => transform.apply(value),
+            (None, Some(replacement)) => replacement.apply(value),
+            (None, None) => Err(value),
+        };
+        result.unwrap_or_else(|value| value)
+    };
+
+    let mut found = false;
+    let mut changed = 0;
+    for path in &ctx.old_field_paths {
+        let path: Vec<&str> = path.iter().map(|s| s.as_str()).collect();
+        found |= !refield::rename::find_nested_values(doc, &path).is_empty();
+        changed += refield::rename::transform_nested_field(doc, &path, &value_fn);
+    }
+    (found, changed)
+}
+
+/// Re-applies the change of the selected mode to the latest version of a document whose update
+/// was rejected with a revision conflict (see `write_resolving_conflicts`).
+/// Returns whether the latest version still needs writing: not if it already holds the change,
+/// no longer satisfies `--when`, or would now be skipped (e.g. because of a merge conflict).
+fn reapply_change(ctx: &Context, doc: &mut Value) -> bool {
+    if ctx
+        .args
+        .when
+        .as_ref()
+        .is_some_and(|when| !when.matches(doc))
+    {
+        return false;
+    }
+
+    let args = &ctx.args;
+    let new_field = args.new_field.as_deref().unwrap_or_default();
+    let latest = args.prune_empty.then(|| doc.clone());
+    let untouched = AtomicUsize::new(0);
+    let value_fn = |value| transform_value(ctx, value, &untouched);
+    let changed = if !ctx.mapping_rules.is_empty() {
+        let mut changed = false;
+        for rule in &ctx.mapping_rules {
+            let old_path = refield::rename::split_path(&rule.old_field);
+            let before = before_rename(ctx, doc);
+            let stats = refield::rename::rename_nested_field_with_transform(
+                doc,
+                &old_path,
+                &rule.new_field,
+                &ctx.rename_options,
+                &value_fn,
+            );
+            if stats.conflicts + stats.ambiguous + stats.collisions > 0 {
+                return false;
+            }
+            if stats.changed() {
+                changed = true;
+                drop_empty_field(doc, before.as_ref(), &rule.new_field);
+            }
+        }
+        changed
+    } else if anywhere_key(args).is_some() {
+        let stats = rename_anywhere(ctx, doc, &value_fn);
+        stats.conflicts + stats.ambiguous + stats.collisions == 0 && stats.changed()
+    } else if args.promote {
+        let old_path: Vec<&str> = ctx.old_field_paths[0].iter().map(|s| s.as_str()).collect();
+        let new_path: Vec<&str> = new_field.split('.').collect();
+        let stats =
+            refield::rename::promote_nested_field(doc, &old_path, &new_path, args.prune_empty);
+        stats.conflicts == 0 && stats.promoted > 0
+    } else if args.move_field {
+        let stats = move_old_field(ctx, doc);
+        stats.collisions == 0 && stats.changed()
+    } else if args.delete {
+        delete_old_fields(ctx, doc)
+    } else if args.copy {
+        let old_path: Vec<&str> = ctx.old_field_paths[0].iter().map(|s| s.as_str()).collect();
+        let stats = refield::rename::copy_nested_field(doc, &old_path, new_field, args.on_conflict);
+        stats.collisions == 0 && stats.changed()
+    } else if let Some(compute) = &args.compute {
+        match compute.apply(doc) {
+            Ok(changed) => delete_compute_sources(ctx, compute, doc) || changed,
+            Err(_) => false,
+        }
+    } else if args.transform.is_some() || args.replace_value.is_some() {
+        transform_values(ctx, doc).1 > 0
+    } else {
+        let old_field_paths: Vec<Vec<&str>> = ctx
+            .old_field_paths
+            .iter()
+            .map(|path| path.iter().map(|s| s.as_str()).collect())
+            .collect();
+        let candidates: Vec<&[&str]> = old_field_paths.iter().map(|p| p.as_slice()).collect();
+        let before = before_rename(ctx, doc);
+        match refield::rename::rename_first_match_with_transform(
+            doc,
+            &candidates,
+            new_field,
+            &ctx.rename_options,
+            &value_fn,
+        ) {
+            Some((index, stats)) if stats.conflicts + stats.ambiguous + stats.collisions == 0 => {
+                drop_empty_field(doc, before.as_ref(), new_field);
+                delete_other_candidates(ctx, doc, &candidates, index);
+                true
+            }
+            _ => false,
+        }
+    };
+
+    if let (true, Some(latest)) = (changed, &latest) {
+        refield::rename::prune_empty(doc, latest);
+    }
+    changed
+}
+
+/// Applies the configured value transform to a value moved by a rename, then the `--on-empty` policy.
+/// Values the transform does not apply to are kept as they are and counted in `untouched`.
+fn transform_value(ctx: &Context, value: Value, untouched: &AtomicUsize) -> Value {
+    let value = match &ctx.args.value_transform {
+        Some(transform) => transform.apply(value).unwrap_or_else(|value| {
+            untouched.fetch_add(1, Ordering::Relaxed);
+            value
+        }),
+        None => value,
+    };
+
+    normalize_empty(ctx, value)
+}
+
+/// Counts the empty values moved by a rename under `--on-empty`, renaming empty strings as `null`
+/// with `--on-empty null`. Dropped fields are removed afterwards, by `drop_empty_field`.
+fn normalize_empty(ctx: &Context, value: Value) -> Value {
+    match (&value, ctx.args.on_empty) {
+        (_, OnEmpty::Keep) => value,
+        (Value::Null, _) => {
+            ctx.null_value_count.fetch_add(1, Ordering::Relaxed);
+            value
+        }
+        (Value::String(s), on_empty) if s.is_empty() => {
+            ctx.empty_string_count.fetch_add(1, Ordering::Relaxed);
+            match on_empty {
+                OnEmpty::Null => Value::Null,
+                _ => value,
+            }
+        }
+        _ => value,
+    }
+}
+
+/// With `--on-empty drop`, a copy of the document before a rename, for `drop_empty_field`.
+fn before_rename(ctx: &Context, doc: &Value) -> Option<Value> {
+    (ctx.args.on_empty == OnEmpty::Drop).then(|| doc.clone())
+}
+
+/// With `--on-empty drop`, removes the renamed field wherever the rename left it holding an empty
+/// value, so the document is saved without the old field and without an empty new one. Empty
+/// values the new field held before the rename (`before`, from `before_rename`) are kept.
+fn drop_empty_field(doc: &mut Value, before: Option<&Value>, new_field: &str) {
+    if let Some(before) = before {
+        let path = refield::rename::split_path(new_field);
+        refield::rename::drop_produced_empty_values(doc, before, &path);
+    }
+}
+
+/// Reports the values of a document that the value transform left unchanged.
+fn report_untouched_values(
+    ctx: &Context,
+    log: &mut DocumentLog,
+    id: &str,
+    untouched: &AtomicUsize,
+) {
+    let untouched = untouched.load(Ordering::Relaxed);
+    if untouched > 0 {
+        ctx.untouched_value_count
+            .fetch_add(untouched, Ordering::Relaxed);
+        log.warn(format!(
+            "\t{} values in document ID {} were left unchanged: the value transform does not apply to them.",
+            untouched, id
+        ));
+    }
+}
+
+/// Reports a document in which the original values were backed up before renaming.
+fn report_backup(ctx: &Context, log: &mut DocumentLog, id: &str) {
+    ctx.backup_count.fetch_add(1, Ordering::Relaxed);
+    log.info(format!("\tbackup created in document ID: {}", id));
+}
+
+/// Reports a document left untouched because the new field already exists, as a skip or,
+/// with `--on-conflict error`, as a failure.
+fn report_collision(ctx: &Context, log: &mut DocumentLog, doc: &Value, id: &str, new_field: &str) {
+    ctx.collision_count.fetch_add(1, Ordering::Relaxed);
+    if ctx.args.on_conflict == OnConflict::Error {
+        let error = format!("'{}' already exists", new_field);
+        ctx.error_count.fetch_add(1, Ordering::Relaxed);
+        count_in_batch(|batch| &batch.failed);
+        log.error(format!("\tError renaming document {}: {}", id, error));
+        audit_error(ctx, doc, id, Outcome::Failed, error);
+    } else {
+        log.warn(format!(
+            "\tField '{}' already exists in document ID {}; skipped.",
+            new_field, id
+        ));
+        audit(ctx, doc, id, Outcome::Conflict);
+    }
+}
+
+/// Records the outcome of a document in the audit database, with the document's current revision.
+fn audit(ctx: &Context, doc: &Value, id: &str, outcome: Outcome) {
+    audit_rev(ctx, id, doc[&ctx.args.rev_field].as_str(), outcome);
+}
+
+/// Records the outcome of a document with the error that caused it, which only the report keeps.
+fn audit_error(ctx: &Context, doc: &Value, id: &str, outcome: Outcome, error: impl ToString) {
+    if let Some(report) = &ctx.report {
+        report.send(ReportEntry::new(id, outcome, Some(error.to_string())));
+    }
+    record_audit(ctx, id, doc[&ctx.args.rev_field].as_str(), outcome);
+}
+
+/// Records the outcome of a document in the report and the audit database, if enabled.
+fn audit_rev(ctx: &Context, id: &str, rev: Option<&str>, outcome: Outcome) {
+    if let Some(report) = &ctx.report {
+        report.send(ReportEntry::new(id, outcome, None));
+    }
+    record_audit(ctx, id, rev, outcome);
+}
+
+/// Records the outcome of a document in the audit database, if `--audit-db` is set.
+/// Failing to record is reported at the end but does not interrupt the migration.
+fn record_audit(ctx: &Context, id: &str, rev: Option<&str>, outcome: Outcome) {
+    if let Some(audit) = &ctx.audit {
+        audit.record(id, rev, &ctx.audit_rule, outcome);
+    }
+}
+
+/// Writes a modified document to CouchDB, or only reports it in dry-run mode,
+/// and records its ID among the changed documents.
+/// With `--prune-empty`, the objects and arrays the change left empty are removed first.
+async fn save_document(ctx: &Context, log: &mut DocumentLog, doc: &mut Value, id: &str) {
+    let pruned = ctx.args.prune_empty
+        && ORIGINAL
+            .try_with(|original| {
+                original
+                    .as_ref()
+                    .is_some_and(|original| refield::rename::prune_empty(doc, original))
+            })
+            .unwrap_or(false);
+    if pruned {
+        ctx.pruned_doc_count.fetch_add(1, Ordering::Relaxed);
+        log.info(format!("\tpruned empty objects in document ID: {}", id));
+    }
+
+    // With `--bulk-size`, the document waits to be written with others
+    if let (Some(bulk_size), false) = (ctx.args.bulk_size, ctx.args.dry_run) {
+        let full = {
+            let mut buffer = ctx.bulk_buffer.lock().unwrap();
+            buffer.push(doc.clone());
+            match buffer.len() >= bulk_size {
+                true => std::mem::take(&mut *buffer),
+                false => Vec::new(),
+            }
+        };
+        flush_bulk(ctx, full).await;
+        return;
+    }
+
+    if !ctx.args.dry_run {
+        // Update the document in CouchDB, rebasing the change on the latest revision on conflicts
+        let resolution = write_resolving_conflicts(
+            doc,
+            CONFLICT_RETRIES,
+            |doc| async move { update_document(ctx, &doc).await },
+            || {
+                ctx.conflict_refetch_count.fetch_add(1, Ordering::Relaxed);
+                fetch_document_by_id(
+                    &ctx.client,
+                    &ctx.args.db_url,
+                    &ctx.args.table_name,
+                    id,
+                    ctx.args.raw_id,
+                    ctx.args.read_quorum,
+                    ctx.auth.as_ref(),
+                )
+            },
+            |latest| reapply_change(ctx, latest),
+        )
+        .await;
+        let doc = &*doc;
+        match resolution {
+            Err(err) => {
+                ctx.error_count.fetch_add(1, Ordering::Relaxed);
+                count_in_batch(|batch| &batch.failed);
+                log.error(format!("\tError updating document {}: {}", id, err));
+                audit_error(ctx, doc, id, Outcome::Failed, err);
+            }
+            Ok(Resolution::Written(rev)) => {
+                ctx.updated_count.fetch_add(1, Ordering::Relaxed);
+                count_in_batch(|batch| &batch.changed);
+                ctx.changed_ids.lock().unwrap().insert(id.to_string());
+                log.info(format!("\tupdated document ID: {}", id));
+                audit_rev(ctx, id, rev.as_deref(), Outcome::Updated);
+                emit_document(ctx, log, doc, rev);
+            }
+            Ok(Resolution::Unneeded) => {
+                log.info(format!(
+                    "\tdocument ID {} was updated concurrently and no longer needs the change.",
+                    id
+                ));
+                audit(ctx, doc, id, Outcome::Unchanged);
+            }
+        }
+    } else {
+        let doc = &*doc;
+
+        // Ask the server whether it would accept the update, without persisting it
+        if ctx.args.validate_on_server {
+            match refield::validate::validate_on_server(
+                &ctx.client,
+                &ctx.args.db_url,
+                &ctx.args.table_name,
+                doc,
+                ctx.auth.as_ref(),
+            )
+            .await
+            {
+                // The scratch copy is written, then deleted
+                Ok(()) => {
+                    ctx.scratch_write_count.fetch_add(2, Ordering::Relaxed);
+                }
+                Err(err) => {
+                    ctx.rejected_count.fetch_add(1, Ordering::Relaxed);
+                    log.error(format!(
+                        "\tDry-run: Document ID {} would be rejected: {}",
+                        id, err
+                    ));
+                    audit_error(ctx, doc, id, Outcome::Rejected, err);
+                    return;
+                }
+            }
+        }
+
+        // Dry-run mode: Log what would have been updated
+        count_in_batch(|batch| &batch.changed);
+        ctx.changed_ids.lock().unwrap().insert(id.to_string());
+        log.info(format!(
+            "\tDry-run: Document ID {} would have been updated.",
+            id
+        ));
+        log_dry_run_diff(ctx, log, doc);
+        audit(ctx, doc, id, Outcome::WouldUpdate);
+        emit_document(ctx, log, doc, None);
+    }
+}
+
+/// Writes buffered documents with a single `_bulk_docs` request (`--bulk-size`), then records
+/// the outcome of each one like `save_document` does. Conflicting documents are reported, not rebased.
+async fn flush_bulk(ctx: &Context, docs: Vec<Value>) {
+    if docs.is_empty() {
+        return;
+    }
+
+    let result = ctx
+        .write_slots
+        .run(async {
+            // Every document of the request counts against the global write budget
+            if let Some(limiter) = &ctx.write_limiter {
+                for _ in &docs {
+                    limiter.acquire().await;
+                }
+            }
+
+            bulk_update(
+                &ctx.client,
+                &ctx.args.db_url,
+                &ctx.args.table_name,
+                &docs,
+                ctx.auth.as_ref(),
+                ctx.args.max_retries,
+                ctx.args.write_quorum,
+            )
+            .await
+        })
+        .await;
+    let rows = match result {
+        Ok(rows) => rows,
+        Err(err) => {
+            ctx.error_count.fetch_add(docs.len(), Ordering::Relaxed);
+            ctx.log
+                .error(format!("\tError updating a batch of documents: {}", err));
+            for doc in &docs {
+                audit_error(
+                    ctx,
+                    doc,
+                    doc["_id"].as_str().unwrap_or("<unknown>"),
+                    Outcome::Failed,
+                    &err,
+                );
+            }
+            return;
+        }
+    };
+
+    // Rows come back in the order of the documents sent
+    for (doc, row) in docs.iter().zip(rows) {
+        let mut log = ctx.log.document();
+        let id = row.id.as_str();
+        match row.outcome {
+            BulkOutcome::Written(rev) => {
+                ctx.updated_count.fetch_add(1, Ordering::Relaxed);
+                ctx.changed_ids.lock().unwrap().insert(id.to_string());
+                log.info(format!("\tupdated document ID: {}", id));
+                audit_rev(ctx, id, rev.as_deref(), Outcome::Updated);
+                emit_document(ctx, &mut log, doc, rev);
+            }
+            BulkOutcome::Conflict => {
+                ctx.error_count.fetch_add(1, Ordering::Relaxed);
+                log.error(format!(
+                    "\tError updating document {}: it was updated concurrently (conflict).",
+                    id
+                ));
+                audit_error(ctx, doc, id, Outcome::Conflict, "conflict");
+            }
+            BulkOutcome::Failed(reason) => {
+                ctx.error_count.fetch_add(1, Ordering::Relaxed);
+                log.error(format!("\tError updating document {}: {}", id, reason));
+                audit_error(ctx, doc, id, Outcome::Failed, reason);
+            }
+        }
+    }
+}
+
+/// Logs how a dry-run would change a document, compared to the document as fetched: one line
+/// per changed field, or both versions in full with `--verbose`.
+fn log_dry_run_diff(ctx: &Context, log: &mut DocumentLog, doc: &Value) {
+    let Ok(Some(original)) = ORIGINAL.try_with(Option::clone) else {
+        return;
+    };
+
+    let lines = match ctx.args.verbose {
+        true => render_before_after(&original, doc),
+        false => render_diff(&diff_documents(&original, doc)),
+    };
+    for line in lines {
+        log.info(format!("\t  {}", line));
+    }
+}
+
+/// Writes an updated document to stdout as a single JSON line when `--emit-updated` is set,
+/// with the new revision returned by the server, if any.
+fn emit_document(ctx: &Context, log: &mut DocumentLog, doc: &Value, rev: Option<String>) {
+    if !ctx.args.emit_updated {
+        return;
+    }
+
+    match rev {
+        Some(rev) => {
+            let mut doc = doc.clone();
+            doc[&ctx.args.rev_field] = Value::String(rev);
+            log.data(doc.to_string());
+        }
+        None => log.data(doc.to_string()),
+    }
+}
+
+/// Used as a callback to soft-delete a document whose old field equals the configured value.
+async fn soft_delete_document(ctx: Arc<Context>, mut doc: Value) {
+    let args = &ctx.args;
+    let Some(expected) = &args.delete_doc_when_equals else {
+        return;
+    };
+    ctx.processed_count.fetch_add(1, Ordering::Relaxed);
+    let id = doc[&args.id_field]
+        .as_str()
+        .unwrap_or("<unknown>")
+        .to_string();
+
+    // The document matches if any old field path holds the expected value
+    let matches = ctx.old_field_paths.iter().any(|path| {
+        let path: Vec<&str> = path.iter().map(|s| s.as_str()).collect();
+        refield::rename::find_nested_values(&doc, &path).contains(&expected)
+    });
+
+    if !matches {
+        return;
+    }
+
+    if args.dry_run {
+        ctx.deleted_count.fetch_add(1, Ordering::Relaxed);
+        count_in_batch(|batch| &batch.changed);
+        ctx.changed_ids.lock().unwrap().insert(id.clone());
+        ctx.log.info(format!(
+            "\tDry-run: Document ID {} would have been deleted.",
+            id
+        ));
+        audit(&ctx, &doc, &id, Outcome::WouldDelete);
+        return;
+    }
+
+    // Mark the document deleted and persist it
+    doc["_deleted"] = Value::Bool(true);
+    if let Err(err) = update_document(&ctx, &doc).await {
+        ctx.error_count.fetch_add(1, Ordering::Relaxed);
+        count_in_batch(|batch| &batch.failed);
+        ctx.log
+            .error(format!("\tError deleting document {}: {}", id, err));
+        audit_error(&ctx, &doc, &id, Outcome::Failed, err);
+    } else {
+        ctx.deleted_count.fetch_add(1, Ordering::Relaxed);
+        ctx.updated_count.fetch_add(1, Ordering::Relaxed);
+        count_in_batch(|batch| &batch.changed);
+        ctx.changed_ids.lock().unwrap().insert(id.clone());
+        ctx.log.info(format!("\tdeleted document ID: {}", id));
+        audit(&ctx, &doc, &id, Outcome::Deleted);
+    }
+}
+
+/// Describes the data the run would irrecoverably remove, if any: soft-deleted documents
+/// (`--delete-doc-when-equals`) or deleted fields (`--delete-others`).
+fn destructive_operation(args: &Args) -> Option<&'static str> {
+    if args.delete_doc_when_equals.is_some() {
+        Some("Matching documents will be deleted")
+    } else if args.delete {
+        Some("The --old fields will be deleted from matching documents")
+    } else if args.delete_others && args.old_fields.len() > 1 {
+        Some("The remaining --old fields will be deleted from matching documents")
+    } else if args.delete_sources {
+        Some("The fields referenced by --compute will be deleted from matching documents")
+    } else {
+        None
+    }
+}
+
+/// Asks the user to type the table name on stdin to confirm a destructive operation,
+/// returning true only for an exact match.
+fn confirm_table_name(operation: &str, table_name: &str) -> bool {
+    let prompt = format!(
+        "{} in table '{}'. Type the table name to continue: ",
+        operation, table_name
+    );
+
+    // Keep the prompt out of the data written to stdout
+    if refield::log::stdout_reserved() {
+        eprint!("{}", prompt);
+        let _ = std::io::stderr().flush();
+    } else {
+        print!("{}", prompt);
+        let _ = std::io::stdout().flush();
+    }
+
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+
+    answer.trim_end_matches(['\r', '\n']) == table_name
+}
+
+/// Persists changes to a document in CouchDB when the dry-run mode is disabled.
+/// The document's ID and revision are read from the configured ID and revision fields.
+/// Transient failures are retried within the `--max-retries` budget; a stale revision
+/// is reported as `WriteError::Conflict`.
+/// Returns the new revision reported by the server, if any.
+async fn update_document(ctx: &Context, doc: &Value) -> Result<Option<String>, WriteError> {
+    let args = &ctx.args;
+    let id = doc[&args.id_field]
+        .as_str()
+        .ok_or_else(|| WriteError::Failed(format!("Document missing '{}' field", args.id_field)))?;
+
+    // Documents of `--input-file` are kept for `--output-file` instead of being sent anywhere
+    if args.input_file.is_some() {
+        ctx.file_updates
+            .lock()
+            .unwrap()
+            .insert(id.to_string(), doc.clone());
+        return Ok(None);
+    }
+    let rev = doc[&args.rev_field].as_str().ok_or_else(|| {
+        WriteError::Failed(format!("Document missing '{}' field", args.rev_field))
+    })?;
+    let url = format!(
+        "{}/{}/{}",
+        args.db_url,
+        args.table_name,
+        encode_doc_id(id, args.raw_id)
+    );
+
+    // Hold one of the `--concurrency` write slots until the server has answered
+    ctx.write_slots
+        .run(async {
+            // Wait for the global write budget before sending
+            if let Some(limiter) = &ctx.write_limiter {
+                limiter.acquire().await;
+            }
+
+            let mut request = ctx.client.put(&url).json(doc).header("If-Match", rev);
+            if let Some(w) = args.write_quorum {
+                request = request.query(&[("w", w)]);
+            }
+            let request = refield::iam::authorize(ctx.auth.as_ref(), request)
+                .await
+                .map_err(WriteError::Failed)?;
+            let response = send_with_retry(request, args.max_retries)
+                .await
+                .map_err(|e| WriteError::Failed(e.to_string()))?;
+
+            if response.status() == StatusCode::CONFLICT {
+                return Err(WriteError::Conflict);
+            }
+            if response.status() != StatusCode::OK && response.status() != StatusCode::CREATED {
+                return Err(WriteError::Failed(format!(
+                    "Failed to update document {}: Status code {}",
+                    id,
+                    response.status()
+                )));
+            }
+
+            let body: Value = response.json().await.unwrap_or_default();
+            Ok(body["rev"].as_str().map(String::from))
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// Builds the context of a run against table `db` of `server`, with the given arguments
+    /// after the URL and table.
+    fn test_context(server: &MockServer, args: &[&str]) -> Arc<Context> {
+        let uri = server.uri();
+        let command_line = ["refield", "-u", &uri, "-t", "db"]
+            .into_iter()
+            .chain(args.iter().copied());
+        let args = refield::args::parse_args_from(command_line).unwrap();
+        let (log, _) = refield::log::start_writer(false, true);
+        Arc::new(Context::new(args, Client::new(), log))
+    }
+
+    /// Serves table `db` as a single `_find` page of `docs`, followed by an empty one.
+    async fn single_page_couchdb(docs: Vec<Value>) -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/db"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(json!({ "doc_count": docs.len() })),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/db/_find"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(json!({ "docs": docs, "bookmark": "1" })),
+            )
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/db/_find"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(json!({ "docs": [], "bookmark": "1" })),
+            )
+            .mount(&server)
+            .await;
+        server
+    }
+
+    /// Scans the table as `main` does, then waits for the processing tasks.
+    async fn scan(ctx: &Arc<Context>) -> FetchSummary {
+        let callback_ctx = ctx.clone();
+        let summary = new_fetcher(ctx)
+            .with_callback(Box::new(move |doc: Value| {
+                spawn_scanned(&callback_ctx, doc)
+            }))
+            .execute()
+            .await
+            .unwrap();
+        join_tasks(ctx).await;
+        summary
+    }
+
+    #[tokio::test]
+    async fn test_non_object_documents_are_counted_as_anomalies() {
+        let server = single_page_couchdb(vec![
+            json!({ "_id": "a", "_rev": "1-a", "x": 1 }),
+            json!([1, 2]),
+            json!("text"),
+        ])
+        .await;
+        let ctx = test_context(&server, &["-o", "x", "-n", "y", "--dry-run"]);
+
+        let summary = scan(&ctx).await;
+
+        assert_eq!(summary.total_fetched, 3);
+        assert_eq!(ctx.malformed_count.load(Ordering::Relaxed), 2);
+        assert_eq!(ctx.processed_count.load(Ordering::Relaxed), 1);
+        assert_eq!(
+            *ctx.changed_ids.lock().unwrap(),
+            BTreeSet::from(["a".to_string()])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_updates_are_sent_with_the_write_quorum() {
+        let server = single_page_couchdb(vec![json!({ "_id": "a", "_rev": "1-a", "x": 1 })]).await;
+        Mock::given(method("PUT"))
+            .and(path("/db/a"))
+            .and(query_param("w", "2"))
+            .respond_with(
+                ResponseTemplate::new(201).set_body_json(json!({ "ok": true, "rev": "2-a" })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        let ctx = test_context(&server, &["-o", "x", "-n", "y", "--write-quorum", "2"]);
+
+        scan(&ctx).await;
+
+        assert_eq!(ctx.updated_count.load(Ordering::Relaxed), 1);
+        assert_eq!(ctx.error_count.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_documents_are_skipped_rather_than_rewritten() {
+        let server = single_page_couchdb(vec![
+            json!({ "_id": "a", "_rev": "1-a", "x": 1 }),
+            json!({ "_id": "b", "_rev": "1-b", "x": "b".repeat(200) }),
+        ])
+        .await;
+        Mock::given(method("PUT"))
+            .and(path("/db/a"))
+            .respond_with(
+                ResponseTemplate::new(201).set_body_json(json!({ "ok": true, "rev": "2-a" })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/db/b"))
+            .respond_with(ResponseTemplate::new(201))
+            .expect(0)
+            .mount(&server)
+            .await;
+        let ctx = test_context(&server, &["-o", "x", "-n", "y", "--max-doc-bytes", "100"]);
+
+        scan(&ctx).await;
+
+        assert_eq!(ctx.oversized_count.load(Ordering::Relaxed), 1);
+        assert_eq!(ctx.updated_count.load(Ordering::Relaxed), 1);
+        assert_eq!(
+            *ctx.changed_ids.lock().unwrap(),
+            BTreeSet::from(["a".to_string()])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_count_changed_scans_the_selector_without_writing() {
+        let server = single_page_couchdb(vec![
+            json!({ "_id": "a", "_rev": "1-a", "type": "user", "x": 1 }),
+            json!({ "_id": "b", "_rev": "1-b", "type": "user" }),
+        ])
+        .await;
+        let ctx = test_context(
+            &server,
+            &[
+                "-o",
+                "x",
+                "-n",
+                "y",
+                "--count-changed",
+                "--selector",
+                r#"{"type": "user"}"#,
+            ],
+        );
+
+        let summary = scan(&ctx).await;
+
+        let requests = server.received_requests().await.unwrap();
+        let find: Value = requests
+            .iter()
+            .find(|request| request.url.path() == "/db/_find")
+            .unwrap()
+            .body_json()
+            .unwrap();
+        assert!(find["selector"].to_string().contains(r#""type":"user""#));
+        assert!(requests
+            .iter()
+            .all(|request| request.method.as_str() != "PUT"));
+        assert_eq!(summary.total_fetched, 2);
+        assert_eq!(
+            *ctx.changed_ids.lock().unwrap(),
+            BTreeSet::from(["a".to_string()])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scanned_ids_are_fetched_one_by_one_within_the_read_slots() {
+        let server = single_page_couchdb(vec![
+            json!({ "_id": "a", "_rev": "1-a" }),
+            json!({ "_id": "gone", "_rev": "1-g" }),
+            json!({ "_id": "c", "_rev": "1-c" }),
+        ])
+        .await;
+        let delay = Duration::from_millis(100);
+        for id in ["a", "c"] {
+            Mock::given(method("GET"))
+                .and(path(format!("/db/{}", id)))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_json(json!({ "_id": id, "_rev": "1-a", "x": 1 }))
+                        .set_delay(delay),
+                )
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+        Mock::given(method("GET"))
+            .and(path("/db/gone"))
+            .respond_with(ResponseTemplate::new(404).set_delay(delay))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let ctx = test_context(
+            &server,
+            &[
+                "-o",
+                "x",
+                "-n",
+                "y",
+                "--dry-run",
+                "--include-docs",
+                "false",
+                "--concurrency",
+                "1",
+            ],
+        );
+
+        let started = Instant::now();
+        scan(&ctx).await;
+
+        // A single read slot lets only one document be fetched at a time
+        assert!(started.elapsed() >= delay * 3);
+        let requests = server.received_requests().await.unwrap();
+        let find: Value = requests
+            .iter()
+            .find(|request| request.url.path() == "/db/_find")
+            .unwrap()
+            .body_json()
+            .unwrap();
+        assert_eq!(find["fields"], json!(["_id", "_rev"]));
+        assert_eq!(
+            *ctx.changed_ids.lock().unwrap(),
+            BTreeSet::from(["a".to_string(), "c".to_string()])
+        );
+        assert_eq!(ctx.processed_count.load(Ordering::Relaxed), 2);
+        assert_eq!(ctx.error_count.load(Ordering::Relaxed), 0);
+    }
 }
@@ -0,0 +1,135 @@
+use crate::args::validate_rename_paths;
+use serde_json::Value;
+
+/// A single rename rule: the field at `old_field` is renamed to `new_field`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenameRule {
+    pub old_field: String, // Field to be renamed (supports dot notation for nested fields)
+    pub new_field: String, // New name of the field
+}
+
+/// Loads rename rules from a mapping file.
+/// See `parse_mapping` for the supported formats.
+pub fn load_mapping_file(path: &str) -> Result<Vec<RenameRule>, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read mapping file '{}': {}", path, e))?;
+
+    parse_mapping(&content).map_err(|e| format!("Invalid mapping file '{}': {}", path, e))
+}
+
+/// Parses rename rules from either a JSON object (`{"old": "new", ...}`) or CSV lines (`old,new`).
+/// In CSV, blank lines, `#` comments, and an optional `old,new` header are skipped.
+/// Every rule is validated like `--old`/`--new`: both paths must share the same parent.
+pub fn parse_mapping(content: &str) -> Result<Vec<RenameRule>, String> {
+    let rules = if content.trim_start().starts_with('{') {
+        parse_json_mapping(content)?
+    } else {
+        parse_csv_mapping(content)?
+    };
+
+    if rules.is_empty() {
+        return Err("No rename rules found".to_string());
+    }
+
+    for rule in &rules {
+        validate_rename_paths(&rule.old_field, &rule.new_field)?;
+    }
+
+    Ok(rules)
+}
+
+/// Reads the rules of a JSON object mapping old field names to new ones, in file order.
+fn parse_json_mapping(content: &str) -> Result<Vec<RenameRule>, String> {
+    let json: Value = serde_json::from_str(content).map_err(|e| e.to_string())?;
+    let Value::Object(obj) = json else {
+        return Err("Expected a JSON object of old to new field names".to_string());
+    };
+
+    obj.into_iter()
+        .map(|(old_field, new_field)| match new_field {
+            Value::String(new_field) => Ok(RenameRule {
+                old_field,
+                new_field,
+            }),
+            other => Err(format!(
+                "New name of '{}' must be a string, found {}",
+                old_field, other
+            )),
+        })
+        .collect()
+}
+
+/// Reads the `old,new` rules of a CSV mapping.
+fn parse_csv_mapping(content: &str) -> Result<Vec<RenameRule>, String> {
+    let mut rules = Vec::new();
+
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || (number == 0 && line == "old,new") {
+            continue;
+        }
+
+        let Some((old_field, new_field)) = line.split_once(',') else {
+            return Err(format!("Line {}: expected 'old,new'", number + 1));
+        };
+        let (old_field, new_field) = (old_field.trim(), new_field.trim());
+        if old_field.is_empty() || new_field.is_empty() || new_field.contains(',') {
+            return Err(format!("Line {}: expected 'old,new'", number + 1));
+        }
+
+        rules.push(RenameRule {
+            old_field: old_field.to_string(),
+            new_field: new_field.to_string(),
+        });
+    }
+
+    Ok(rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(old_field: &str, new_field: &str) -> RenameRule {
+        RenameRule {
+            old_field: old_field.to_string(),
+            new_field: new_field.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_csv_mapping_skips_header_and_comments() {
+        let content =
+            "old,new\n# legacy names\nqty,quantity\n\n profile.fname , profile.first_name\n";
+
+        let rules = parse_mapping(content).unwrap();
+
+        assert_eq!(
+            rules,
+            vec![
+                rule("qty", "quantity"),
+                rule("profile.fname", "profile.first_name")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_json_mapping_keeps_file_order() {
+        let content = r#"{ "zeta": "z", "alpha": "a" }"#;
+
+        let rules = parse_mapping(content).unwrap();
+
+        assert_eq!(rules, vec![rule("zeta", "z"), rule("alpha", "a")]);
+    }
+
+    #[test]
+    fn test_parse_mapping_rejects_invalid_rules() {
+        assert!(parse_mapping("a.b,c.d").is_err(), "Parents differ");
+        assert!(parse_mapping("qty").is_err(), "Missing new name");
+        assert!(
+            parse_mapping(r#"{ "qty": 1 }"#).is_err(),
+            "New name is not a string"
+        );
+        assert!(parse_mapping("# nothing\n").is_err(), "No rules");
+    }
+}
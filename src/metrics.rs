@@ -0,0 +1,187 @@
+#[cfg(feature = "pushgateway")]
+use prometheus::{Encoder, Gauge, IntGauge, Registry, TextEncoder};
+use reqwest::Client;
+#[cfg(feature = "pushgateway")]
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+#[cfg(feature = "pushgateway")]
+use std::time::Instant;
+#[cfg(feature = "pushgateway")]
+use tokio::task::JoinHandle;
+
+/// Point-in-time values of the run's counters, pushed to the Prometheus pushgateway.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MetricsSnapshot {
+    pub docs_processed: usize, // Documents processed so far
+    pub docs_updated: usize,   // Documents written to the database so far
+    pub errors: usize,         // Errors encountered so far
+}
+
+/// Prometheus gauges mirroring a `MetricsSnapshot`, plus the processing rate.
+#[cfg(feature = "pushgateway")]
+struct Gauges {
+    registry: Registry,
+    docs_processed: IntGauge,
+    docs_updated: IntGauge,
+    errors: IntGauge,
+    current_rate: Gauge,
+}
+
+#[cfg(feature = "pushgateway")]
+impl Gauges {
+    fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new();
+        let docs_processed = IntGauge::new("refield_docs_processed", "Documents processed")?;
+        let docs_updated = IntGauge::new("refield_docs_updated", "Documents updated")?;
+        let errors = IntGauge::new("refield_errors", "Errors encountered")?;
+        let current_rate = Gauge::new(
+            "refield_current_rate",
+            "Documents processed per second since the previous push",
+        )?;
+
+        registry.register(Box::new(docs_processed.clone()))?;
+        registry.register(Box::new(docs_updated.clone()))?;
+        registry.register(Box::new(errors.clone()))?;
+        registry.register(Box::new(current_rate.clone()))?;
+
+        Ok(Self {
+            registry,
+            docs_processed,
+            docs_updated,
+            errors,
+            current_rate,
+        })
+    }
+
+    /// Encodes the gauges in the Prometheus text exposition format.
+    fn encode(&self) -> Result<Vec<u8>, prometheus::Error> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(buffer)
+    }
+}
+
+/// Pushes the run's counters to a Prometheus pushgateway.
+/// Metrics are grouped under `job="refield"` and `instance=<table_name>`.
+/// Push failures are reported on stderr and do not interrupt the migration.
+#[cfg(feature = "pushgateway")]
+struct Pushgateway {
+    client: Client,                    // HTTP client for making requests
+    url: String,                       // URL of the metrics group on the pushgateway
+    gauges: Gauges,                    // Gauges updated before each push
+    previous: Mutex<(usize, Instant)>, // Documents processed and time at the previous push
+}
+
+#[cfg(feature = "pushgateway")]
+impl Pushgateway {
+    /// Constructs a new `Pushgateway` for the given gateway base URL and table.
+    fn new(client: Client, gateway_url: &str, table_name: &str) -> Result<Self, String> {
+        Ok(Self {
+            client,
+            url: format!(
+                "{}/metrics/job/refield/instance/{}",
+                gateway_url.trim_end_matches('/'),
+                urlencoding::encode(table_name)
+            ),
+            gauges: Gauges::new().map_err(|e| e.to_string())?,
+            previous: Mutex::new((0, Instant::now())),
+        })
+    }
+
+    /// Updates the gauges from `current` and pushes them, replacing the previous values of the group.
+    async fn push(&self, current: MetricsSnapshot) {
+        // Compute the processing rate since the previous push
+        let rate = {
+            let mut previous = self.previous.lock().unwrap();
+            let elapsed = previous.1.elapsed().as_secs_f64();
+            let processed = current.docs_processed.saturating_sub(previous.0);
+            *previous = (current.docs_processed, Instant::now());
+            processed as f64 / elapsed
+        };
+
+        self.gauges
+            .docs_processed
+            .set(current.docs_processed as i64);
+        self.gauges.docs_updated.set(current.docs_updated as i64);
+        self.gauges.errors.set(current.errors as i64);
+        self.gauges.current_rate.set(rate);
+
+        let body = match self.gauges.encode() {
+            Ok(body) => body,
+            Err(err) => {
+                tracing::warn!("Failed to encode metrics: {}", err);
+                return;
+            }
+        };
+
+        match self.client.put(&self.url).body(body).send().await {
+            Ok(response) if response.status().is_success() => {}
+            Ok(response) => tracing::warn!(
+                "Failed to push metrics to pushgateway: Status code {}",
+                response.status()
+            ),
+            Err(err) => tracing::warn!("Failed to push metrics to pushgateway: {}", err),
+        }
+    }
+}
+
+/// Handle on the background task pushing metrics to a Prometheus pushgateway.
+pub struct MetricsPusher {
+    #[cfg(feature = "pushgateway")]
+    pushgateway: Arc<Pushgateway>, // Target of the pushes
+    #[cfg(feature = "pushgateway")]
+    handle: JoinHandle<()>, // Background task pushing periodically
+    #[cfg(not(feature = "pushgateway"))]
+    never: std::convert::Infallible, // Cannot be constructed without the feature
+}
+
+impl MetricsPusher {
+    /// Stops the periodic pushes and pushes the final values of the counters.
+    pub async fn finish(self, current: MetricsSnapshot) {
+        #[cfg(feature = "pushgateway")]
+        {
+            self.handle.abort();
+            self.pushgateway.push(current).await;
+        }
+        #[cfg(not(feature = "pushgateway"))]
+        {
+            let _ = current;
+            match self.never {}
+        }
+    }
+}
+
+/// Starts a background task that pushes a fresh snapshot of the counters to `gateway_url` every `interval`.
+/// Fails when refield was built without the `pushgateway` feature.
+pub fn start_pushgateway<F>(
+    client: Client,
+    gateway_url: &str,
+    table_name: &str,
+    interval: Duration,
+    snapshot: F,
+) -> Result<MetricsPusher, String>
+where
+    F: Fn() -> MetricsSnapshot + Send + 'static,
+{
+    #[cfg(feature = "pushgateway")]
+    {
+        let pushgateway = Arc::new(Pushgateway::new(client, gateway_url, table_name)?);
+        let periodic = pushgateway.clone();
+        let handle = tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                periodic.push(snapshot()).await;
+            }
+        });
+
+        Ok(MetricsPusher {
+            pushgateway,
+            handle,
+        })
+    }
+    #[cfg(not(feature = "pushgateway"))]
+    {
+        let _ = (client, gateway_url, table_name, interval, snapshot);
+        Err("--pushgateway requires refield to be built with the `pushgateway` feature".to_string())
+    }
+}
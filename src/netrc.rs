@@ -0,0 +1,224 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use reqwest::header::HeaderValue;
+use std::path::PathBuf;
+
+/// Login and password for basic authentication, from `--username`/`--password` or a `.netrc` entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub login: String,    // User name sent with basic authentication
+    pub password: String, // Password sent with basic authentication
+}
+
+impl Credentials {
+    /// The `Authorization` header value for basic authentication with these credentials.
+    pub fn authorization(&self) -> Result<HeaderValue, String> {
+        let encoded = STANDARD.encode(format!("{}:{}", self.login, self.password));
+        let mut value = HeaderValue::from_str(&format!("Basic {}", encoded))
+            .map_err(|e| format!("Invalid credentials: {}", e))?;
+        value.set_sensitive(true);
+        Ok(value)
+    }
+}
+
+/// Location of the netrc file: `$NETRC` if set, otherwise `~/.netrc` (`%USERPROFILE%\_netrc` on Windows).
+pub fn netrc_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("NETRC") {
+        return Some(PathBuf::from(path));
+    }
+
+    if cfg!(windows) {
+        std::env::var_os("USERPROFILE").map(|home| PathBuf::from(home).join("_netrc"))
+    } else {
+        std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".netrc"))
+    }
+}
+
+/// Reads the credentials for `host` from the netrc file.
+/// A missing file means no credentials; an unreadable one is an error.
+pub fn lookup(host: &str) -> Result<Option<Credentials>, String> {
+    let Some(path) = netrc_path() else {
+        return Ok(None);
+    };
+
+    match std::fs::read_to_string(&path) {
+        Ok(content) => Ok(find_credentials(&content, host)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(format!(
+            "Failed to read netrc file '{}': {}",
+            path.display(),
+            err
+        )),
+    }
+}
+
+/// A `machine` or `default` entry while it is being read.
+#[derive(Debug, Default)]
+struct Entry {
+    matches: bool,            // Whether the entry names the host looked up
+    is_default: bool,         // Whether this is the `default` entry
+    login: Option<String>,    // Value of the `login` token, if read
+    password: Option<String>, // Value of the `password` token, if read
+}
+
+impl Entry {
+    /// The entry's credentials, if it has both a login and a password.
+    fn credentials(self) -> Option<Credentials> {
+        Some(Credentials {
+            login: self.login?,
+            password: self.password?,
+        })
+    }
+}
+
+/// Finds the credentials for `host` in the contents of a netrc file.
+/// The first `machine` entry naming the host wins; a `default` entry applies to any other host.
+/// Entries without both a login and a password are ignored, as are `macdef` macros.
+pub fn find_credentials(content: &str, host: &str) -> Option<Credentials> {
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut tokens = tokenize(content).into_iter();
+
+    while let Some(token) = tokens.next() {
+        match (token.as_str(), entries.last_mut()) {
+            ("machine", _) => entries.push(Entry {
+                matches: tokens
+                    .next()
+                    .is_some_and(|name| name.eq_ignore_ascii_case(host)),
+                ..Entry::default()
+            }),
+            ("default", _) => entries.push(Entry {
+                is_default: true,
+                ..Entry::default()
+            }),
+            ("login", Some(entry)) => entry.login = tokens.next(),
+            ("password", Some(entry)) => entry.password = tokens.next(),
+            ("account" | "macdef", _) => {
+                // The value is skipped; a macro body was already dropped by `tokenize`
+                tokens.next();
+            }
+            _ => {}
+        }
+    }
+
+    let (matching, others): (Vec<Entry>, Vec<Entry>) =
+        entries.into_iter().partition(|entry| entry.matches);
+    matching
+        .into_iter()
+        .find_map(Entry::credentials)
+        .or_else(|| {
+            others
+                .into_iter()
+                .filter(|entry| entry.is_default)
+                .find_map(Entry::credentials)
+        })
+}
+
+/// Splits a netrc file into whitespace-separated tokens, honoring double quotes
+/// and skipping `#` comments and the bodies of `macdef` macros.
+fn tokenize(content: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut in_macro = false;
+
+    for line in content.lines() {
+        if in_macro {
+            in_macro = !line.trim().is_empty();
+            continue;
+        }
+
+        let mut chars = line.trim_start().chars().peekable();
+        if chars.peek() == Some(&'#') {
+            continue;
+        }
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+                continue;
+            }
+
+            let mut token = String::new();
+            if c == '"' {
+                chars.next();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => token.extend(chars.next()),
+                        c => token.push(c),
+                    }
+                }
+            } else {
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() {
+                        break;
+                    }
+                    token.push(c);
+                    chars.next();
+                }
+            }
+
+            if token == "macdef" {
+                in_macro = true;
+            }
+            tokens.push(token);
+        }
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NETRC: &str = r#"
+# Production cluster
+machine couch.example.com login admin password "s3cret pass"
+machine other.example.com
+    login bob
+    account ops
+    password hunter2
+
+macdef init
+machine couch.example.com login macro password ignored
+
+default login guest password guest
+"#;
+
+    fn credentials(login: &str, password: &str) -> Option<Credentials> {
+        Some(Credentials {
+            login: login.to_string(),
+            password: password.to_string(),
+        })
+    }
+
+    #[test]
+    fn test_find_credentials_by_machine() {
+        assert_eq!(
+            find_credentials(NETRC, "couch.example.com"),
+            credentials("admin", "s3cret pass")
+        );
+        assert_eq!(
+            find_credentials(NETRC, "OTHER.example.com"),
+            credentials("bob", "hunter2")
+        );
+    }
+
+    #[test]
+    fn test_find_credentials_falls_back_to_default() {
+        assert_eq!(
+            find_credentials(NETRC, "unknown.example.com"),
+            credentials("guest", "guest")
+        );
+        assert_eq!(find_credentials("machine a login x password y", "b"), None);
+        assert_eq!(find_credentials("machine a login x", "a"), None);
+    }
+
+    #[test]
+    fn test_authorization_header() {
+        let header = credentials("Aladdin", "open sesame")
+            .unwrap()
+            .authorization()
+            .unwrap();
+        assert_eq!(header, "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==");
+        assert!(header.is_sensitive());
+    }
+}
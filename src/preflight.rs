@@ -0,0 +1,194 @@
+use crate::iam::{authorize, IamAuth};
+use reqwest::{Client, StatusCode};
+
+/// Runs a series of cheap checks against the CouchDB server before the scan starts.
+/// - Verifies the server at `db_host` is reachable.
+/// - Verifies the table exists.
+/// - When `check_write` is set, verifies the credentials may access the table's security object.
+///
+/// Returns a descriptive error for the first check that fails.
+pub async fn preflight_check(
+    client: &Client,
+    db_host: &str,
+    table_name: &str,
+    check_write: bool,
+    auth: Option<&IamAuth>,
+) -> Result<(), String> {
+    // Check that the CouchDB server responds at all
+    let response = authorize(auth, client.get(db_host))
+        .await?
+        .send()
+        .await
+        .map_err(|e| format!("CouchDB server at '{}' is unreachable: {}", db_host, e))?;
+
+    if !response.status().is_success() {
+        return Err(format!(
+            "CouchDB server at '{}' responded with status code {}",
+            db_host,
+            response.status()
+        ));
+    }
+
+    // Check that the table exists (HEAD avoids transferring the metadata body)
+    let url = format!("{}/{}", db_host, table_name);
+    let response = authorize(auth, client.head(&url))
+        .await?
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    match response.status() {
+        StatusCode::OK => {}
+        StatusCode::NOT_FOUND => {
+            return Err(format!("Table '{}' does not exist", table_name));
+        }
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+            return Err(format!("Not authorized to read table '{}'", table_name));
+        }
+        status => {
+            return Err(format!(
+                "Failed to check table '{}': Status code {}",
+                table_name, status
+            ));
+        }
+    }
+
+    // Writes are only attempted outside of dry-run mode
+    if !check_write {
+        return Ok(());
+    }
+
+    // Reading the security object requires at least member access, which is also needed to write
+    let url = format!("{}/{}/_security", db_host, table_name);
+    let response = authorize(auth, client.get(&url))
+        .await?
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    match response.status() {
+        StatusCode::OK => Ok(()),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+            Err(format!("Not authorized to write to table '{}'", table_name))
+        }
+        status => Err(format!(
+            "Failed to check write permission on table '{}': Status code {}",
+            table_name, status
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// Mounts a server that responds to GET / with `server_status` and HEAD /db with `table_status`
+    async fn couchdb(server_status: u16, table_status: u16) -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/"))
+            .respond_with(ResponseTemplate::new(server_status))
+            .mount(&server)
+            .await;
+        Mock::given(method("HEAD"))
+            .and(path("/db"))
+            .respond_with(ResponseTemplate::new(table_status))
+            .mount(&server)
+            .await;
+        server
+    }
+
+    #[tokio::test]
+    async fn test_preflight_check_passes() {
+        let server = couchdb(200, 200).await;
+
+        let result = preflight_check(&Client::new(), &server.uri(), "db", false, None).await;
+
+        assert_eq!(result, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_preflight_check_rejects_an_unreachable_server() {
+        // Nothing listens on the port once the listener is dropped
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let uri = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let err = preflight_check(&Client::new(), &uri, "db", false, None)
+            .await
+            .unwrap_err();
+
+        assert!(err.contains("is unreachable"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_preflight_check_rejects_a_failing_server() {
+        let server = couchdb(503, 200).await;
+
+        let err = preflight_check(&Client::new(), &server.uri(), "db", false, None)
+            .await
+            .unwrap_err();
+
+        assert!(err.ends_with("responded with status code 503 Service Unavailable"));
+    }
+
+    #[tokio::test]
+    async fn test_preflight_check_rejects_a_missing_table() {
+        let server = couchdb(200, 404).await;
+
+        let result = preflight_check(&Client::new(), &server.uri(), "db", false, None).await;
+
+        assert_eq!(result, Err("Table 'db' does not exist".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_preflight_check_rejects_an_unreadable_table() {
+        for status in [401, 403] {
+            let server = couchdb(200, status).await;
+
+            let result = preflight_check(&Client::new(), &server.uri(), "db", false, None).await;
+
+            assert_eq!(
+                result,
+                Err("Not authorized to read table 'db'".to_string()),
+                "status {}",
+                status
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_preflight_check_rejects_a_forbidden_security_object() {
+        let server = couchdb(200, 200).await;
+        Mock::given(method("GET"))
+            .and(path("/db/_security"))
+            .respond_with(ResponseTemplate::new(403))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let result = preflight_check(&Client::new(), &server.uri(), "db", true, None).await;
+
+        assert_eq!(
+            result,
+            Err("Not authorized to write to table 'db'".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_preflight_check_skips_the_security_object_without_writes() {
+        let server = couchdb(200, 200).await;
+        Mock::given(method("GET"))
+            .and(path("/db/_security"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        let result = preflight_check(&Client::new(), &server.uri(), "db", false, None).await;
+
+        assert_eq!(result, Ok(()));
+    }
+}
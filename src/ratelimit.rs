@@ -0,0 +1,170 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, Semaphore};
+use tokio::time::sleep;
+
+/// A token bucket shared by every task, limiting how many operations start per second.
+/// The bucket holds a single token, so operations start at least `1 / rate` seconds apart
+/// and no one-second window holds more than `rate + 1` of them, even after an idle period.
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: f64,            // Tokens added per second
+    bucket: Mutex<State>, // Tokens left and time of the last refill; waiters queue on it in order
+}
+
+#[derive(Debug)]
+struct State {
+    tokens: f64,       // Tokens currently available
+    refilled: Instant, // Time the tokens were last topped up
+}
+
+impl RateLimiter {
+    /// Creates a bucket allowing `rate` operations per second (`rate` must be positive).
+    /// The first operation starts straight away.
+    pub fn new(rate: f64) -> Self {
+        RateLimiter {
+            rate,
+            bucket: Mutex::new(State {
+                tokens: 1.0,
+                refilled: Instant::now(),
+            }),
+        }
+    }
+
+    /// Waits until a token is available and takes it.
+    pub async fn acquire(&self) {
+        let mut state = self.bucket.lock().await;
+        self.refill(&mut state);
+
+        if state.tokens < 1.0 {
+            // The lock is held while waiting, so later callers are served in turn
+            sleep(Duration::from_secs_f64((1.0 - state.tokens) / self.rate)).await;
+            self.refill(&mut state);
+        }
+        state.tokens = (state.tokens - 1.0).max(0.0);
+    }
+
+    /// Adds the tokens earned since the last refill, up to the single token the bucket holds.
+    fn refill(&self, state: &mut State) {
+        let now = Instant::now();
+        let earned = now.duration_since(state.refilled).as_secs_f64() * self.rate;
+        state.tokens = (state.tokens + earned).min(1.0);
+        state.refilled = now;
+    }
+}
+
+/// Bounds how many operations run at the same time across every task, e.g. document writes.
+#[derive(Debug)]
+pub struct ConcurrencyLimit {
+    slots: Semaphore, // One permit per operation allowed to run at once
+}
+
+impl ConcurrencyLimit {
+    /// Allows up to `max` operations at once (`max` must be at least 1).
+    pub fn new(max: usize) -> Self {
+        ConcurrencyLimit {
+            slots: Semaphore::new(max),
+        }
+    }
+
+    /// Waits for a free slot, then runs `operation`, holding the slot until it completes.
+    pub async fn run<F: Future>(&self, operation: F) -> F::Output {
+        let _permit = self
+            .slots
+            .acquire()
+            .await
+            .expect("the semaphore is never closed");
+        operation.await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_rate_limiter_spaces_acquisitions() {
+        let limiter = Arc::new(RateLimiter::new(100.0));
+        let started = Instant::now();
+
+        // The first token is available at once, the next 24 take 10ms each
+        let tasks: Vec<_> = (0..25)
+            .map(|_| {
+                let limiter = limiter.clone();
+                tokio::spawn(async move { limiter.acquire().await })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(240), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(1), "{:?}", elapsed);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter_caps_aggregate_throughput() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let limiter = Arc::new(RateLimiter::new(50.0));
+        let acquired = Arc::new(AtomicUsize::new(0));
+        let deadline = Instant::now() + Duration::from_millis(400);
+
+        // Many tasks writing as fast as they can share the same budget
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let (limiter, acquired) = (limiter.clone(), acquired.clone());
+                tokio::spawn(async move {
+                    loop {
+                        limiter.acquire().await;
+                        if Instant::now() >= deadline {
+                            break;
+                        }
+                        acquired.fetch_add(1, Ordering::SeqCst);
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        // The first token plus 0.4s worth of tokens (20), whatever the number of tasks
+        let acquired = acquired.load(Ordering::SeqCst);
+        assert!(acquired <= 22, "{}", acquired);
+        assert!(acquired >= 15, "{}", acquired);
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_limit_bounds_operations_in_flight() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let limit = Arc::new(ConcurrencyLimit::new(3));
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+
+        let tasks: Vec<_> = (0..20)
+            .map(|_| {
+                let (limit, in_flight, peak) = (limit.clone(), in_flight.clone(), peak.clone());
+                tokio::spawn(async move {
+                    limit
+                        .run(async {
+                            let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                            peak.fetch_max(now, Ordering::SeqCst);
+                            sleep(Duration::from_millis(10)).await;
+                            in_flight.fetch_sub(1, Ordering::SeqCst);
+                        })
+                        .await
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert_eq!(peak.load(Ordering::SeqCst), 3);
+        assert_eq!(in_flight.load(Ordering::SeqCst), 0);
+    }
+}
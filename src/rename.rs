@@ -1,185 +1,3064 @@
-use serde_json::Value;
+use regex::Regex;
+use serde_json::{Map, Value};
+use std::str::FromStr;
 
-/// Recursively rename a field in a JSON document, including nested object arrays
+/// Top-level field holding the attachment stubs of a CouchDB document
+pub const ATTACHMENTS: &str = "_attachments";
+
+/// Options that adjust how `rename_nested_field_with_options` walks a document.
+#[derive(Debug, Clone, Default)]
+pub struct RenameOptions {
+    pub max_array_depth: Option<usize>, // Maximum number of array levels to descend into (None = unlimited)
+    pub merge: Option<MergePolicy>, // Merge into an existing destination object instead of overwriting it
+    pub ignore_case: bool,          // Match the old field path segments case-insensitively
+    pub case_conflict: CaseConflict, // What to do when several case variants of the old field coexist
+    pub backup_suffix: Option<String>, // Keep a copy of each original value under `<old_key><suffix>`
+    pub include_attachments: bool, // Let renames reach into `_attachments`, whose stubs are otherwise left untouched
+    pub on_conflict: OnConflict, // What to do when the new field already exists and is not merged into
+}
+
+/// What a rename does when the destination key already exists in the object, and the old field
+/// is not merged into it (see `RenameOptions::merge`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnConflict {
+    Skip, // Leave the object untouched; the document is not updated
+    #[default]
+    Overwrite, // Replace the existing value with the renamed one
+    Error, // Leave the object untouched and report the document as failed
+}
+
+impl FromStr for OnConflict {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(OnConflict::Skip),
+            "overwrite" => Ok(OnConflict::Overwrite),
+            "error" => Ok(OnConflict::Error),
+            _ => Err(format!(
+                "Unknown conflict policy '{}'. Expected one of: skip, overwrite, error.",
+                s
+            )),
+        }
+    }
+}
+
+/// How a case-insensitive rename handles several case variants of the old field in the same object
+/// (e.g. `UserId` and `userid`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CaseConflict {
+    Merge, // Move every variant to the new name; the first one in document order wins, objects are merged
+    #[default]
+    Error, // Leave the object untouched and report the ambiguity
+}
+
+impl FromStr for CaseConflict {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "merge" => Ok(CaseConflict::Merge),
+            "error" => Ok(CaseConflict::Error),
+            _ => Err(format!(
+                "Unknown case conflict policy '{}'. Expected one of: merge, error.",
+                s
+            )),
+        }
+    }
+}
+
+/// How conflicting keys are resolved when merging the old field's object into an existing destination object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergePolicy {
+    KeepOld, // Values from the old (renamed) field win
+    KeepNew, // Values already present in the destination field win
+    Error,   // Conflicting keys abort the rename of the document
+}
+
+impl FromStr for MergePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep-old" => Ok(MergePolicy::KeepOld),
+            "keep-new" => Ok(MergePolicy::KeepNew),
+            "error" => Ok(MergePolicy::Error),
+            _ => Err(format!(
+                "Unknown merge policy '{}'. Expected one of: keep-old, keep-new, error.",
+                s
+            )),
+        }
+    }
+}
+
+/// A built-in transformation applied to a value as it is moved to the new field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValueTransform {
+    SplitOn(String), // Split a delimited string into an array of trimmed, non-empty strings
+    Lower,           // Lowercase a string
+    Upper,           // Uppercase a string
+    Trim,            // Strip leading and trailing whitespace from a string
+    ToNumber,        // Parse a string holding a number, e.g. `" 42 "`, into a JSON number
+    ToString,        // Write a number or boolean as a string
+}
+
+impl FromStr for ValueTransform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lower" | "lowercase" => Ok(ValueTransform::Lower),
+            "upper" | "uppercase" => Ok(ValueTransform::Upper),
+            "trim" => Ok(ValueTransform::Trim),
+            "to-number" => Ok(ValueTransform::ToNumber),
+            "to-string" => Ok(ValueTransform::ToString),
+            _ => Err(format!(
+                "Unknown transform '{}'. Expected one of: lower, upper, trim, to-number, to-string.",
+                s
+            )),
+        }
+    }
+}
+
+impl ValueTransform {
+    /// Applies the transformation to a value.
+    /// Values the transformation does not apply to are handed back unchanged as the error.
+    pub fn apply(&self, value: Value) -> Result<Value, Value> {
+        match (self, value) {
+            (ValueTransform::SplitOn(delimiter), Value::String(s)) => Ok(Value::Array(
+                s.split(delimiter.as_str())
+                    .map(str::trim)
+                    .filter(|part| !part.is_empty())
+                    .map(|part| Value::String(part.to_string()))
+                    .collect(),
+            )),
+            (ValueTransform::Lower, Value::String(s)) => Ok(Value::String(s.to_lowercase())),
+            (ValueTransform::Upper, Value::String(s)) => Ok(Value::String(s.to_uppercase())),
+            (ValueTransform::Trim, Value::String(s)) => Ok(Value::String(s.trim().to_string())),
+            (ValueTransform::ToNumber, Value::String(s)) => {
+                match serde_json::from_str::<serde_json::Number>(s.trim()) {
+                    Ok(number) => Ok(Value::Number(number)),
+                    Err(_) => Err(Value::String(s)),
+                }
+            }
+            (ValueTransform::ToString, value @ (Value::Number(_) | Value::Bool(_))) => {
+                Ok(Value::String(value.to_string()))
+            }
+            (_, value) => Err(value),
+        }
+    }
+}
+
+/// What happens to an empty value (`""` or `null`) moved by a rename.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnEmpty {
+    #[default]
+    Keep, // Move empty values as they are
+    Null, // Move empty strings as `null`
+    Drop, // Remove the field instead of renaming it
+}
+
+impl FromStr for OnEmpty {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(OnEmpty::Keep),
+            "null" => Ok(OnEmpty::Null),
+            "drop" => Ok(OnEmpty::Drop),
+            _ => Err(format!(
+                "Unknown empty-value policy '{}'. Expected one of: keep, null, drop.",
+                s
+            )),
+        }
+    }
+}
+
+/// Whether a value counts as empty for `OnEmpty`: an empty string or `null`.
+pub fn is_empty_value(value: &Value) -> bool {
+    matches!(value, Value::Null) || value.as_str().is_some_and(str::is_empty)
+}
+
+/// A replacement applied in place to the values of a field, from `--replace-value FROM:TO`.
+#[derive(Debug, Clone)]
+pub enum ValueReplacement {
+    Equals { from: Value, to: Value }, // Replace values equal to `from` (a string or a number) with `to`
+    Regex { pattern: Regex, to: String }, // Replace the matches of `pattern` within string values; `to` may use `$1`
+}
+
+impl ValueReplacement {
+    /// Parses a `FROM:TO` specification, split at the first colon that is not escaped as `\:`.
+    /// Without `regex`, each side is a number if it parses as one and a string otherwise;
+    /// with `regex`, `FROM` is a regular expression and `TO` its replacement.
+    pub fn parse(spec: &str, regex: bool) -> Result<Self, String> {
+        let Some((from, to)) = split_replacement(spec) else {
+            return Err(format!(
+                "Invalid replacement '{}': expected 'FROM:TO'",
+                spec
+            ));
+        };
+
+        if regex {
+            let pattern = Regex::new(&from)
+                .map_err(|e| format!("Invalid replacement pattern '{}': {}", from, e))?;
+            return Ok(ValueReplacement::Regex {
+                pattern,
+                to: to.to_string(),
+            });
+        }
+
+        Ok(ValueReplacement::Equals {
+            from: scalar_value(&from),
+            to: scalar_value(to),
+        })
+    }
+
+    /// Applies the replacement to a value.
+    /// Values the replacement does not match are handed back unchanged as the error.
+    pub fn apply(&self, value: Value) -> Result<Value, Value> {
+        match (self, value) {
+            (ValueReplacement::Equals { from, to }, value) if value == *from => Ok(to.clone()),
+            (ValueReplacement::Regex { pattern, to }, Value::String(s)) if pattern.is_match(&s) => {
+                Ok(Value::String(
+                    pattern.replace_all(&s, to.as_str()).into_owned(),
+                ))
+            }
+            (_, value) => Err(value),
+        }
+    }
+}
+
+/// Splits a `FROM:TO` specification at the first colon not escaped as `\:`, and unescapes the
+/// colons of `FROM`. Other backslash sequences are kept as they are, e.g. for a regex.
+fn split_replacement(spec: &str) -> Option<(String, &str)> {
+    let mut from = String::new();
+    let mut chars = spec.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            ':' => return Some((from, &spec[i + 1..])),
+            '\\' => match chars.next() {
+                Some((_, ':')) => from.push(':'),
+                Some((_, escaped)) => {
+                    from.push('\\');
+                    from.push(escaped);
+                }
+                None => from.push('\\'),
+            },
+            c => from.push(c),
+        }
+    }
+    None
+}
+
+/// Reads one side of a `FROM:TO` replacement: a JSON number if it is one, a string otherwise.
+fn scalar_value(s: &str) -> Value {
+    match serde_json::from_str::<Value>(s) {
+        Ok(Value::Number(n)) => Value::Number(n),
+        _ => Value::String(s.to_string()),
+    }
+}
+
+/// Splits a dot-notation field path into segments.
+///
+/// A `[N]` suffix on a key is a segment of its own that addresses element `N` of an array
+/// (`items[0].name` is `items`, `[0]`, `name`). A bare numeric segment is an object key
+/// (`codes.0.name` reads the key `"0"` of `codes`) unless it meets an array, in which case it
+/// also addresses that element (`items.0.name`). Other arrays on the path are descended into
+/// element by element, as before.
+///
+/// A bare `*` segment is a wildcard for every element of an array or every value of an object
+/// (`items.*.price`); `\*` names a key that is literally `*`.
+pub fn split_path(path: &str) -> Vec<&str> {
+    let mut segments = Vec::new();
+    for part in path.split('.') {
+        let mut key = part;
+        let mut indices = Vec::new();
+        while let Some(open) = key.rfind('[') {
+            if array_index(&key[open..]).is_none() {
+                break;
+            }
+            indices.push(&key[open..]);
+            key = &key[..open];
+        }
+        if !key.is_empty() || indices.is_empty() {
+            segments.push(key);
+        }
+        segments.extend(indices.into_iter().rev());
+    }
+    segments
+}
+
+/// The array index addressed by a `[N]` path segment, or `None` for an object key.
+pub fn array_index(segment: &str) -> Option<usize> {
+    segment.strip_prefix('[')?.strip_suffix(']')?.parse().ok()
+}
+
+/// The element addressed by a bare numeric segment met on an array, e.g. `1` in `tags.1.name`.
+/// On an object the same segment is a key, and a path never ends with an index.
+fn bare_index(segment: &str, remaining_path: &[&str]) -> Option<usize> {
+    match remaining_path.is_empty() || !segment.bytes().all(|b| b.is_ascii_digit()) {
+        true => None,
+        false => segment.parse().ok(),
+    }
+}
+
+/// Path segment matching every element of an array or every value of an object
+pub const WILDCARD: &str = "*";
+
+/// The object key named by a path segment: `\*` escapes a key that is literally `*`.
+pub fn literal_key(segment: &str) -> &str {
+    match segment {
+        "\\*" => WILDCARD,
+        _ => segment,
+    }
+}
+
+/// Counts of what happened while renaming a field in a single document.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RenameStats {
+    pub renamed: usize,    // Fields moved to the new name
+    pub merged: usize,     // Fields merged into an existing destination object
+    pub conflicts: usize,  // Merges refused because of conflicting keys (`MergePolicy::Error`)
+    pub ambiguous: usize, // Objects left untouched because several case variants coexist (`CaseConflict::Error`)
+    pub backups: usize, // Original values copied to a backup field (`RenameOptions::backup_suffix`)
+    pub collisions: usize, // Renames refused because the new field already exists (`OnConflict::Skip`/`Error`)
+}
+
+impl RenameStats {
+    /// Whether the document was modified.
+    pub fn changed(&self) -> bool {
+        self.renamed + self.merged > 0
+    }
+
+    /// Whether the old field was found at all, even if the rename was refused.
+    pub fn found(&self) -> bool {
+        self.changed() || self.conflicts > 0 || self.ambiguous > 0 || self.collisions > 0
+    }
+}
+
+/// Counts of what happened while promoting a field in a single document.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PromoteStats {
+    pub promoted: usize,  // Values moved up to the destination
+    pub pruned: usize,    // Parent objects removed because the move left them empty
+    pub conflicts: usize, // Values left in place because the destination key already exists
+}
+
+/// Recursively rename a field in a JSON document, including nested object arrays.
+/// The renamed field keeps its position among the keys of its object.
+///
+/// A path ending at a key that holds an array renames that key, whatever the array contains;
+/// arrays are only descended into while path segments remain, to rename a field inside their elements.
 pub fn rename_nested_field(doc: &mut Value, old_field_path: &[&str], new_field: &str) -> bool {
+    rename_nested_field_with_options(doc, old_field_path, new_field, &RenameOptions::default())
+}
+
+/// Recursively rename a field in a JSON document, honoring the given `RenameOptions`
+pub fn rename_nested_field_with_options(
+    doc: &mut Value,
+    old_field_path: &[&str],
+    new_field: &str,
+    options: &RenameOptions,
+) -> bool {
+    rename_nested_field_with_stats(doc, old_field_path, new_field, options).changed()
+}
+
+/// Recursively rename a field in a JSON document, returning what happened at each occurrence
+pub fn rename_nested_field_with_stats(
+    doc: &mut Value,
+    old_field_path: &[&str],
+    new_field: &str,
+    options: &RenameOptions,
+) -> RenameStats {
+    rename_nested_field_with_transform(doc, old_field_path, new_field, options, &|value| value)
+}
+
+/// Recursively rename a field in a JSON document, passing each moved value through `value_fn`
+/// before it is inserted under the new name (e.g. to trim strings or map enum values).
+pub fn rename_nested_field_with(
+    doc: &mut Value,
+    old_field_path: &[&str],
+    new_field: &str,
+    value_fn: impl Fn(Value) -> Value,
+) -> bool {
+    rename_nested_field_with_transform(
+        doc,
+        old_field_path,
+        new_field,
+        &RenameOptions::default(),
+        &value_fn,
+    )
+    .changed()
+}
+
+/// Recursively rename a field in a JSON document, honoring the given `RenameOptions` and
+/// transforming each moved value with `value_fn`. Objects merged into an existing destination
+/// (see `RenameOptions::merge`) are moved as they are. Only the last segment of `new_field`
+/// is used: check its prefix beforehand with `ignored_new_field_prefix`.
+pub fn rename_nested_field_with_transform(
+    doc: &mut Value,
+    old_field_path: &[&str],
+    new_field: &str,
+    options: &RenameOptions,
+    value_fn: &dyn Fn(Value) -> Value,
+) -> RenameStats {
+    if !options.include_attachments && touches_attachments(old_field_path, new_field) {
+        return RenameStats::default();
+    }
+
+    let mut stats = RenameStats::default();
+    rename_at_depth(
+        doc,
+        old_field_path,
+        new_field,
+        options,
+        value_fn,
+        0,
+        &mut stats,
+    );
+    stats
+}
+
+/// Returns the parent path of `new_field` if it has one that differs from the parent of `old_field_path`.
+/// The rename only uses the last segment of `new_field`, so such a prefix is silently ignored.
+/// A `new_field` without a parent (a bare key) is always accepted.
+pub fn ignored_new_field_prefix<'n>(
+    old_field_path: &[&str],
+    new_field: &'n str,
+) -> Option<&'n str> {
+    let (prefix, _) = new_field.rsplit_once('.')?;
+    let old_parent = old_field_path
+        .split_last()
+        .map(|(_, parent)| parent)
+        .unwrap_or_default();
+
+    if split_path(prefix) == old_parent {
+        None
+    } else {
+        Some(prefix)
+    }
+}
+
+/// Recursive worker for `rename_nested_field_with_transform`, tracking the current array depth
+fn rename_at_depth(
+    doc: &mut Value,
+    old_field_path: &[&str],
+    new_field: &str,
+    options: &RenameOptions,
+    value_fn: &dyn Fn(Value) -> Value,
+    array_depth: usize,
+    stats: &mut RenameStats,
+) {
     if old_field_path.is_empty() {
-        return false; // Invalid path
+        return; // Invalid path
+    }
+
+    let (current_key, remaining_path) = old_field_path.split_first().unwrap();
+
+    // An index segment only descends into that element of an array
+    if let Some(index) = array_index(current_key) {
+        let within_depth = options.max_array_depth.is_none_or(|max| array_depth < max);
+        if let (Value::Array(arr), true) = (&mut *doc, within_depth) {
+            if let Some(item) = arr.get_mut(index) {
+                rename_at_depth(
+                    item,
+                    remaining_path,
+                    new_field,
+                    options,
+                    value_fn,
+                    array_depth + 1,
+                    stats,
+                );
+            }
+        }
+        return;
+    }
+
+    // A wildcard segment descends into every element of an array or every value of an object
+    if *current_key == WILDCARD {
+        if remaining_path.is_empty() {
+            return; // Invalid path: a wildcard cannot be renamed
+        }
+        match doc {
+            Value::Object(obj) => {
+                for value in obj.values_mut() {
+                    rename_at_depth(
+                        value,
+                        remaining_path,
+                        new_field,
+                        options,
+                        value_fn,
+                        array_depth,
+                        stats,
+                    );
+                }
+            }
+            Value::Array(arr) if options.max_array_depth.is_none_or(|max| array_depth < max) => {
+                for item in arr {
+                    rename_at_depth(
+                        item,
+                        remaining_path,
+                        new_field,
+                        options,
+                        value_fn,
+                        array_depth + 1,
+                        stats,
+                    );
+                }
+            }
+            _ => {}
+        }
+        return;
+    }
+    let current_key = &literal_key(current_key);
+
+    match doc {
+        Value::Object(obj) => {
+            if remaining_path.is_empty() {
+                // use the last component of new_field as the new field name
+                let new_key = literal_key(new_field.split('.').next_back().unwrap());
+
+                // Base case: Rename the field
+                rename_in_object(obj, current_key, new_key, options, value_fn, stats);
+            } else if options.ignore_case {
+                // Recursive case: Traverse every case variant of the key
+                for (key, value) in obj.iter_mut() {
+                    if key.to_lowercase() == current_key.to_lowercase() {
+                        rename_at_depth(
+                            value,
+                            remaining_path,
+                            new_field,
+                            options,
+                            value_fn,
+                            array_depth,
+                            stats,
+                        );
+                    }
+                }
+            } else if let Some(value) = obj.get_mut(*current_key) {
+                // Recursive case: Traverse deeper
+                rename_at_depth(
+                    value,
+                    remaining_path,
+                    new_field,
+                    options,
+                    value_fn,
+                    array_depth,
+                    stats,
+                );
+            }
+        }
+        Value::Array(arr) => {
+            // Stop descending once the configured array depth has been reached
+            if options
+                .max_array_depth
+                .is_some_and(|max| array_depth >= max)
+            {
+                return;
+            }
+
+            // A bare number met on an array, rather than on an object, descends into that element
+            if let Some(index) = bare_index(current_key, remaining_path) {
+                if let Some(item) = arr.get_mut(index) {
+                    rename_at_depth(
+                        item,
+                        remaining_path,
+                        new_field,
+                        options,
+                        value_fn,
+                        array_depth + 1,
+                        stats,
+                    );
+                }
+                return;
+            }
+
+            // Process each element in the array recursively; only object elements can hold the
+            // remaining path, so arrays of scalars are left as they are
+            for item in arr {
+                rename_at_depth(
+                    item,
+                    old_field_path,
+                    new_field,
+                    options,
+                    value_fn,
+                    array_depth + 1,
+                    stats,
+                );
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Renames `old_key` to `new_key` within an object, if present (or any case variant of it, with
+/// `options.ignore_case`). Whether an existing `new_key` refuses the rename (`options.on_conflict`)
+/// is decided before anything is moved.
+fn rename_in_object(
+    obj: &mut Map<String, Value>,
+    old_key: &str,
+    new_key: &str,
+    options: &RenameOptions,
+    value_fn: &dyn Fn(Value) -> Value,
+    stats: &mut RenameStats,
+) {
+    let moved = match options.ignore_case {
+        true => case_variant_to_move(obj, old_key, new_key),
+        false => obj.get_key_value(old_key).map(|(key, _)| key.as_str()),
+    };
+    let Some(moved) = moved else {
+        return;
+    };
+
+    if destination_taken(obj, moved, new_key, options) {
+        stats.collisions += 1;
+    } else if options.ignore_case {
+        rename_case_variants(obj, old_key, new_key, options, value_fn, stats);
+    } else {
+        rename_key(obj, old_key, new_key, options, value_fn, stats);
+    }
+}
+
+/// The first case variant of `old_key` that a case-insensitive rename moves to `new_key`, if any.
+/// `new_key` is left out when it is a variant itself: it already holds the renamed value.
+fn case_variant_to_move<'o>(
+    obj: &'o Map<String, Value>,
+    old_key: &str,
+    new_key: &str,
+) -> Option<&'o str> {
+    let old_key = old_key.to_lowercase();
+    obj.keys()
+        .map(String::as_str)
+        .find(|key| *key != new_key && key.to_lowercase() == old_key)
+}
+
+/// Whether renaming `old_key` to `new_key` is refused by `options.on_conflict` because `new_key`
+/// already exists. With `ignore_case`, a case variant of the old key under the new name does not
+/// count, nor does a destination object the old field is merged into.
+fn destination_taken(
+    obj: &Map<String, Value>,
+    old_key: &str,
+    new_key: &str,
+    options: &RenameOptions,
+) -> bool {
+    let case_variant = options.ignore_case && old_key.to_lowercase() == new_key.to_lowercase();
+    if options.on_conflict == OnConflict::Overwrite || old_key == new_key || case_variant {
+        return false;
+    }
+    let Some(destination) = obj.get(new_key) else {
+        return false;
+    };
+    let merged = options.merge.is_some()
+        && destination.is_object()
+        && obj.get(old_key).is_some_and(Value::is_object);
+    !merged
+}
+
+/// Moves whichever case variants of `old_key` are present to `new_key`, resolving several
+/// coexisting variants according to `options.case_conflict`
+fn rename_case_variants(
+    obj: &mut Map<String, Value>,
+    old_key: &str,
+    new_key: &str,
+    options: &RenameOptions,
+    value_fn: &dyn Fn(Value) -> Value,
+    stats: &mut RenameStats,
+) {
+    let old_key = old_key.to_lowercase();
+    let variants: Vec<String> = obj
+        .keys()
+        .filter(|key| key.to_lowercase() == old_key)
+        .cloned()
+        .collect();
+
+    // The new name itself counts as a variant but never needs to move
+    let to_move: Vec<&String> = variants.iter().filter(|key| *key != new_key).collect();
+
+    if variants.len() > 1 && options.case_conflict == CaseConflict::Error {
+        stats.ambiguous += 1;
+        return;
+    }
+
+    // Later variants are merged into the first one moved (or the existing new field), which wins
+    let merge_options = RenameOptions {
+        merge: Some(MergePolicy::KeepNew),
+        ..options.clone()
+    };
+    for variant in to_move {
+        if !obj.contains_key(new_key) {
+            rename_key(obj, variant, new_key, options, value_fn, stats);
+        } else if obj.get(variant).is_some_and(Value::is_object)
+            && obj.get(new_key).is_some_and(Value::is_object)
+        {
+            rename_key(obj, variant, new_key, &merge_options, value_fn, stats);
+        } else {
+            // A value that cannot be merged loses to the one already under the new name
+            backup_key(obj, variant, new_key, options, stats);
+            obj.shift_remove(variant);
+            stats.merged += 1;
+        }
+    }
+}
+
+/// Moves `old_key` to `new_key` within an object, merging into an existing destination object if requested
+fn rename_key(
+    obj: &mut Map<String, Value>,
+    old_key: &str,
+    new_key: &str,
+    options: &RenameOptions,
+    value_fn: &dyn Fn(Value) -> Value,
+    stats: &mut RenameStats,
+) {
+    if let Some(policy) = options.merge {
+        let mergeable = old_key != new_key
+            && obj.get(old_key).is_some_and(Value::is_object)
+            && obj.get(new_key).is_some_and(Value::is_object);
+
+        if mergeable {
+            // Refuse the merge before modifying anything if keys conflict
+            if policy == MergePolicy::Error {
+                let source = obj[old_key].as_object().unwrap();
+                let destination = obj[new_key].as_object().unwrap();
+                if source.keys().any(|key| destination.contains_key(key)) {
+                    stats.conflicts += 1;
+                    return;
+                }
+            }
+
+            backup_key(obj, old_key, new_key, options, stats);
+            let Some(Value::Object(source)) = obj.shift_remove(old_key) else {
+                return;
+            };
+            let Some(Value::Object(destination)) = obj.get_mut(new_key) else {
+                return;
+            };
+            for (key, value) in source {
+                if policy == MergePolicy::KeepNew && destination.contains_key(&key) {
+                    continue;
+                }
+                destination.insert(key, value);
+            }
+            stats.merged += 1;
+            return;
+        }
+    }
+
+    backup_key(obj, old_key, new_key, options, stats);
+
+    // Reinsert the renamed field at its original position so the key order is preserved
+    let Some(index) = obj.keys().position(|key| key == old_key) else {
+        return;
+    };
+    let Some(value) = obj.shift_remove(old_key) else {
+        return;
+    };
+    let index = match obj.keys().position(|key| key == new_key) {
+        // An existing destination is overwritten; account for its removal before the insertion point
+        Some(existing) => {
+            obj.shift_remove(new_key);
+            if existing < index {
+                index - 1
+            } else {
+                index
+            }
+        }
+        None => index,
+    };
+    obj.shift_insert(index, new_key.to_string(), value_fn(value));
+    stats.renamed += 1;
+}
+
+/// Copies the value of `old_key` to `<old_key><suffix>` before it is moved, if a backup suffix is set.
+/// An existing backup field is overwritten in place; a new one is appended to the object.
+fn backup_key(
+    obj: &mut Map<String, Value>,
+    old_key: &str,
+    new_key: &str,
+    options: &RenameOptions,
+    stats: &mut RenameStats,
+) {
+    let Some(suffix) = &options.backup_suffix else {
+        return;
+    };
+    let backup = format!("{}{}", old_key, suffix);
+    if old_key == new_key || backup == new_key {
+        return;
+    }
+    let Some(value) = obj.get(old_key).cloned() else {
+        return;
+    };
+
+    obj.insert(backup, value);
+    stats.backups += 1;
+}
+
+/// Renames every key equal to `old_key` to `new_key`, at any depth of the document
+/// (objects and arrays alike), honoring the given `RenameOptions` at each occurrence
+/// except `max_array_depth`. Returns what happened across all occurrences.
+pub fn rename_key_anywhere(
+    doc: &mut Value,
+    old_key: &str,
+    new_key: &str,
+    options: &RenameOptions,
+    value_fn: &dyn Fn(Value) -> Value,
+) -> RenameStats {
+    // The attachment stubs are set aside so that nothing in them is renamed
+    let attachments = match doc {
+        Value::Object(obj) if !options.include_attachments => obj
+            .keys()
+            .position(|key| key == ATTACHMENTS)
+            .and_then(|index| Some((index, obj.shift_remove(ATTACHMENTS)?))),
+        _ => None,
+    };
+
+    let mut stats = RenameStats::default();
+    rename_anywhere(doc, old_key, new_key, options, value_fn, &mut stats);
+
+    if let (Some((index, attachments)), Value::Object(obj)) = (attachments, doc) {
+        let index = index.min(obj.len());
+        obj.shift_insert(index, ATTACHMENTS.to_string(), attachments);
+    }
+    stats
+}
+
+/// Whether renaming `old_field_path` to `new_field` would read or write the `_attachments` field:
+/// the old path starts with it, or a top-level field would be renamed to it.
+pub fn touches_attachments(old_field_path: &[&str], new_field: &str) -> bool {
+    old_field_path.first() == Some(&ATTACHMENTS)
+        || (old_field_path.len() == 1 && new_field.split('.').next_back() == Some(ATTACHMENTS))
+}
+
+/// Recursive worker for `rename_key_anywhere`
+fn rename_anywhere(
+    doc: &mut Value,
+    old_key: &str,
+    new_key: &str,
+    options: &RenameOptions,
+    value_fn: &dyn Fn(Value) -> Value,
+    stats: &mut RenameStats,
+) {
+    match doc {
+        Value::Object(obj) => {
+            rename_in_object(obj, old_key, new_key, options, value_fn, stats);
+
+            // Occurrences nested in the values, including the renamed one, are renamed too
+            for value in obj.values_mut() {
+                rename_anywhere(value, old_key, new_key, options, value_fn, stats);
+            }
+        }
+        Value::Array(arr) => {
+            for item in arr {
+                rename_anywhere(item, old_key, new_key, options, value_fn, stats);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Renames every key matching `pattern` to its replacement by `template`, which may refer to the
+/// capture groups of the pattern (`$1`, `${name}`), at any depth of the document. The options are
+/// honored at each key like `rename_key_anywhere` does, except `ignore_case`: the pattern decides
+/// how case is matched (e.g. `(?i)`). The top-level CouchDB fields (`_id`, `_rev`, ...) are never
+/// renamed, and keys the replacement would leave unchanged or empty are left alone.
+pub fn rename_matching_fields(
+    doc: &mut Value,
+    pattern: &Regex,
+    template: &str,
+    options: &RenameOptions,
+    value_fn: &dyn Fn(Value) -> Value,
+) -> RenameStats {
+    let options = RenameOptions {
+        ignore_case: false,
+        ..options.clone()
+    };
+    let mut stats = RenameStats::default();
+    rename_matching(doc, pattern, template, &options, value_fn, true, &mut stats);
+    stats
+}
+
+/// Recursive worker for `rename_matching_fields`
+fn rename_matching(
+    doc: &mut Value,
+    pattern: &Regex,
+    template: &str,
+    options: &RenameOptions,
+    value_fn: &dyn Fn(Value) -> Value,
+    top_level: bool,
+    stats: &mut RenameStats,
+) {
+    match doc {
+        Value::Object(obj) => {
+            let renames: Vec<(String, String)> = obj
+                .keys()
+                .filter(|key| !(top_level && key.starts_with('_')))
+                .filter_map(|key| {
+                    let new_key = pattern.replace_all(key, template);
+                    (!new_key.is_empty() && new_key != key.as_str())
+                        .then(|| (key.clone(), new_key.into_owned()))
+                })
+                .collect();
+            for (old_key, new_key) in renames {
+                rename_in_object(obj, &old_key, &new_key, options, value_fn, stats);
+            }
+
+            // Keys nested in the values, including the renamed ones, are matched too
+            for (key, value) in obj.iter_mut() {
+                if top_level && key == ATTACHMENTS && !options.include_attachments {
+                    continue;
+                }
+                rename_matching(value, pattern, template, options, value_fn, false, stats);
+            }
+        }
+        Value::Array(arr) => {
+            for item in arr {
+                rename_matching(item, pattern, template, options, value_fn, false, stats);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Rename the first candidate field present in a JSON document to `new_field`.
+/// Candidates are tried in order; returns the index of the candidate that was found, if any,
+/// along with what happened to it.
+pub fn rename_first_match(
+    doc: &mut Value,
+    candidates: &[&[&str]],
+    new_field: &str,
+    options: &RenameOptions,
+) -> Option<(usize, RenameStats)> {
+    rename_first_match_with_transform(doc, candidates, new_field, options, &|value| value)
+}
+
+/// Like `rename_first_match`, transforming each moved value with `value_fn`.
+pub fn rename_first_match_with_transform(
+    doc: &mut Value,
+    candidates: &[&[&str]],
+    new_field: &str,
+    options: &RenameOptions,
+    value_fn: &dyn Fn(Value) -> Value,
+) -> Option<(usize, RenameStats)> {
+    candidates
+        .iter()
+        .enumerate()
+        .map(|(index, old_field_path)| {
+            (
+                index,
+                rename_nested_field_with_transform(
+                    doc,
+                    old_field_path,
+                    new_field,
+                    options,
+                    value_fn,
+                ),
+            )
+        })
+        .find(|(_, stats)| stats.found())
+}
+
+/// Recursively copy a field of a JSON document to a new name, including nested object arrays,
+/// leaving the original in place. Only the last segment of `new_field` is used.
+///
+/// The copy is inserted right after the original. An existing field of that name holding another
+/// value is replaced in place, or left alone and counted as a collision, according to
+/// `on_conflict`. A field that already holds the same value as the original is left alone, so
+/// copying twice changes nothing. The copies made are counted as `renamed`.
+pub fn copy_nested_field(
+    doc: &mut Value,
+    old_field_path: &[&str],
+    new_field: &str,
+    on_conflict: OnConflict,
+) -> RenameStats {
+    let new_key = new_field.split('.').next_back().unwrap();
+    let mut stats = RenameStats::default();
+    for_each_parent(doc, old_field_path, &mut |obj, old_key| {
+        // Copy the field, unless the copy is already there
+        let Some(index) = obj.keys().position(|key| key == old_key) else {
+            return;
+        };
+        let value = obj[old_key].clone();
+        match obj.get_mut(new_key) {
+            Some(existing) if *existing == value => {}
+            Some(_) if on_conflict != OnConflict::Overwrite => stats.collisions += 1,
+            Some(existing) => {
+                *existing = value;
+                stats.renamed += 1;
+            }
+            None => {
+                obj.shift_insert(index + 1, new_key.to_string(), value);
+                stats.renamed += 1;
+            }
+        }
+    });
+    stats
+}
+
+/// Calls `f` with every object holding the last key of `field_path` (which need not be present),
+/// and that key, following the same rules as `find_nested_values`: object arrays are descended
+/// into element by element, `[N]` (or a bare number met on an array) selects an element, `*`
+/// every element of an array or value of an object, and `\*` names a key that is literally `*`.
+fn for_each_parent(
+    doc: &mut Value,
+    field_path: &[&str],
+    f: &mut dyn FnMut(&mut Map<String, Value>, &str),
+) {
+    let Some((current_key, remaining_path)) = field_path.split_first() else {
+        return; // Invalid path
+    };
+
+    if let Some(index) = array_index(current_key) {
+        if let Some(item) = doc.as_array_mut().and_then(|arr| arr.get_mut(index)) {
+            for_each_parent(item, remaining_path, f);
+        }
+        return;
+    }
+
+    if *current_key == WILDCARD {
+        if remaining_path.is_empty() {
+            return; // Invalid path: a wildcard is not a field
+        }
+        match doc {
+            Value::Object(obj) => {
+                for value in obj.values_mut() {
+                    for_each_parent(value, remaining_path, f);
+                }
+            }
+            Value::Array(arr) => {
+                for item in arr {
+                    for_each_parent(item, remaining_path, f);
+                }
+            }
+            _ => {}
+        }
+        return;
+    }
+    let current_key = literal_key(current_key);
+
+    match doc {
+        // Base case: The object that may hold the field
+        Value::Object(obj) if remaining_path.is_empty() => f(obj, current_key),
+        // Recursive case: Traverse deeper
+        Value::Object(obj) => {
+            if let Some(value) = obj.get_mut(current_key) {
+                for_each_parent(value, remaining_path, f);
+            }
+        }
+        Value::Array(arr) => {
+            // A bare number met on an array, rather than on an object, descends into that element
+            if let Some(index) = bare_index(current_key, remaining_path) {
+                if let Some(item) = arr.get_mut(index) {
+                    for_each_parent(item, remaining_path, f);
+                }
+                return;
+            }
+
+            // Process each element in the array recursively
+            for item in arr {
+                for_each_parent(item, field_path, f);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Recursively collect every value found at a field path, including nested object arrays
+pub fn find_nested_values<'v>(doc: &'v Value, field_path: &[&str]) -> Vec<&'v Value> {
+    let mut found = Vec::new();
+    collect_nested_values(doc, field_path, &mut found);
+    found
+}
+
+/// Whether a document holds at least one value at a field path, following the same rules as
+/// `find_nested_values` (nested object arrays, indexes, wildcards). The document is left unchanged.
+pub fn field_exists(doc: &Value, field_path: &[&str]) -> bool {
+    !find_nested_values(doc, field_path).is_empty()
+}
+
+/// Recursive worker for `find_nested_values`
+fn collect_nested_values<'v>(doc: &'v Value, field_path: &[&str], found: &mut Vec<&'v Value>) {
+    let Some((current_key, remaining_path)) = field_path.split_first() else {
+        return; // Invalid path
+    };
+
+    if let Some(index) = array_index(current_key) {
+        if let Some(item) = doc.as_array().and_then(|arr| arr.get(index)) {
+            collect_nested_values(item, remaining_path, found);
+        }
+        return;
+    }
+
+    if *current_key == WILDCARD {
+        let values: Box<dyn Iterator<Item = &Value>> = match doc {
+            Value::Object(obj) => Box::new(obj.values()),
+            Value::Array(arr) => Box::new(arr.iter()),
+            _ => return,
+        };
+        for value in values {
+            match remaining_path.is_empty() {
+                true => found.push(value),
+                false => collect_nested_values(value, remaining_path, found),
+            }
+        }
+        return;
+    }
+    let current_key = &literal_key(current_key);
+
+    match doc {
+        Value::Object(obj) => {
+            if let Some(value) = obj.get(*current_key) {
+                if remaining_path.is_empty() {
+                    // Base case: Record the value
+                    found.push(value);
+                } else {
+                    // Recursive case: Traverse deeper
+                    collect_nested_values(value, remaining_path, found);
+                }
+            }
+        }
+        Value::Array(arr) => {
+            if let Some(index) = bare_index(current_key, remaining_path) {
+                if let Some(item) = arr.get(index) {
+                    collect_nested_values(item, remaining_path, found);
+                }
+                return;
+            }
+
+            // Process each element in the array recursively
+            for item in arr {
+                collect_nested_values(item, field_path, found);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Recursively replace every value found at a field path with `value_fn(value)`, in place,
+/// including nested object arrays. Keys are left as they are.
+/// Returns the number of values that `value_fn` actually changed.
+pub fn transform_nested_field(
+    doc: &mut Value,
+    field_path: &[&str],
+    value_fn: &dyn Fn(Value) -> Value,
+) -> usize {
+    let mut changed = 0;
+    for_each_parent(doc, field_path, &mut |obj, key| {
+        let Some(value) = obj.get_mut(key) else {
+            return;
+        };
+        let transformed = value_fn(value.clone());
+        if transformed != *value {
+            *value = transformed;
+            changed += 1;
+        }
+    });
+    changed
+}
+
+/// Moves the value at `old_field_path` up to `new_field_path`, whose parent must be an ancestor
+/// of the old field's parent (e.g. `meta.version` to `version`, or `items.meta.version` to
+/// `items.version` in every element of the `items` array). Arrays are descended into down to
+/// the destination's parent; below it, only objects are walked.
+///
+/// The promoted key takes the position of the outermost wrapper object in its new parent.
+/// A destination that already exists is left alone and counted as a conflict, unless it is
+/// that wrapper and holds nothing but the promoted value (e.g. `data.data` to `data`).
+/// With `prune_empty`, the wrappers left empty by the move are removed.
+pub fn promote_nested_field(
+    doc: &mut Value,
+    old_field_path: &[&str],
+    new_field_path: &[&str],
+    prune_empty: bool,
+) -> PromoteStats {
+    let mut stats = PromoteStats::default();
+    let Some((new_key, ancestor)) = new_field_path.split_last() else {
+        return stats;
+    };
+    if old_field_path.len() <= new_field_path.len() || !old_field_path.starts_with(ancestor) {
+        return stats;
+    }
+
+    let relative = &old_field_path[ancestor.len()..];
+    promote_at(doc, ancestor, relative, new_key, prune_empty, &mut stats);
+    stats
+}
+
+/// Recursive worker for `promote_nested_field`, walking down to the destination's parent objects
+fn promote_at(
+    value: &mut Value,
+    ancestor: &[&str],
+    relative: &[&str],
+    new_key: &str,
+    prune_empty: bool,
+    stats: &mut PromoteStats,
+) {
+    // A bare number met on an array descends into that element
+    if let (Value::Array(items), Some((key, rest))) = (&mut *value, ancestor.split_first()) {
+        if let Some(index) = bare_index(key, relative) {
+            if let Some(item) = items.get_mut(index) {
+                promote_at(item, rest, relative, new_key, prune_empty, stats);
+            }
+            return;
+        }
+    }
+
+    match (value, ancestor.split_first()) {
+        (Value::Array(items), _) => {
+            for item in items {
+                promote_at(item, ancestor, relative, new_key, prune_empty, stats);
+            }
+        }
+        (Value::Object(obj), None) => promote_in_object(obj, relative, new_key, prune_empty, stats),
+        (Value::Object(obj), Some((key, rest))) => {
+            if let Some(child) = obj.get_mut(*key) {
+                promote_at(child, rest, relative, new_key, prune_empty, stats);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Moves the value at the object-only `relative` path (at least two keys) to `new_key` in `obj`.
+fn promote_in_object(
+    obj: &mut Map<String, Value>,
+    relative: &[&str],
+    new_key: &str,
+    prune_empty: bool,
+    stats: &mut PromoteStats,
+) {
+    let wrapper = relative[0];
+    let Some(index) = obj.keys().position(|key| key == wrapper) else {
+        return;
+    };
+    let Some(Value::Object(inner)) = obj.get(wrapper) else {
+        return;
+    };
+    if nested_object_value(inner, &relative[1..]).is_none() {
+        return;
+    }
+
+    // Replacing the wrapper itself is only safe if it holds nothing else
+    let replaces_wrapper = new_key == wrapper;
+    if (replaces_wrapper && !holds_only(inner, &relative[1..]))
+        || (!replaces_wrapper && obj.contains_key(new_key))
+    {
+        stats.conflicts += 1;
+        return;
+    }
+
+    let Some(Value::Object(inner)) = obj.get_mut(wrapper) else {
+        return;
+    };
+    let Some(value) = take_nested(inner, &relative[1..], prune_empty, &mut stats.pruned) else {
+        return;
+    };
+    let wrapper_empty = inner.is_empty();
+    stats.promoted += 1;
+
+    if replaces_wrapper {
+        obj.insert(new_key.to_string(), value);
+        return;
+    }
+    if prune_empty && wrapper_empty {
+        obj.shift_remove(wrapper);
+        stats.pruned += 1;
+    }
+    obj.shift_insert(index, new_key.to_string(), value);
+}
+
+/// Moves the value at `old_field_path` to `new_field_path`, which may be under another parent
+/// (e.g. `a.b.c` to `a.x.c`), creating the missing objects on the way. Arrays are descended
+/// into down to the deepest parent both paths share; below it, only objects are walked, so
+/// `items.meta.sku` to `items.info.sku` moves the field within every element of `items`.
+///
+/// Like a rename, a value already at the destination is overwritten, or left alone and counted
+/// as a collision, according to `on_conflict`. A field is left in place if the destination cannot
+/// be created because a field on the way is not an object. The values moved are counted as
+/// `renamed`.
+pub fn move_nested_field(
+    doc: &mut Value,
+    old_field_path: &[&str],
+    new_field_path: &[&str],
+    on_conflict: OnConflict,
+) -> RenameStats {
+    let mut stats = RenameStats::default();
+    if old_field_path.is_empty() || new_field_path.is_empty() {
+        return stats; // Invalid path
+    }
+
+    // Both paths keep at least their last key below the shared parent
+    let shared = old_field_path
+        .iter()
+        .zip(new_field_path)
+        .take_while(|(old, new)| old == new)
+        .count()
+        .min(old_field_path.len() - 1)
+        .min(new_field_path.len() - 1);
+
+    let mut move_one = |obj: &mut Map<String, Value>| {
+        move_in_object(
+            obj,
+            &old_field_path[shared..],
+            &new_field_path[shared..],
+            on_conflict,
+            &mut stats,
+        )
+    };
+    move_at(
+        doc,
+        &old_field_path[..shared],
+        &old_field_path[shared..],
+        &mut move_one,
+    );
+    stats
+}
+
+/// Recursive worker for `move_nested_field`, walking down to the shared parent objects and
+/// calling `f` with each of them.
+fn move_at(
+    value: &mut Value,
+    shared: &[&str],
+    old_rest: &[&str],
+    f: &mut dyn FnMut(&mut Map<String, Value>),
+) {
+    // A bare number met on an array descends into that element
+    if let (Value::Array(items), Some((key, rest))) = (&mut *value, shared.split_first()) {
+        if let Some(index) = bare_index(key, old_rest) {
+            if let Some(item) = items.get_mut(index) {
+                move_at(item, rest, old_rest, f);
+            }
+            return;
+        }
+    }
+
+    match (value, shared.split_first()) {
+        (Value::Array(items), _) => {
+            for item in items {
+                move_at(item, shared, old_rest, f);
+            }
+        }
+        (Value::Object(obj), None) => f(obj),
+        (Value::Object(obj), Some((key, rest))) => {
+            if let Some(child) = obj.get_mut(*key) {
+                move_at(child, rest, old_rest, f);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Moves the value at the object-only path `old_rest` below `obj` to `new_rest`, creating the
+/// missing objects along `new_rest`. An existing value at `new_rest` refuses the move unless
+/// `on_conflict` is `Overwrite`.
+fn move_in_object(
+    obj: &mut Map<String, Value>,
+    old_rest: &[&str],
+    new_rest: &[&str],
+    on_conflict: OnConflict,
+    stats: &mut RenameStats,
+) {
+    if nested_object_value(obj, old_rest).is_none() || !can_hold_nested(obj, new_rest) {
+        return;
+    }
+    if on_conflict != OnConflict::Overwrite && nested_object_value(obj, new_rest).is_some() {
+        stats.collisions += 1;
+        return;
+    }
+    let Some(value) = take_nested(obj, old_rest, false, &mut 0) else {
+        return;
+    };
+
+    let (new_key, parents) = new_rest.split_last().unwrap(); // Never empty
+    let mut target = obj;
+    for key in parents {
+        target = target
+            .entry(key.to_string())
+            .or_insert_with(|| Value::Object(Map::new()))
+            .as_object_mut()
+            .unwrap(); // Checked by `can_hold_nested`; taking the old value only removes a key
+    }
+    target.insert(new_key.to_string(), value);
+    stats.renamed += 1;
+}
+
+/// Whether a value can be set at `path` below `obj`: every existing field on the way is an object.
+fn can_hold_nested(obj: &Map<String, Value>, path: &[&str]) -> bool {
+    let mut current = obj;
+    for key in &path[..path.len().saturating_sub(1)] {
+        match current.get(*key) {
+            None => return true,
+            Some(Value::Object(child)) => current = child,
+            Some(_) => return false,
+        }
+    }
+    true
+}
+
+/// Returns the value at `path` below `obj`, walking objects only.
+fn nested_object_value<'v>(obj: &'v Map<String, Value>, path: &[&str]) -> Option<&'v Value> {
+    let (last, parents) = path.split_last()?;
+    let mut current = obj;
+    for key in parents {
+        current = current.get(*key)?.as_object()?;
+    }
+    current.get(*last)
+}
+
+/// Whether every object along `path` below `obj` holds nothing but the next key of the path.
+fn holds_only(obj: &Map<String, Value>, path: &[&str]) -> bool {
+    match path.split_first() {
+        None => true,
+        Some((key, rest)) => {
+            obj.len() == 1
+                && match obj.get(*key) {
+                    Some(Value::Object(child)) if !rest.is_empty() => holds_only(child, rest),
+                    Some(_) => rest.is_empty(),
+                    None => false,
+                }
+        }
+    }
+}
+
+/// Removes and returns the value at `path` below `obj`, walking objects only.
+/// With `prune_empty`, the objects along the path left empty are removed and counted in `pruned`.
+fn take_nested(
+    obj: &mut Map<String, Value>,
+    path: &[&str],
+    prune_empty: bool,
+    pruned: &mut usize,
+) -> Option<Value> {
+    match path {
+        [] => None,
+        [key] => obj.shift_remove(*key),
+        [key, rest @ ..] => {
+            let Some(Value::Object(child)) = obj.get_mut(*key) else {
+                return None;
+            };
+            let value = take_nested(child, rest, prune_empty, pruned)?;
+            if prune_empty && child.is_empty() {
+                obj.shift_remove(*key);
+                *pruned += 1;
+            }
+            Some(value)
+        }
+    }
+}
+
+/// Recursively removes the objects and arrays that a change from `original` left empty: those
+/// empty now that held something at the same place (key or index) in `original`, including those
+/// only emptied once their own emptied children are removed. Empty objects and arrays that were
+/// already empty, or are new, are kept, and so is the root. Returns whether anything was pruned.
+pub fn prune_empty(doc: &mut Value, original: &Value) -> bool {
+    match (doc, original) {
+        (Value::Object(obj), Value::Object(before)) => {
+            let mut pruned = false;
+            let mut emptied = Vec::new();
+            for (key, value) in obj.iter_mut() {
+                let Some(before) = before.get(key) else {
+                    continue;
+                };
+                pruned |= prune_empty(value, before);
+                if emptied_by_change(value, before) {
+                    emptied.push(key.clone());
+                }
+            }
+            for key in &emptied {
+                obj.shift_remove(key);
+            }
+            pruned || !emptied.is_empty()
+        }
+        (Value::Array(items), Value::Array(before)) => {
+            let mut pruned = false;
+            let mut emptied = Vec::new();
+            for (index, (value, before)) in items.iter_mut().zip(before).enumerate() {
+                pruned |= prune_empty(value, before);
+                if emptied_by_change(value, before) {
+                    emptied.push(index);
+                }
+            }
+            for index in emptied.iter().rev() {
+                items.remove(*index);
+            }
+            pruned || !emptied.is_empty()
+        }
+        _ => false,
+    }
+}
+
+/// Whether `value` is an empty object or array where `before` held a non-empty one.
+fn emptied_by_change(value: &Value, before: &Value) -> bool {
+    match (value, before) {
+        (Value::Object(obj), Value::Object(before)) => obj.is_empty() && !before.is_empty(),
+        (Value::Array(items), Value::Array(before)) => items.is_empty() && !before.is_empty(),
+        _ => false,
+    }
+}
+
+/// Recursively delete a field from a JSON document, including nested object arrays
+pub fn delete_nested_field(doc: &mut Value, field_path: &[&str]) -> bool {
+    let mut deleted = false;
+    for_each_parent(doc, field_path, &mut |obj, key| {
+        deleted |= obj.shift_remove(key).is_some();
+    });
+    deleted
+}
+
+/// Recursively delete a field from a JSON document wherever it holds an empty value
+/// (see `is_empty_value`), including nested object arrays.
+/// Returns the number of fields deleted.
+pub fn drop_empty_values(doc: &mut Value, field_path: &[&str]) -> usize {
+    let mut dropped = 0;
+    for_each_parent(doc, field_path, &mut |obj, key| {
+        if obj.get(key).is_some_and(is_empty_value) {
+            obj.shift_remove(key);
+            dropped += 1;
+        }
+    });
+    dropped
+}
+
+/// Like `drop_empty_values`, but keeps the empty values that `original`, the document before a
+/// rename, already held at the same place: only the empty values the rename produced are deleted.
+/// The rename must only have changed the last key of `field_path`, so that both documents have
+/// the same objects holding it. Returns the number of fields deleted.
+pub fn drop_produced_empty_values(doc: &mut Value, original: &Value, field_path: &[&str]) -> usize {
+    let mut held = Vec::new();
+    for_each_parent(&mut original.clone(), field_path, &mut |obj, key| {
+        held.push(obj.get(key).is_some_and(is_empty_value));
+    });
+
+    let mut held = held.into_iter();
+    let mut dropped = 0;
+    for_each_parent(doc, field_path, &mut |obj, key| {
+        let already_empty = held.next().unwrap_or(false);
+        if !already_empty && obj.get(key).is_some_and(is_empty_value) {
+            obj.shift_remove(key);
+            dropped += 1;
+        }
+    });
+    dropped
+}
+
+/// Unit tests for the application
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_rename_nested_field_simple_object() {
+        let mut doc = json!({
+            "a": {
+                "b": 1
+            }
+        });
+
+        let old_field_path = vec!["a", "b"];
+        let new_field = "new_b";
+
+        let result = rename_nested_field(&mut doc, &old_field_path, new_field);
+
+        assert!(result, "Field renaming should succeed");
+        assert_eq!(
+            doc,
+            json!({
+                "a": {
+                    "new_b": 1
+                }
+            }),
+            "Field 'b' should be renamed to 'new_b'"
+        );
+    }
+
+    #[test]
+    fn test_rename_nested_field_nested_object() {
+        let mut doc = json!({
+            "a": {
+                "b": {
+                    "c": 2
+                }
+            }
+        });
+
+        let old_field_path = vec!["a", "b", "c"];
+        let new_field = "new_c";
+
+        let result = rename_nested_field(&mut doc, &old_field_path, new_field);
+
+        assert!(result, "Field renaming should succeed");
+        assert_eq!(
+            doc,
+            json!({
+                "a": {
+                    "b": {
+                        "new_c": 2
+                    }
+                }
+            }),
+            "Field 'c' should be renamed to 'new_c'"
+        );
+    }
+
+    #[test]
+    fn test_rename_nested_field_array_of_objects() {
+        let mut doc = json!({
+            "a": {
+                "b": [
+                    { "c": 1 },
+                    { "c": 2 }
+                ]
+            }
+        });
+
+        let old_field_path = vec!["a", "b", "c"];
+        let new_field = "new_c";
+
+        let result = rename_nested_field(&mut doc, &old_field_path, new_field);
+
+        assert!(result, "Field renaming should succeed");
+        assert_eq!(
+            doc,
+            json!({
+                "a": {
+                    "b": [
+                        { "new_c": 1 },
+                        { "new_c": 2 }
+                    ]
+                }
+            }),
+            "Fields 'c' in array elements should be renamed to 'new_c'"
+        );
+    }
+
+    #[test]
+    fn test_copy_nested_field_simple_object() {
+        let mut doc = json!({ "a": 1, "b": 2 });
+
+        assert!(copy_nested_field(&mut doc, &["a"], "new_a", OnConflict::Overwrite).changed());
+        assert_eq!(doc, json!({ "a": 1, "new_a": 1, "b": 2 }));
+        assert_eq!(
+            doc.as_object().unwrap().keys().collect::<Vec<_>>(),
+            vec!["a", "new_a", "b"],
+            "The copy follows the original"
+        );
+
+        assert!(
+            !copy_nested_field(&mut doc, &["a"], "new_a", OnConflict::Overwrite).changed(),
+            "Already copied"
+        );
+        assert!(
+            !copy_nested_field(&mut doc, &["missing"], "other", OnConflict::Overwrite).changed()
+        );
+    }
+
+    #[test]
+    fn test_copy_nested_field_nested_object() {
+        let mut doc = json!({ "a": { "b": { "c": 2, "new_c": 1 } } });
+
+        assert!(copy_nested_field(
+            &mut doc,
+            &["a", "b", "c"],
+            "a.b.new_c",
+            OnConflict::Overwrite
+        )
+        .changed());
+        assert_eq!(
+            doc,
+            json!({ "a": { "b": { "c": 2, "new_c": 2 } } }),
+            "An outdated copy is overwritten in place"
+        );
+    }
+
+    #[test]
+    fn test_copy_nested_field_array_of_objects() {
+        let mut doc = json!({
+            "a": {
+                "b": [
+                    { "c": 1 },
+                    { "d": 2 },
+                    { "c": { "x": true } }
+                ]
+            }
+        });
+
+        assert!(
+            copy_nested_field(&mut doc, &["a", "b", "c"], "new_c", OnConflict::Overwrite).changed()
+        );
+        assert_eq!(
+            doc,
+            json!({
+                "a": {
+                    "b": [
+                        { "c": 1, "new_c": 1 },
+                        { "d": 2 },
+                        { "c": { "x": true }, "new_c": { "x": true } }
+                    ]
+                }
+            })
+        );
+
+        let mut doc = json!({ "items": [{ "c": 1 }, { "c": 2 }] });
+        assert!(copy_nested_field(
+            &mut doc,
+            &["items", "[1]", "c"],
+            "new_c",
+            OnConflict::Overwrite
+        )
+        .changed());
+        assert_eq!(
+            doc,
+            json!({ "items": [{ "c": 1 }, { "c": 2, "new_c": 2 }] })
+        );
+    }
+
+    #[test]
+    fn test_rename_nested_field_renames_array_of_scalars() {
+        let mut doc = json!({ "a": { "tags": ["x", "y"], "n": [1, 2] } });
+
+        assert!(rename_nested_field(&mut doc, &["a", "tags"], "labels"));
+        assert!(rename_nested_field(&mut doc, &["a", "n"], "numbers"));
+        assert_eq!(
+            doc,
+            json!({ "a": { "labels": ["x", "y"], "numbers": [1, 2] } }),
+            "Keys holding arrays of scalars should be renamed as a whole"
+        );
+    }
+
+    #[test]
+    fn test_rename_nested_field_array_key_vs_element_field() {
+        let doc = json!({ "items": [{ "items": 1 }, { "qty": 2 }] });
+
+        // A path ending at the array key renames the key, not the fields inside its elements
+        let mut renamed_key = doc.clone();
+        assert!(rename_nested_field(&mut renamed_key, &["items"], "lines"));
+        assert_eq!(
+            renamed_key,
+            json!({ "lines": [{ "items": 1 }, { "qty": 2 }] })
+        );
+
+        // A path continuing past the array key renames the field inside each element
+        let mut renamed_field = doc.clone();
+        assert!(rename_nested_field(
+            &mut renamed_field,
+            &["items", "items"],
+            "count"
+        ));
+        assert_eq!(
+            renamed_field,
+            json!({ "items": [{ "count": 1 }, { "qty": 2 }] })
+        );
+
+        // Scalar elements cannot hold the remaining path
+        let mut scalars = json!({ "tags": ["x", "y"] });
+        assert!(!rename_nested_field(&mut scalars, &["tags", "x"], "z"));
+        assert_eq!(scalars, json!({ "tags": ["x", "y"] }));
+    }
+
+    #[test]
+    fn test_split_path_separates_array_indices() {
+        assert_eq!(split_path("items[0].name"), vec!["items", "[0]", "name"]);
+        assert_eq!(split_path("grid[1][2].v"), vec!["grid", "[1]", "[2]", "v"]);
+        assert_eq!(split_path("codes.0.name"), vec!["codes", "0", "name"]);
+        assert_eq!(split_path("a[x].b"), vec!["a[x]", "b"], "Not an index");
+        assert_eq!(array_index("[3]"), Some(3));
+        assert_eq!(array_index("3"), None);
+    }
+
+    #[test]
+    fn test_rename_wildcard_over_array() {
+        let mut doc = json!({
+            "items": [{ "price": 1 }, { "price": 2, "qty": 3 }, { "qty": 4 }]
+        });
+
+        assert!(rename_nested_field(
+            &mut doc,
+            &split_path("items.*.price"),
+            "items.*.cost"
+        ));
+        assert_eq!(
+            doc,
+            json!({ "items": [{ "cost": 1 }, { "cost": 2, "qty": 3 }, { "qty": 4 }] })
+        );
+    }
+
+    #[test]
+    fn test_rename_wildcard_over_keyed_object() {
+        let mut doc = json!({
+            "items": { "apple": { "price": 1 }, "pear": { "price": 2 }, "*": { "price": 3 } }
+        });
+
+        assert!(rename_nested_field(
+            &mut doc,
+            &split_path("items.*.price"),
+            "items.*.cost"
+        ));
+        assert_eq!(
+            doc,
+            json!({
+                "items": { "apple": { "cost": 1 }, "pear": { "cost": 2 }, "*": { "cost": 3 } }
+            })
+        );
+        assert_eq!(
+            find_nested_values(&doc, &split_path("items.*.cost")).len(),
+            3
+        );
+
+        // An escaped `\*` only matches the key that is literally `*`
+        assert!(rename_nested_field(
+            &mut doc,
+            &split_path("items.\\*.cost"),
+            "items.\\*.price"
+        ));
+        assert_eq!(doc["items"]["*"], json!({ "price": 3 }));
+        assert_eq!(doc["items"]["apple"], json!({ "cost": 1 }));
+    }
+
+    #[test]
+    fn test_rename_numeric_key_vs_array_index() {
+        let doc = json!({
+            "codes": { "0": { "name": "zero" }, "1": { "name": "one" } },
+            "items": [{ "name": "first" }, { "name": "second" }]
+        });
+
+        // A bare `0` is the object key "0"
+        let mut by_key = doc.clone();
+        assert!(rename_nested_field(
+            &mut by_key,
+            &split_path("codes.0.name"),
+            "label"
+        ));
+        assert_eq!(by_key["codes"]["0"], json!({ "label": "zero" }));
+        assert_eq!(by_key["codes"]["1"], json!({ "name": "one" }));
+
+        // A bracketed `[1]` is the second element of the array, and nothing else
+        let mut by_index = doc.clone();
+        assert!(rename_nested_field(
+            &mut by_index,
+            &split_path("items[1].name"),
+            "label"
+        ));
+        assert_eq!(
+            by_index["items"],
+            json!([{ "name": "first" }, { "label": "second" }])
+        );
+
+        // A bracketed index never reads an object key
+        let mut unchanged = doc.clone();
+        assert!(!rename_nested_field(
+            &mut unchanged,
+            &split_path("codes[0].name"),
+            "label"
+        ));
+        assert!(!rename_nested_field(
+            &mut unchanged,
+            &split_path("items[5].name"),
+            "label"
+        ));
+        assert_eq!(unchanged, doc);
+
+        assert_eq!(
+            find_nested_values(&doc, &split_path("items[0].name")),
+            vec![&json!("first")]
+        );
+    }
+
+    #[test]
+    fn test_rename_bare_index_into_array() {
+        let doc = json!({ "a": [{ "b": 1 }, { "b": 2 }] });
+
+        let mut second = doc.clone();
+        assert!(rename_nested_field(&mut second, &split_path("a.1.b"), "c"));
+        assert_eq!(second, json!({ "a": [{ "b": 1 }, { "c": 2 }] }));
+        assert_eq!(
+            find_nested_values(&second, &split_path("a.0.b")),
+            vec![&json!(1)]
+        );
+
+        let mut out_of_bounds = doc.clone();
+        assert!(!rename_nested_field(
+            &mut out_of_bounds,
+            &split_path("a.2.b"),
+            "c"
+        ));
+        assert_eq!(out_of_bounds, doc);
+    }
+
+    #[test]
+    fn test_rename_nested_field_nonexistent_field() {
+        let mut doc = json!({
+            "a": {
+                "b": 1
+            }
+        });
+
+        let old_field_path = vec!["a", "x"];
+        let new_field = "new_x";
+
+        let result = rename_nested_field(&mut doc, &old_field_path, new_field);
+
+        assert!(!result, "Field renaming should fail for nonexistent fields");
+        assert_eq!(
+            doc,
+            json!({
+                "a": {
+                    "b": 1
+                }
+            }),
+            "Document should remain unchanged"
+        );
+    }
+
+    #[test]
+    fn test_rename_nested_field_invalid_path() {
+        let mut doc = json!({
+            "a": {
+                "b": 1
+            }
+        });
+
+        let old_field_path: Vec<&str> = vec![];
+        let new_field = "new_b";
+
+        let result = rename_nested_field(&mut doc, &old_field_path, new_field);
+
+        assert!(!result, "Field renaming should fail for invalid paths");
+        assert_eq!(
+            doc,
+            json!({
+                "a": {
+                    "b": 1
+                }
+            }),
+            "Document should remain unchanged"
+        );
+    }
+
+    #[test]
+    fn test_rename_first_match_uses_first_present_candidate() {
+        let mut doc = json!({
+            "quantity": 1,
+            "amount": 2
+        });
+
+        let candidates: Vec<&[&str]> = vec![&["qty"], &["amount"], &["quantity"]];
+
+        let result = rename_first_match(&mut doc, &candidates, "total", &RenameOptions::default());
+
+        assert_eq!(
+            result.map(|(index, _)| index),
+            Some(1),
+            "Candidate 'amount' should match first"
+        );
+        assert_eq!(
+            doc,
+            json!({
+                "quantity": 1,
+                "total": 2
+            }),
+            "Only the first matching candidate should be renamed"
+        );
+    }
+
+    #[test]
+    fn test_rename_first_match_no_candidate_present() {
+        let mut doc = json!({ "a": 1 });
+
+        let candidates: Vec<&[&str]> = vec![&["qty"], &["amount"]];
+
+        let result =
+            rename_first_match(&mut doc, &candidates, "quantity", &RenameOptions::default());
+
+        assert!(result.is_none(), "No candidate should match");
+        assert_eq!(doc, json!({ "a": 1 }), "Document should remain unchanged");
+    }
+
+    fn two_level_array_doc() -> Value {
+        json!({
+            "a": [
+                { "b": [ { "c": 1 }, { "c": 2 } ] },
+                { "b": [ { "c": 3 } ] }
+            ]
+        })
+    }
+
+    #[test]
+    fn test_rename_max_array_depth_cuts_off_nested_arrays() {
+        let mut doc = two_level_array_doc();
+        let options = RenameOptions {
+            max_array_depth: Some(1),
+            ..Default::default()
+        };
+
+        let result =
+            rename_nested_field_with_options(&mut doc, &["a", "b", "c"], "new_c", &options);
+
+        assert!(!result, "Second array level should not be descended into");
+        assert_eq!(
+            doc,
+            two_level_array_doc(),
+            "Document should remain unchanged"
+        );
+    }
+
+    #[test]
+    fn test_rename_max_array_depth_allows_enough_levels() {
+        let mut doc = two_level_array_doc();
+        let options = RenameOptions {
+            max_array_depth: Some(2),
+            ..Default::default()
+        };
+
+        let result =
+            rename_nested_field_with_options(&mut doc, &["a", "b", "c"], "new_c", &options);
+
+        assert!(
+            result,
+            "Field renaming should succeed within two array levels"
+        );
+        assert_eq!(
+            doc,
+            json!({
+                "a": [
+                    { "b": [ { "new_c": 1 }, { "new_c": 2 } ] },
+                    { "b": [ { "new_c": 3 } ] }
+                ]
+            }),
+            "Fields 'c' in both array levels should be renamed to 'new_c'"
+        );
+    }
+
+    #[test]
+    fn test_rename_max_array_depth_zero_skips_arrays() {
+        let mut doc = json!({
+            "a": { "b": 1 },
+            "list": [ { "b": 2 } ]
+        });
+        let options = RenameOptions {
+            max_array_depth: Some(0),
+            ..Default::default()
+        };
+
+        assert!(rename_nested_field_with_options(
+            &mut doc,
+            &["a", "b"],
+            "new_b",
+            &options
+        ));
+        assert!(!rename_nested_field_with_options(
+            &mut doc,
+            &["list", "b"],
+            "new_b",
+            &options
+        ));
+        assert_eq!(
+            doc,
+            json!({
+                "a": { "new_b": 1 },
+                "list": [ { "b": 2 } ]
+            }),
+            "Only the field outside of arrays should be renamed"
+        );
+    }
+
+    #[test]
+    fn test_find_nested_values_in_object_array() {
+        let doc = json!({
+            "a": [
+                { "b": 1 },
+                { "c": 2 },
+                { "b": "x" }
+            ]
+        });
+
+        let values = find_nested_values(&doc, &["a", "b"]);
+
+        assert_eq!(values, vec![&json!(1), &json!("x")]);
+        assert!(find_nested_values(&doc, &["a", "z"]).is_empty());
+    }
+
+    #[test]
+    fn test_field_exists() {
+        let doc = json!({
+            "user": { "name": { "first": "Ada" }, "tags": [] },
+            "orders": [{ "id": 1 }, { "id": 2, "lines": [{ "sku": null }] }],
+            "empty": {}
+        });
+
+        assert!(field_exists(&doc, &split_path("user.name.first")));
+        assert!(field_exists(&doc, &split_path("user.tags")));
+        assert!(
+            field_exists(&doc, &split_path("orders.lines.sku")),
+            "Null values exist"
+        );
+        assert!(field_exists(&doc, &split_path("orders[1].id")));
+        assert!(field_exists(&doc, &split_path("empty")));
+
+        assert!(!field_exists(&doc, &split_path("user.name.last")));
+        assert!(!field_exists(&doc, &split_path("user.name.first.initial")));
+        assert!(!field_exists(&doc, &split_path("orders[0].lines")));
+        assert!(!field_exists(&doc, &split_path("empty.key")));
+        assert!(!field_exists(&json!([]), &split_path("id")));
+    }
+
+    fn merge_doc() -> Value {
+        json!({
+            "address": { "city": "Paris", "zip": "75001" },
+            "location": { "city": "Lyon", "country": "FR" }
+        })
+    }
+
+    fn merge_options(policy: MergePolicy) -> RenameOptions {
+        RenameOptions {
+            merge: Some(policy),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_merge_keep_old_prefers_renamed_values() {
+        let mut doc = merge_doc();
+
+        let stats = rename_nested_field_with_stats(
+            &mut doc,
+            &["address"],
+            "location",
+            &merge_options(MergePolicy::KeepOld),
+        );
+
+        assert_eq!(stats.merged, 1);
+        assert_eq!(
+            doc,
+            json!({ "location": { "city": "Paris", "zip": "75001", "country": "FR" } })
+        );
+    }
+
+    #[test]
+    fn test_merge_keep_new_prefers_existing_values() {
+        let mut doc = merge_doc();
+
+        let stats = rename_nested_field_with_stats(
+            &mut doc,
+            &["address"],
+            "location",
+            &merge_options(MergePolicy::KeepNew),
+        );
+
+        assert_eq!(stats.merged, 1);
+        assert_eq!(
+            doc,
+            json!({ "location": { "city": "Lyon", "zip": "75001", "country": "FR" } })
+        );
+    }
+
+    #[test]
+    fn test_merge_error_leaves_document_untouched() {
+        let mut doc = merge_doc();
+
+        let stats = rename_nested_field_with_stats(
+            &mut doc,
+            &["address"],
+            "location",
+            &merge_options(MergePolicy::Error),
+        );
+
+        assert_eq!(stats.conflicts, 1);
+        assert!(!stats.changed());
+        assert_eq!(doc, merge_doc(), "Document should remain unchanged");
+    }
+
+    #[test]
+    fn test_merge_without_destination_is_plain_rename() {
+        let mut doc = json!({ "address": { "city": "Paris" } });
+
+        let stats = rename_nested_field_with_stats(
+            &mut doc,
+            &["address"],
+            "location",
+            &merge_options(MergePolicy::Error),
+        );
+
+        assert_eq!(stats.renamed, 1);
+        assert_eq!(doc, json!({ "location": { "city": "Paris" } }));
+    }
+
+    #[test]
+    fn test_rename_preserves_key_order() {
+        let mut doc = json!({ "first": 1, "old": 2, "last": 3 });
+
+        assert!(rename_nested_field(&mut doc, &["old"], "new"));
+        assert_eq!(
+            serde_json::to_string(&doc).unwrap(),
+            r#"{"first":1,"new":2,"last":3}"#,
+            "Renamed field should stay in its original position"
+        );
+    }
+
+    #[test]
+    fn test_rename_over_existing_key_preserves_order() {
+        let mut doc = json!({ "new": 0, "a": 1, "old": 2, "b": 3 });
+
+        assert!(rename_nested_field(&mut doc, &["old"], "new"));
+        assert_eq!(
+            serde_json::to_string(&doc).unwrap(),
+            r#"{"a":1,"new":2,"b":3}"#,
+            "Overwritten field should be replaced at the renamed field's position"
+        );
+    }
+
+    #[test]
+    fn test_on_conflict_with_existing_destination() {
+        let original = json!({ "items": [{ "sku": "a", "code": "b" }, { "code": "c" }] });
+        let on_conflict = |on_conflict| RenameOptions {
+            on_conflict,
+            ..Default::default()
+        };
+
+        let mut doc = original.clone();
+        let stats = rename_nested_field_with_stats(
+            &mut doc,
+            &["items", "code"],
+            "sku",
+            &on_conflict(OnConflict::Overwrite),
+        );
+        assert_eq!(stats.renamed, 2);
+        assert_eq!(doc, json!({ "items": [{ "sku": "b" }, { "sku": "c" }] }));
+
+        for policy in [OnConflict::Skip, OnConflict::Error] {
+            let mut doc = original.clone();
+            let stats = rename_nested_field_with_stats(
+                &mut doc,
+                &["items", "code"],
+                "sku",
+                &on_conflict(policy),
+            );
+            assert_eq!(stats.collisions, 1, "{:?}", policy);
+            assert!(stats.found());
+            assert_eq!(
+                doc["items"][0], original["items"][0],
+                "The existing field is kept with {:?}",
+                policy
+            );
+        }
+
+        // A destination the old field is merged into is not a conflict
+        let mut doc = merge_doc();
+        let options = RenameOptions {
+            on_conflict: OnConflict::Error,
+            ..merge_options(MergePolicy::KeepNew)
+        };
+        let stats = rename_nested_field_with_stats(&mut doc, &["address"], "location", &options);
+        assert_eq!((stats.merged, stats.collisions), (1, 0));
+
+        assert_eq!("skip".parse::<OnConflict>(), Ok(OnConflict::Skip));
+        assert!("replace".parse::<OnConflict>().is_err());
+    }
+
+    #[test]
+    fn test_on_conflict_with_copy_and_move() {
+        let original = json!({ "items": [{ "sku": "a", "code": "b" }, { "code": "c" }] });
+
+        for policy in [OnConflict::Skip, OnConflict::Error] {
+            let mut doc = original.clone();
+            let stats = copy_nested_field(&mut doc, &["items", "code"], "sku", policy);
+            assert_eq!((stats.renamed, stats.collisions), (1, 1), "{:?}", policy);
+            assert_eq!(doc["items"][0], original["items"][0], "{:?}", policy);
+
+            let mut doc = original.clone();
+            let stats = move_nested_field(&mut doc, &["items", "code"], &["items", "sku"], policy);
+            assert_eq!((stats.renamed, stats.collisions), (1, 1), "{:?}", policy);
+            assert_eq!(doc["items"][0], original["items"][0], "{:?}", policy);
+        }
+
+        // A copy already in place is not a conflict
+        let mut doc = json!({ "code": "b", "sku": "b" });
+        let stats = copy_nested_field(&mut doc, &["code"], "sku", OnConflict::Error);
+        assert_eq!(stats, RenameStats::default());
+
+        let mut doc = original.clone();
+        let stats = copy_nested_field(&mut doc, &["items", "code"], "sku", OnConflict::Overwrite);
+        assert_eq!((stats.renamed, stats.collisions), (2, 0));
+        assert_eq!(doc["items"][0], json!({ "sku": "b", "code": "b" }));
+    }
+
+    #[test]
+    fn test_delete_preserves_key_order() {
+        let mut doc = json!({ "a": 1, "b": 2, "c": 3, "d": 4 });
+
+        assert!(delete_nested_field(&mut doc, &["b"]));
+        assert_eq!(
+            serde_json::to_string(&doc).unwrap(),
+            r#"{"a":1,"c":3,"d":4}"#
+        );
+    }
+
+    #[test]
+    fn test_delete_nested_field_nested_object() {
+        let mut doc = json!({ "a": { "b": { "c": 1, "d": 2 } } });
+
+        assert!(delete_nested_field(&mut doc, &["a", "b", "c"]));
+        assert_eq!(doc, json!({ "a": { "b": { "d": 2 } } }));
+        assert!(
+            !delete_nested_field(&mut doc, &["a", "b", "c"]),
+            "Already gone"
+        );
+        assert!(!delete_nested_field(&mut doc, &["a", "x", "c"]));
+    }
+
+    #[test]
+    fn test_delete_nested_field_array_of_objects() {
+        let mut doc = json!({
+            "items": [
+                { "sku": "a", "legacy": 1 },
+                { "sku": "b" },
+                { "sku": "c", "legacy": { "x": true } },
+                "scalar"
+            ]
+        });
+
+        assert!(delete_nested_field(&mut doc, &["items", "legacy"]));
+        assert_eq!(
+            doc,
+            json!({ "items": [{ "sku": "a" }, { "sku": "b" }, { "sku": "c" }, "scalar"] })
+        );
+    }
+
+    #[test]
+    fn test_move_nested_field_into_new_nested_object() {
+        let mut doc = json!({ "a": { "b": { "c": 1, "d": 2 } } });
+
+        assert!(move_nested_field(
+            &mut doc,
+            &["a", "b", "c"],
+            &["a", "x", "y", "c"],
+            OnConflict::Overwrite
+        )
+        .changed());
+        assert_eq!(
+            doc,
+            json!({ "a": { "b": { "d": 2 }, "x": { "y": { "c": 1 } } } })
+        );
+        assert!(!move_nested_field(
+            &mut doc,
+            &["a", "b", "c"],
+            &["a", "x", "y", "c"],
+            OnConflict::Overwrite
+        )
+        .changed());
+    }
+
+    #[test]
+    fn test_move_nested_field_into_existing_sibling_object() {
+        let mut doc = json!({
+            "items": [
+                { "tel": "1", "contact": { "email": "a@x" } },
+                { "tel": "2", "contact": "none" },
+                { "contact": {} }
+            ]
+        });
+
+        assert!(move_nested_field(
+            &mut doc,
+            &["items", "tel"],
+            &["items", "contact", "phone"],
+            OnConflict::Overwrite
+        )
+        .changed());
+        assert_eq!(
+            doc,
+            json!({
+                "items": [
+                    { "contact": { "email": "a@x", "phone": "1" } },
+                    { "tel": "2", "contact": "none" },
+                    { "contact": {} }
+                ]
+            }),
+            "A field is only moved where the destination's parent is an object"
+        );
+
+        let mut doc = json!({ "a": { "x": 1 }, "b": { "x": 2 } });
+        assert!(
+            move_nested_field(&mut doc, &["a", "x"], &["b", "x"], OnConflict::Overwrite).changed()
+        );
+        assert_eq!(
+            doc,
+            json!({ "a": {}, "b": { "x": 1 } }),
+            "Overwritten like a rename"
+        );
+    }
+
+    #[test]
+    fn test_ignored_new_field_prefix() {
+        assert_eq!(ignored_new_field_prefix(&["a", "b", "c"], "a.b.d"), None);
+        assert_eq!(ignored_new_field_prefix(&["a", "b", "c"], "d"), None);
+        assert_eq!(
+            ignored_new_field_prefix(&["a", "b", "c"], "x.y.d"),
+            Some("x.y")
+        );
+        assert_eq!(ignored_new_field_prefix(&["c"], "x.d"), Some("x"));
+    }
+
+    #[test]
+    fn test_rename_nested_field_with_transforms_moved_values() {
+        let mut doc = json!({
+            "items": [{ "code": " a1 " }, { "code": "b2" }, { "other": " c3 " }]
+        });
+
+        let result =
+            rename_nested_field_with(
+                &mut doc,
+                &["items", "code"],
+                "items.sku",
+                |value| match value {
+                    Value::String(s) => Value::String(s.trim().to_uppercase()),
+                    other => other,
+                },
+            );
+
+        assert!(result);
+        assert_eq!(
+            doc,
+            json!({ "items": [{ "sku": "A1" }, { "sku": "B2" }, { "other": " c3 " }] })
+        );
+    }
+
+    #[test]
+    fn test_ignore_case_renames_any_variant() {
+        let mut doc = json!({ "users": [{ "UserId": 1 }, { "userid": 2 }, { "userId": 3 }] });
+        let options = RenameOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
+
+        let stats =
+            rename_nested_field_with_stats(&mut doc, &["users", "USERID"], "user_id", &options);
+
+        assert_eq!(stats.renamed, 3);
+        assert_eq!(
+            doc,
+            json!({ "users": [{ "user_id": 1 }, { "user_id": 2 }, { "user_id": 3 }] })
+        );
     }
 
-    let (current_key, remaining_path) = old_field_path.split_first().unwrap();
+    #[test]
+    fn test_ignore_case_matches_parent_segments() {
+        let mut doc = json!({
+            "Contact": { "Email": "a@x.io" },
+            "contact_old": { "email": "b@x.io" },
+            "CONTACT": { "EMAIL": "c@x.io" }
+        });
+        let options = RenameOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
 
-    match doc {
-        Value::Object(obj) => {
-            if let Some(value) = obj.get_mut(*current_key) {
-                if remaining_path.is_empty() {
-                    // Base case: Rename the field
-                    if let Some(value) = obj.remove(*current_key) {
-                        // split the new_field into components
-                        let new_field_path: Vec<&str> = new_field.split('.').collect();
-                        // use last element as the new field name
-                        let new_field = new_field_path.last().unwrap();
-
-                        obj.insert(new_field.to_string(), value);
-                        return true;
-                    }
-                } else {
-                    // Recursive case: Traverse deeper
-                    return rename_nested_field(value, remaining_path, new_field);
-                }
-            }
-        }
-        Value::Array(arr) => {
-            // Process each element in the array recursively
-            let mut renamed = false;
-            for item in arr {
-                renamed |= rename_nested_field(item, old_field_path, new_field);
-            }
-            return renamed;
-        }
-        _ => {}
+        // Parents keep their casing; only the renamed key takes the exact new name
+        let stats =
+            rename_nested_field_with_stats(&mut doc, &["contact", "email"], "email", &options);
+
+        assert_eq!(stats.renamed, 2);
+        assert_eq!(
+            doc,
+            json!({
+                "Contact": { "email": "a@x.io" },
+                "contact_old": { "email": "b@x.io" },
+                "CONTACT": { "email": "c@x.io" }
+            })
+        );
     }
 
-    false
-}
+    #[test]
+    fn test_ignore_case_coexisting_variants() {
+        let original = json!({ "UserId": 1, "name": "a", "userid": 2 });
 
-/// Unit tests for the application
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use serde_json::json;
+        // By default the ambiguous object is left untouched
+        let mut doc = original.clone();
+        let options = RenameOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
+        let stats = rename_nested_field_with_stats(&mut doc, &["userId"], "userId", &options);
+        assert_eq!(stats.ambiguous, 1);
+        assert!(!stats.changed());
+        assert_eq!(doc, original);
+
+        // With the merge policy the first variant in document order wins
+        let options = RenameOptions {
+            ignore_case: true,
+            case_conflict: CaseConflict::Merge,
+            ..Default::default()
+        };
+        let stats = rename_nested_field_with_stats(&mut doc, &["userId"], "userId", &options);
+        assert!(stats.changed());
+        assert_eq!(
+            serde_json::to_string(&doc).unwrap(),
+            r#"{"userId":1,"name":"a"}"#
+        );
+    }
 
     #[test]
-    fn test_rename_nested_field_simple_object() {
+    fn test_drop_produced_empty_values_keeps_earlier_ones() {
+        let original = json!({
+            "lines": [
+                { "old": "", "b": "" },
+                { "old": "x" },
+                { "b": null },
+                { "old": null },
+            ],
+        });
+        let mut doc = original.clone();
+        rename_nested_field(&mut doc, &["lines", "old"], "b");
+
+        assert_eq!(
+            drop_produced_empty_values(&mut doc, &original, &["lines", "b"]),
+            1
+        );
+        assert_eq!(
+            doc,
+            json!({ "lines": [{ "b": "" }, { "b": "x" }, { "b": null }, {}] })
+        );
+    }
+
+    #[test]
+    fn test_drop_empty_values() {
         let mut doc = json!({
-            "a": {
-                "b": 1
-            }
+            "a": "",
+            "lines": [{ "b": null }, { "b": 0 }, { "b": "" }, { "c": "" }],
         });
 
-        let old_field_path = vec!["a", "b"];
-        let new_field = "new_b";
+        assert_eq!(drop_empty_values(&mut doc, &["a"]), 1);
+        assert_eq!(drop_empty_values(&mut doc, &["lines", "b"]), 2);
+        assert_eq!(doc, json!({ "lines": [{}, { "b": 0 }, {}, { "c": "" }] }));
+    }
 
-        let result = rename_nested_field(&mut doc, &old_field_path, new_field);
+    #[test]
+    fn test_value_transforms_on_matching_and_other_values() {
+        let apply = |kind: &str, value: Value| kind.parse::<ValueTransform>().unwrap().apply(value);
 
-        assert!(result, "Field renaming should succeed");
+        assert_eq!(apply("lowercase", json!("MiXeD")), Ok(json!("mixed")));
+        assert_eq!(apply("lowercase", json!(1)), Err(json!(1)));
+        assert_eq!(apply("uppercase", json!("MiXeD")), Ok(json!("MIXED")));
+        assert_eq!(apply("uppercase", json!(null)), Err(json!(null)));
+        assert_eq!(apply("trim", json!("  a b ")), Ok(json!("a b")));
+        assert_eq!(apply("trim", json!(["  a "])), Err(json!(["  a "])));
+
+        assert_eq!(apply("to-number", json!(" 42 ")), Ok(json!(42)));
+        assert_eq!(apply("to-number", json!("-1.5")), Ok(json!(-1.5)));
+        assert_eq!(
+            apply("to-number", json!("12 apples")),
+            Err(json!("12 apples"))
+        );
+        assert_eq!(apply("to-number", json!(7)), Err(json!(7)));
+
+        assert_eq!(apply("to-string", json!(42)), Ok(json!("42")));
+        assert_eq!(apply("to-string", json!(true)), Ok(json!("true")));
+        assert_eq!(apply("to-string", json!("42")), Err(json!("42")));
+        assert_eq!(
+            apply("to-string", json!({ "n": 1 })),
+            Err(json!({ "n": 1 }))
+        );
+
+        assert!("titlecase".parse::<ValueTransform>().is_err());
+    }
+
+    #[test]
+    fn test_split_on_transform() {
+        let split = ValueTransform::SplitOn(",".to_string());
+
+        assert_eq!(split.apply(json!("a, b,,c ")), Ok(json!(["a", "b", "c"])));
+        assert_eq!(split.apply(json!("")), Ok(json!([])));
+        assert_eq!(split.apply(json!(["a"])), Err(json!(["a"])));
+        assert_eq!(split.apply(json!(null)), Err(json!(null)));
+    }
+
+    #[test]
+    fn test_backup_suffix_keeps_original_value() {
+        let mut doc = json!({ "items": [{ "tags": "a,b", "id": 1 }, { "id": 2 }] });
+        let options = RenameOptions {
+            backup_suffix: Some("__backup".to_string()),
+            ..Default::default()
+        };
+        let split = ValueTransform::SplitOn(",".to_string());
+
+        let stats = rename_nested_field_with_transform(
+            &mut doc,
+            &["items", "tags"],
+            "labels",
+            &options,
+            &|value| split.apply(value).unwrap_or_else(|value| value),
+        );
+
+        assert_eq!(stats.backups, 1);
+        assert_eq!(
+            serde_json::to_string(&doc).unwrap(),
+            r#"{"items":[{"labels":["a","b"],"id":1,"tags__backup":"a,b"},{"id":2}]}"#,
+            "Backup should hold the untransformed value"
+        );
+    }
+
+    #[test]
+    fn test_transform_nested_field_keeps_keys() {
+        let mut doc = json!({
+            "users": [
+                { "email": " Ann@Example.com " },
+                { "email": "bob@example.com" },
+                { "email": 42 },
+                { "name": "carl" }
+            ]
+        });
+        let lower = |value: Value| {
+            ValueTransform::Lower
+                .apply(value)
+                .and_then(|value| ValueTransform::Trim.apply(value))
+                .unwrap_or_else(|value| value)
+        };
+
+        let changed = transform_nested_field(&mut doc, &["users", "email"], &lower);
+
+        assert_eq!(changed, 1, "Only the mixed-case email should change");
         assert_eq!(
             doc,
             json!({
-                "a": {
-                    "new_b": 1
-                }
-            }),
-            "Field 'b' should be renamed to 'new_b'"
+                "users": [
+                    { "email": "ann@example.com" },
+                    { "email": "bob@example.com" },
+                    { "email": 42 },
+                    { "name": "carl" }
+                ]
+            })
         );
     }
 
     #[test]
-    fn test_rename_nested_field_nested_object() {
+    fn test_value_replacement_by_equality() {
+        let replacement = ValueReplacement::parse("UK:GB", false).unwrap();
         let mut doc = json!({
-            "a": {
-                "b": {
-                    "c": 2
-                }
-            }
+            "addresses": [
+                { "country": "UK" },
+                { "country": "uk" },
+                { "country": "FR" }
+            ]
         });
 
-        let old_field_path = vec!["a", "b", "c"];
-        let new_field = "new_c";
-
-        let result = rename_nested_field(&mut doc, &old_field_path, new_field);
+        let changed = transform_nested_field(&mut doc, &["addresses", "country"], &|value| {
+            replacement.apply(value).unwrap_or_else(|value| value)
+        });
 
-        assert!(result, "Field renaming should succeed");
+        assert_eq!(changed, 1);
         assert_eq!(
             doc,
             json!({
-                "a": {
-                    "b": {
-                        "new_c": 2
-                    }
-                }
-            }),
-            "Field 'c' should be renamed to 'new_c'"
+                "addresses": [
+                    { "country": "GB" },
+                    { "country": "uk" },
+                    { "country": "FR" }
+                ]
+            })
+        );
+
+        let numeric = ValueReplacement::parse("1:2.5", false).unwrap();
+        assert_eq!(numeric.apply(json!(1)), Ok(json!(2.5)));
+        assert_eq!(
+            numeric.apply(json!("1")),
+            Err(json!("1")),
+            "Types must match"
+        );
+
+        assert!(
+            ValueReplacement::parse("UK", false).is_err(),
+            "Missing colon"
         );
     }
 
     #[test]
-    fn test_rename_nested_field_array_of_objects() {
+    fn test_value_replacement_by_regex() {
+        let replacement = ValueReplacement::parse(r"^(\d{3})-(\d{4})$:$1$2", true).unwrap();
+
+        assert_eq!(replacement.apply(json!("555-1234")), Ok(json!("5551234")));
+        assert_eq!(replacement.apply(json!("5551234")), Err(json!("5551234")));
+        assert_eq!(replacement.apply(json!(555)), Err(json!(555)));
+
+        assert!(
+            ValueReplacement::parse("(:x", true).is_err(),
+            "Invalid pattern"
+        );
+    }
+
+    #[test]
+    fn test_value_replacement_with_escaped_colons() {
+        let time = ValueReplacement::parse(r"^(\d{2})\:(\d{2})$:$1.$2", true).unwrap();
+        assert_eq!(time.apply(json!("09:30")), Ok(json!("09.30")));
+
+        let url = ValueReplacement::parse(r"http\://old:https://new", false).unwrap();
+        assert_eq!(url.apply(json!("http://old")), Ok(json!("https://new")));
+
+        // An escaped backslash does not escape the colon after it
+        let backslash = ValueReplacement::parse(r"a\\:b", true).unwrap();
+        assert_eq!(backslash.apply(json!(r"a\")), Ok(json!("b")));
+
+        assert!(
+            ValueReplacement::parse(r"a\:b", false).is_err(),
+            "Only an escaped colon"
+        );
+    }
+
+    #[test]
+    fn test_rename_key_anywhere_renames_every_depth() {
         let mut doc = json!({
-            "a": {
-                "b": [
-                    { "c": 1 },
-                    { "c": 2 }
-                ]
-            }
+            "_id": "a",
+            "kind": 1,
+            "meta": { "kind": 2, "tags": [{ "kind": 3 }, "kind", [{ "kind": { "kind": 4 } }]] }
         });
 
-        let old_field_path = vec!["a", "b", "c"];
-        let new_field = "new_c";
-
-        let result = rename_nested_field(&mut doc, &old_field_path, new_field);
+        let stats = rename_key_anywhere(
+            &mut doc,
+            "kind",
+            "type",
+            &RenameOptions::default(),
+            &|value| value,
+        );
 
-        assert!(result, "Field renaming should succeed");
+        assert_eq!(stats.renamed, 5);
         assert_eq!(
             doc,
             json!({
-                "a": {
-                    "b": [
-                        { "new_c": 1 },
-                        { "new_c": 2 }
-                    ]
-                }
+                "_id": "a",
+                "type": 1,
+                "meta": { "type": 2, "tags": [{ "type": 3 }, "kind", [{ "type": { "type": 4 } }]] }
             }),
-            "Fields 'c' in array elements should be renamed to 'new_c'"
+            "Keys are renamed at every depth, string values are left alone"
         );
     }
 
     #[test]
-    fn test_rename_nested_field_nonexistent_field() {
+    fn test_rename_matching_fields_replaces_prefixes() {
         let mut doc = json!({
-            "a": {
-                "b": 1
-            }
+            "_id": "a",
+            "_old_rev": "kept",
+            "old_name": "Ada",
+            "bold_text": true,
+            "items": [{ "old_sku": "x", "old_qty": 1 }, { "qty": 2 }],
+            "meta": { "old_tags": { "old_color": "red" } },
         });
+        let pattern = Regex::new("^old_").unwrap();
 
-        let old_field_path = vec!["a", "x"];
-        let new_field = "new_x";
-
-        let result = rename_nested_field(&mut doc, &old_field_path, new_field);
+        let stats = rename_matching_fields(
+            &mut doc,
+            &pattern,
+            "new_",
+            &RenameOptions::default(),
+            &|value| value,
+        );
 
-        assert!(!result, "Field renaming should fail for nonexistent fields");
+        assert_eq!(stats.renamed, 5);
         assert_eq!(
             doc,
             json!({
-                "a": {
-                    "b": 1
-                }
+                "_id": "a",
+                "_old_rev": "kept",
+                "new_name": "Ada",
+                "bold_text": true,
+                "items": [{ "new_sku": "x", "new_qty": 1 }, { "qty": 2 }],
+                "meta": { "new_tags": { "new_color": "red" } },
             }),
-            "Document should remain unchanged"
+            "Matching keys are renamed at every depth, top-level CouchDB fields are left alone"
         );
     }
 
     #[test]
-    fn test_rename_nested_field_invalid_path() {
+    fn test_rename_matching_fields_substitutes_capture_groups() {
         let mut doc = json!({
-            "a": {
-                "b": 1
+            "_id": "a",
+            "user_id": 1,
+            "order_id": 2,
+            "id": 3,
+            "lines": [{ "product_id": 4 }],
+        });
+        let pattern = Regex::new("^(?<entity>[a-z]+)_id$").unwrap();
+
+        let stats = rename_matching_fields(
+            &mut doc,
+            &pattern,
+            "${entity}Id",
+            &RenameOptions::default(),
+            &|value| value,
+        );
+        assert_eq!(stats.renamed, 3);
+        assert_eq!(
+            doc,
+            json!({
+                "_id": "a",
+                "userId": 1,
+                "orderId": 2,
+                "id": 3,
+                "lines": [{ "productId": 4 }],
+            })
+        );
+
+        // Existing destinations are handled like any rename
+        let mut doc = json!({ "a_1": "x", "b_1": "y", "b": "z" });
+        let options = RenameOptions {
+            on_conflict: OnConflict::Skip,
+            ..RenameOptions::default()
+        };
+        let pattern = Regex::new(r"^([a-z])_\d$").unwrap();
+        let stats = rename_matching_fields(&mut doc, &pattern, "$1", &options, &|value| value);
+        assert_eq!((stats.renamed, stats.collisions), (1, 1));
+        assert_eq!(doc, json!({ "a": "x", "b_1": "y", "b": "z" }));
+    }
+
+    #[test]
+    fn test_attachments_are_preserved_unless_included() {
+        let original = json!({
+            "_id": "a",
+            "_attachments": { "scan.pdf": { "content_type": "application/pdf", "length": 12, "stub": true } },
+            "length": 3,
+            "meta": { "length": 4 },
+        });
+
+        let mut doc = original.clone();
+        let stats = rename_key_anywhere(
+            &mut doc,
+            "length",
+            "size",
+            &RenameOptions::default(),
+            &|value| value,
+        );
+        assert_eq!(stats.renamed, 2);
+        assert_eq!(doc["_attachments"], original["_attachments"]);
+        assert_eq!(
+            doc.as_object().unwrap().keys().collect::<Vec<_>>(),
+            vec!["_id", "_attachments", "size", "meta"],
+            "The stubs keep their position"
+        );
+
+        let mut doc = original.clone();
+        let path = ["_attachments", "scan.pdf", "length"];
+        assert!(!rename_nested_field(&mut doc, &path, "size"));
+        assert_eq!(doc, original);
+
+        let options = RenameOptions {
+            include_attachments: true,
+            ..RenameOptions::default()
+        };
+        assert!(rename_nested_field_with_options(
+            &mut doc, &path, "size", &options
+        ));
+        assert_eq!(doc["_attachments"]["scan.pdf"]["size"], 12);
+    }
+
+    #[test]
+    fn test_promote_nested_field_to_root() {
+        let mut doc = json!({ "_id": "a", "meta": { "version": 3, "author": "ann" }, "x": 1 });
+
+        let stats = promote_nested_field(&mut doc, &["meta", "version"], &["version"], true);
+
+        assert_eq!(stats.promoted, 1);
+        assert_eq!(stats.pruned, 0, "The wrapper still holds 'author'");
+        assert_eq!(
+            serde_json::to_string(&doc).unwrap(),
+            r#"{"_id":"a","version":3,"meta":{"author":"ann"},"x":1}"#
+        );
+    }
+
+    #[test]
+    fn test_promote_nested_field_prunes_empty_wrappers() {
+        let mut doc = json!({ "a": { "b": { "c": 1 } }, "z": 0 });
+
+        let stats = promote_nested_field(&mut doc, &["a", "b", "c"], &["c"], true);
+
+        assert_eq!(stats.promoted, 1);
+        assert_eq!(stats.pruned, 2);
+        assert_eq!(serde_json::to_string(&doc).unwrap(), r#"{"c":1,"z":0}"#);
+
+        let mut kept = json!({ "a": { "b": { "c": 1 } } });
+        promote_nested_field(&mut kept, &["a", "b", "c"], &["c"], false);
+        assert_eq!(kept, json!({ "c": 1, "a": { "b": {} } }));
+    }
+
+    #[test]
+    fn test_promote_nested_field_in_array_elements() {
+        let mut doc = json!({
+            "items": [
+                { "meta": { "sku": "x1" } },
+                { "meta": { "sku": "x2" }, "sku": "old" },
+                { "name": "no meta" }
+            ]
+        });
+
+        let stats =
+            promote_nested_field(&mut doc, &["items", "meta", "sku"], &["items", "sku"], true);
+
+        assert_eq!(
+            stats,
+            PromoteStats {
+                promoted: 1,
+                pruned: 1,
+                conflicts: 1
             }
+        );
+        assert_eq!(
+            doc,
+            json!({
+                "items": [
+                    { "sku": "x1" },
+                    { "meta": { "sku": "x2" }, "sku": "old" },
+                    { "name": "no meta" }
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn test_promote_nested_field_replaces_lone_wrapper() {
+        let mut doc = json!({ "data": { "data": [1, 2] }, "n": 1 });
+        let stats = promote_nested_field(&mut doc, &["data", "data"], &["data"], false);
+        assert_eq!(stats.promoted, 1);
+        assert_eq!(
+            serde_json::to_string(&doc).unwrap(),
+            r#"{"data":[1,2],"n":1}"#
+        );
+
+        let mut busy = json!({ "data": { "data": [1], "other": true } });
+        let stats = promote_nested_field(&mut busy, &["data", "data"], &["data"], false);
+        assert_eq!(stats.conflicts, 1);
+        assert_eq!(busy, json!({ "data": { "data": [1], "other": true } }));
+    }
+
+    #[test]
+    fn test_wildcard_paths_in_every_mode() {
+        let original = json!({
+            "items": [{ "x": "A", "*": "" }, { "x": "B" }],
+            "by_id": { "a": { "x": "C" }, "b": { "y": 1 } },
         });
+        let path = split_path("items.*.x");
+        let values = split_path("by_id.*.x");
+        let literal = split_path("items.\\*");
 
-        let old_field_path: Vec<&str> = vec![];
-        let new_field = "new_b";
+        let mut doc = original.clone();
+        assert!(delete_nested_field(&mut doc, &path));
+        assert!(delete_nested_field(&mut doc, &values));
+        assert_eq!(
+            doc,
+            json!({ "items": [{ "*": "" }, {}], "by_id": { "a": {}, "b": { "y": 1 } } })
+        );
 
-        let result = rename_nested_field(&mut doc, &old_field_path, new_field);
+        let mut doc = original.clone();
+        assert!(copy_nested_field(&mut doc, &path, "items.*.z", OnConflict::Overwrite).changed());
+        assert_eq!(
+            doc["items"],
+            json!([{ "x": "A", "z": "A", "*": "" }, { "x": "B", "z": "B" }])
+        );
 
-        assert!(!result, "Field renaming should fail for invalid paths");
+        let mut doc = original.clone();
+        let lower = |value: Value| json!(value.as_str().unwrap_or_default().to_lowercase());
+        assert_eq!(transform_nested_field(&mut doc, &path, &lower), 2);
+        assert_eq!(transform_nested_field(&mut doc, &values, &lower), 1);
+        assert_eq!(find_nested_values(&doc, &path), vec!["a", "b"]);
+        assert_eq!(doc["by_id"]["a"]["x"], "c");
+
+        let mut doc = original.clone();
+        assert_eq!(
+            drop_empty_values(&mut doc, &literal),
+            1,
+            "'\\*' is the key '*'"
+        );
+        assert_eq!(drop_empty_values(&mut doc, &path), 0);
+        assert_eq!(doc["items"], json!([{ "x": "A" }, { "x": "B" }]));
+    }
+
+    #[test]
+    fn test_bare_index_paths_in_every_mode() {
+        let original = json!({
+            "tags": [{ "x": "A", "meta": { "sku": 1 } }, { "x": "B", "meta": { "sku": 2 } }],
+            "codes": { "0": { "x": "" } },
+        });
+        let path = split_path("tags.1.x");
+
+        let mut doc = original.clone();
+        assert!(delete_nested_field(&mut doc, &path));
+        assert!(!delete_nested_field(&mut doc, &split_path("tags.2.x")));
+        assert_eq!(doc["tags"][0]["x"], "A");
+        assert_eq!(doc["tags"][1].get("x"), None);
+
+        let mut doc = original.clone();
+        assert!(copy_nested_field(&mut doc, &path, "tags.1.y", OnConflict::Overwrite).changed());
+        assert_eq!(doc["tags"][1]["y"], "B");
+        assert_eq!(doc["tags"][0].get("y"), None);
+
+        let mut doc = original.clone();
+        let lower = |value: Value| json!(value.as_str().unwrap_or_default().to_lowercase());
+        assert_eq!(transform_nested_field(&mut doc, &path, &lower), 1);
+        assert_eq!(
+            find_nested_values(&doc, &split_path("tags.x")),
+            vec!["A", "b"]
+        );
+
+        let mut doc = original.clone();
+        assert_eq!(
+            drop_empty_values(&mut doc, &split_path("codes.0.x")),
+            1,
+            "A key on an object"
+        );
+        assert_eq!(doc["codes"], json!({ "0": {} }));
+
+        let mut doc = original.clone();
+        assert!(move_nested_field(
+            &mut doc,
+            &split_path("tags.0.meta.sku"),
+            &split_path("tags.0.info.sku"),
+            OnConflict::Overwrite
+        )
+        .changed());
+        assert_eq!(doc["tags"][0]["info"], json!({ "sku": 1 }));
+        assert_eq!(doc["tags"][1]["meta"], json!({ "sku": 2 }));
+
+        let mut doc = original.clone();
+        let stats = promote_nested_field(
+            &mut doc,
+            &split_path("tags.1.meta.sku"),
+            &split_path("tags.1.sku"),
+            false,
+        );
+        assert_eq!(stats.promoted, 1);
+        assert_eq!(doc["tags"][1]["sku"], 2);
+        assert_eq!(doc["tags"][0]["meta"], json!({ "sku": 1 }));
+    }
+
+    #[test]
+    fn test_prune_empty_collapses_upward() {
+        let original = json!({
+            "_id": "a",
+            "meta": { "tags": [], "inner": { "deeper": { "x": 1 } } },
+            "items": [{ "x": 1 }, { "keep": 0, "x": 2 }, [[3]], {}],
+            "name": "",
+            "flag": null,
+            "old": {}
+        });
+        let mut doc = original.clone();
+        delete_nested_field(&mut doc, &["meta", "inner", "deeper", "x"]);
+        delete_nested_field(&mut doc, &["items", "x"]);
+        doc["items"][2][0] = json!([]);
+        rename_nested_field(&mut doc, &["old"], "new");
+
+        assert!(prune_empty(&mut doc, &original));
         assert_eq!(
             doc,
             json!({
-                "a": {
-                    "b": 1
-                }
+                "_id": "a",
+                "meta": { "tags": [] },
+                "items": [{ "keep": 0 }, {}],
+                "name": "",
+                "flag": null,
+                "new": {}
             }),
-            "Document should remain unchanged"
+            "Only what the change emptied is removed, not the empties that were already there"
+        );
+
+        let pruned = doc.clone();
+        assert!(!prune_empty(&mut doc, &pruned), "Nothing left to prune");
+        let mut root = json!({});
+        assert!(
+            !prune_empty(&mut root, &json!({ "x": 1 })),
+            "The root is kept"
         );
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,156 @@
+use crate::audit::Outcome;
+use serde_json::{json, Value};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle;
+
+/// What happened to a document, as listed in the `--report` file.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct ReportEntry {
+    pub id: String,           // ID of the document
+    pub status: &'static str, // Outcome of the document, named as in the audit database
+    pub matched: bool,        // Whether the document holds a field the operation applies to
+    pub updated: bool,        // Whether the document was written
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>, // Why the document failed or would be rejected, if it did
+}
+
+impl ReportEntry {
+    /// Describes the outcome of a document, with the error that caused it, if any.
+    pub fn new(id: &str, outcome: Outcome, error: Option<String>) -> Self {
+        ReportEntry {
+            id: id.to_string(),
+            status: outcome.as_str(),
+            matched: outcome != Outcome::Missing,
+            updated: matches!(outcome, Outcome::Updated | Outcome::Deleted),
+            error,
+        }
+    }
+}
+
+/// A message handled by the collector task.
+#[derive(Debug)]
+enum ReportEvent {
+    Entry(ReportEntry), // The outcome of a document
+    Finish,             // Return the entries received so far
+}
+
+/// Handle for sending entries to the collector task started by `start_collector`.
+/// Cloning it is cheap; every clone feeds the same collector.
+#[derive(Debug, Clone)]
+pub struct Reporter {
+    sender: UnboundedSender<ReportEvent>,
+}
+
+impl Reporter {
+    /// Adds the entry of a document to the report.
+    pub fn send(&self, entry: ReportEntry) {
+        // Entries sent after the collector has finished are dropped
+        let _ = self.sender.send(ReportEvent::Entry(entry));
+    }
+}
+
+/// The running collector task, to be finished with `finish` once every document is processed.
+pub struct ReportCollector {
+    sender: UnboundedSender<ReportEvent>,
+    task: JoinHandle<Vec<ReportEntry>>,
+}
+
+impl ReportCollector {
+    /// Stops the collector task and returns every entry sent before, in the order received.
+    pub async fn finish(self) -> Vec<ReportEntry> {
+        let _ = self.sender.send(ReportEvent::Finish);
+        self.task.await.unwrap_or_default()
+    }
+}
+
+/// Starts the task collecting the entries that processing tasks send through the `Reporter`.
+pub fn start_collector() -> (Reporter, ReportCollector) {
+    let (sender, receiver) = unbounded_channel();
+    let task = tokio::spawn(collect(receiver));
+
+    (
+        Reporter {
+            sender: sender.clone(),
+        },
+        ReportCollector { sender, task },
+    )
+}
+
+/// Collector loop: keeps each entry until finished or every sender is gone.
+async fn collect(mut receiver: UnboundedReceiver<ReportEvent>) -> Vec<ReportEntry> {
+    let mut entries = Vec::new();
+    while let Some(ReportEvent::Entry(entry)) = receiver.recv().await {
+        entries.push(entry);
+    }
+    entries
+}
+
+/// Renders the report: the number of documents by outcome, then an entry per document,
+/// sorted by ID.
+pub fn render_report(mut entries: Vec<ReportEntry>) -> Value {
+    entries.sort_by(|a, b| a.id.cmp(&b.id));
+    json!({
+        "documents": entries.len(),
+        "matched": entries.iter().filter(|entry| entry.matched).count(),
+        "updated": entries.iter().filter(|entry| entry.updated).count(),
+        "errors": entries.iter().filter(|entry| entry.error.is_some()).count(),
+        "entries": entries,
+    })
+}
+
+/// Writes the report to a file as pretty-printed JSON, replacing its content.
+pub fn write_report(path: &str, entries: Vec<ReportEntry>) -> Result<(), String> {
+    let content = serde_json::to_string_pretty(&render_report(entries))
+        .map_err(|e| format!("Failed to render the report: {}", e))?;
+    std::fs::write(path, content + "\n")
+        .map_err(|e| format!("Failed to write report '{}': {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_report_collects_entries_from_tasks() {
+        let (reporter, collector) = start_collector();
+
+        // Each document is processed on its own task, like in a run
+        let tasks: Vec<_> = [
+            ("b", Outcome::Failed, Some("Status code 403 Forbidden")),
+            ("a", Outcome::Updated, None),
+        ]
+        .into_iter()
+        .map(|(id, outcome, error)| {
+            let reporter = reporter.clone();
+            tokio::spawn(async move {
+                reporter.send(ReportEntry::new(id, outcome, error.map(String::from)));
+            })
+        })
+        .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let report = render_report(collector.finish().await);
+        assert_eq!(
+            report,
+            json!({
+                "documents": 2,
+                "matched": 2,
+                "updated": 1,
+                "errors": 1,
+                "entries": [
+                    { "id": "a", "status": "updated", "matched": true, "updated": true },
+                    {
+                        "id": "b",
+                        "status": "failed",
+                        "matched": true,
+                        "updated": false,
+                        "error": "Status code 403 Forbidden"
+                    },
+                ],
+            })
+        );
+        assert!(!ReportEntry::new("c", Outcome::Missing, None).matched);
+    }
+}
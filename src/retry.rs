@@ -0,0 +1,368 @@
+use reqwest::header::RETRY_AFTER;
+use reqwest::{RequestBuilder, Response, StatusCode};
+use serde_json::Value;
+use std::fmt;
+use std::future::Future;
+use std::time::{Duration, SystemTime};
+use tokio::time::sleep;
+
+/// Delay before the first retry of a request whose response asks for no particular delay;
+/// it doubles with every further retry
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Longest delay between two retries, even when a `Retry-After` header asks for more
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Sends a request, retrying up to `max_retries` times on transient failures: connection errors,
+/// timeouts, and the statuses of `is_retryable` (429 Too Many Requests, 502, 503, 504).
+/// Each retry waits for the delay given by the `Retry-After` header, in seconds or as an HTTP date,
+/// or else backs off exponentially (see `backoff_delay`); either way, no longer than 30s. Other statuses, such as 409 conflicts
+/// and client errors, are returned right away.
+///
+/// Once the retries are exhausted, the last response (or error) is returned as is, so the caller's
+/// status handling still applies. Requests with a streaming body cannot be retried and are sent once.
+pub async fn send_with_retry(
+    request: RequestBuilder,
+    max_retries: usize,
+) -> Result<Response, reqwest::Error> {
+    let mut attempt = 0;
+
+    loop {
+        let Some(next) = request.try_clone() else {
+            return request.send().await;
+        };
+
+        let (delay, reason) = match next.send().await {
+            Ok(response) if !is_retryable(response.status()) || attempt >= max_retries => {
+                return Ok(response)
+            }
+            Ok(response) => (
+                retry_after(&response).unwrap_or_else(|| backoff_delay(attempt)),
+                format!("Server answered {}", response.status()),
+            ),
+            Err(err) if !(err.is_connect() || err.is_timeout()) || attempt >= max_retries => {
+                return Err(err)
+            }
+            Err(err) => (backoff_delay(attempt), format!("Request failed ({})", err)),
+        };
+
+        tracing::warn!(
+            "{}; retrying in {:.1}s ({}/{}).",
+            reason,
+            delay.as_secs_f64(),
+            attempt + 1,
+            max_retries
+        );
+        sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+/// Whether a response status reports a transient condition worth retrying.
+pub fn is_retryable(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Delay before retry number `attempt` (from 0): 100ms, 200ms, 400ms, ... up to 30s.
+pub fn backoff_delay(attempt: usize) -> Duration {
+    let factor = 1u32 << attempt.min(16);
+    INITIAL_BACKOFF.saturating_mul(factor).min(MAX_BACKOFF)
+}
+
+/// Why writing a document failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WriteError {
+    Conflict,       // The document has a newer revision than the one written (409)
+    Failed(String), // Any other failure
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WriteError::Conflict => write!(f, "Status code {}", StatusCode::CONFLICT),
+            WriteError::Failed(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// Outcome of `write_resolving_conflicts`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    Written(Option<String>), // The document was written, with the new revision if the server returned it
+    Unneeded,                // The latest version, fetched after a conflict, needed no change
+}
+
+/// Writes a document, and when the write is rejected because someone else updated the document
+/// in the meantime, fetches its latest version, re-applies the change to it, and writes that
+/// instead, up to `max_conflicts` times.
+///
+/// `reapply` returns whether it changed the latest version; if not, nothing is written.
+/// `doc` ends up holding the version last written or found not to need the change.
+pub async fn write_resolving_conflicts<W, WF, R, RF, A>(
+    doc: &mut Value,
+    max_conflicts: usize,
+    write: W,
+    refetch: R,
+    reapply: A,
+) -> Result<Resolution, WriteError>
+where
+    W: Fn(Value) -> WF,
+    WF: Future<Output = Result<Option<String>, WriteError>>,
+    R: Fn() -> RF,
+    RF: Future<Output = Result<Option<Value>, String>>,
+    A: Fn(&mut Value) -> bool,
+{
+    let mut conflicts = 0;
+
+    loop {
+        match write(doc.clone()).await {
+            Err(WriteError::Conflict) if conflicts < max_conflicts => conflicts += 1,
+            result => return result.map(Resolution::Written),
+        }
+
+        let Some(mut latest) = refetch().await.map_err(WriteError::Failed)? else {
+            return Err(WriteError::Failed(
+                "the document was deleted concurrently".to_string(),
+            ));
+        };
+        let changed = reapply(&mut latest);
+        *doc = latest;
+        if !changed {
+            return Ok(Resolution::Unneeded);
+        }
+    }
+}
+
+/// Reads the delay requested by a response's `Retry-After` header, capped at `MAX_BACKOFF` so
+/// that a misbehaving server cannot stall the run.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+    parse_retry_after(value, SystemTime::now()).map(|delay| delay.min(MAX_BACKOFF))
+}
+
+/// Parses a `Retry-After` value, either a number of seconds or an HTTP date relative to `now`.
+/// A date in the past means the request may be retried immediately.
+pub fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    let value = value.trim();
+
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(now).unwrap_or(Duration::ZERO))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::Client;
+    use serde_json::json;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn test_parse_retry_after() {
+        let now = httpdate::parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT").unwrap();
+
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Sun, 06 Nov 1994 08:50:07 GMT", now),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            parse_retry_after("Sun, 06 Nov 1994 08:00:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[tokio::test]
+    async fn test_send_with_retry_honors_rate_limit() {
+        let server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "0"))
+            .up_to_n_times(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .respond_with(ResponseTemplate::new(201))
+            .mount(&server)
+            .await;
+
+        let request = Client::new().put(server.uri()).body("{}");
+        let response = send_with_retry(request, 3).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(server.received_requests().await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_is_capped_at_the_backoff_maximum() {
+        let server = MockServer::start().await;
+        for delay in ["3600", "5"] {
+            Mock::given(method("GET"))
+                .and(path(format!("/{}", delay)))
+                .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", delay))
+                .mount(&server)
+                .await;
+        }
+
+        let get = |delay: &str| {
+            Client::new()
+                .get(format!("{}/{}", server.uri(), delay))
+                .send()
+        };
+        assert_eq!(retry_after(&get("3600").await.unwrap()), Some(MAX_BACKOFF));
+        assert_eq!(
+            retry_after(&get("5").await.unwrap()),
+            Some(Duration::from_secs(5))
+        );
+    }
+
+    #[test]
+    fn test_backoff_delay_doubles_up_to_a_cap() {
+        assert_eq!(backoff_delay(0), Duration::from_millis(100));
+        assert_eq!(backoff_delay(1), Duration::from_millis(200));
+        assert_eq!(backoff_delay(2), Duration::from_millis(400));
+        assert_eq!(backoff_delay(100), MAX_BACKOFF);
+    }
+
+    #[tokio::test]
+    async fn test_send_with_retry_backs_off_while_unavailable() {
+        let server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+
+        let request = Client::new().put(server.uri()).body("{}");
+        let response = send_with_retry(request, 3).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(server.received_requests().await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_send_with_retry_fails_fast_on_conflicts() {
+        let server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .respond_with(ResponseTemplate::new(409))
+            .mount(&server)
+            .await;
+
+        let request = Client::new().put(server.uri()).body("{}");
+        let response = send_with_retry(request, 3).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_send_with_retry_gives_up_after_budget() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "0"))
+            .mount(&server)
+            .await;
+
+        let response = send_with_retry(Client::new().get(server.uri()), 2)
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(server.received_requests().await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_send_with_retry_times_out_on_a_stalled_server() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(30)))
+            .mount(&server)
+            .await;
+        let client = Client::builder()
+            .timeout(Duration::from_millis(200))
+            .build()
+            .unwrap();
+
+        let err = send_with_retry(client.get(server.uri()), 1)
+            .await
+            .unwrap_err();
+
+        assert!(err.is_timeout());
+        assert_eq!(
+            server.received_requests().await.unwrap().len(),
+            2,
+            "Timeouts are retried"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_write_resolving_conflicts_reapplies_to_latest_revision() {
+        let server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .respond_with(ResponseTemplate::new(409))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .respond_with(ResponseTemplate::new(201).set_body_json(json!({ "rev": "3-c" })))
+            .mount(&server)
+            .await;
+
+        let client = Client::new();
+        let write = |doc: Value| {
+            let request = client.put(server.uri()).json(&doc);
+            async move {
+                let response = request
+                    .send()
+                    .await
+                    .map_err(|e| WriteError::Failed(e.to_string()))?;
+                if response.status() == StatusCode::CONFLICT {
+                    return Err(WriteError::Conflict);
+                }
+                let body: Value = response.json().await.unwrap_or_default();
+                Ok(body["rev"].as_str().map(String::from))
+            }
+        };
+        let refetch = || async {
+            Ok(Some(
+                json!({ "_id": "a", "_rev": "2-b", "fname": "Ada", "age": 36 }),
+            ))
+        };
+        let reapply = |doc: &mut Value| match doc.as_object_mut().unwrap().remove("fname") {
+            Some(value) => {
+                doc["first_name"] = value;
+                true
+            }
+            None => false,
+        };
+
+        let mut doc = json!({ "_id": "a", "_rev": "1-a", "first_name": "Ada" });
+        let resolution = write_resolving_conflicts(&mut doc, 2, write, refetch, reapply).await;
+
+        assert_eq!(resolution, Ok(Resolution::Written(Some("3-c".to_string()))));
+        assert_eq!(
+            doc,
+            json!({ "_id": "a", "_rev": "2-b", "age": 36, "first_name": "Ada" })
+        );
+        let requests = server.received_requests().await.unwrap();
+        assert_eq!(requests.len(), 2, "The stale write and the rebased one");
+        assert_eq!(requests[1].body_json::<Value>().unwrap(), doc);
+    }
+}
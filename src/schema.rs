@@ -0,0 +1,167 @@
+use crate::args::validate_rename_paths;
+use crate::mapping::RenameRule;
+use serde_json::{Map, Value};
+
+/// Rename rules derived from two versions of a JSON Schema, and the fields left unpaired.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaDiff {
+    pub renames: Vec<RenameRule>, // Fields of the old schema paired with a field of the new one
+    pub unmatched_old: Vec<String>, // Fields only in the old schema, left untouched
+    pub unmatched_new: Vec<String>, // Fields only in the new schema, not filled in
+}
+
+/// Loads two JSON Schema files and derives the rename rules between them.
+/// See `diff_schemas` for how fields are paired.
+pub fn load_schema_renames(from_path: &str, to_path: &str) -> Result<SchemaDiff, String> {
+    let from = read_schema(from_path)?;
+    let to = read_schema(to_path)?;
+
+    let diff = diff_schemas(&from, &to).map_err(|e| {
+        format!(
+            "Cannot derive renames from '{}' to '{}': {}",
+            from_path, to_path, e
+        )
+    })?;
+    if diff.renames.is_empty() {
+        return Err(format!(
+            "No renamed fields found between '{}' and '{}'",
+            from_path, to_path
+        ));
+    }
+
+    for rule in &diff.renames {
+        validate_rename_paths(&rule.old_field, &rule.new_field)?;
+    }
+
+    Ok(diff)
+}
+
+/// Reads and parses a JSON Schema file.
+fn read_schema(path: &str) -> Result<Value, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read schema '{}': {}", path, e))?;
+
+    serde_json::from_str(&content).map_err(|e| format!("Invalid schema '{}': {}", path, e))
+}
+
+/// Derives renames between the `properties` of two object schemas, at the top level
+/// and one level down in objects present in both.
+/// Under each parent, a field only in the old schema is paired with the first unpaired field
+/// only in the new schema that has the same `type`, in declaration order.
+pub fn diff_schemas(from: &Value, to: &Value) -> Result<SchemaDiff, String> {
+    let (Some(from), Some(to)) = (properties(from), properties(to)) else {
+        return Err("Both schemas must be objects with 'properties'".to_string());
+    };
+
+    let mut diff = SchemaDiff {
+        renames: Vec::new(),
+        unmatched_old: Vec::new(),
+        unmatched_new: Vec::new(),
+    };
+    diff_properties(from, to, "", &mut diff);
+
+    for (name, from_field) in from {
+        let nested = to
+            .get(name)
+            .and_then(properties)
+            .zip(properties(from_field));
+        if let Some((to_nested, from_nested)) = nested {
+            diff_properties(from_nested, to_nested, &format!("{}.", name), &mut diff);
+        }
+    }
+
+    Ok(diff)
+}
+
+/// Pairs the fields removed from `from` with the fields added to `to`, naming them under `prefix`.
+fn diff_properties(
+    from: &Map<String, Value>,
+    to: &Map<String, Value>,
+    prefix: &str,
+    diff: &mut SchemaDiff,
+) {
+    let mut added: Vec<(&String, &Value)> = to
+        .iter()
+        .filter(|(name, _)| !from.contains_key(*name))
+        .collect();
+
+    for (name, field) in from.iter().filter(|(name, _)| !to.contains_key(*name)) {
+        let paired = added
+            .iter()
+            .position(|(_, candidate)| candidate.get("type") == field.get("type"));
+        match paired {
+            Some(index) => {
+                let (new_name, _) = added.remove(index);
+                diff.renames.push(RenameRule {
+                    old_field: format!("{}{}", prefix, name),
+                    new_field: format!("{}{}", prefix, new_name),
+                });
+            }
+            None => diff.unmatched_old.push(format!("{}{}", prefix, name)),
+        }
+    }
+
+    diff.unmatched_new.extend(
+        added
+            .into_iter()
+            .map(|(name, _)| format!("{}{}", prefix, name)),
+    );
+}
+
+/// The `properties` of an object schema, if it declares any.
+fn properties(schema: &Value) -> Option<&Map<String, Value>> {
+    schema.get("properties")?.as_object()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rule(old_field: &str, new_field: &str) -> RenameRule {
+        RenameRule {
+            old_field: old_field.to_string(),
+            new_field: new_field.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_diff_schemas_pairs_fields_by_type_and_order() {
+        let from = json!({ "type": "object", "properties": {
+            "id": { "type": "string" },
+            "qty": { "type": "integer" },
+            "fname": { "type": "string" },
+            "legacy": { "type": "boolean" },
+            "profile": { "type": "object", "properties": {
+                "tel": { "type": "string" },
+            }},
+        }});
+        let to = json!({ "type": "object", "properties": {
+            "id": { "type": "string" },
+            "first_name": { "type": "string" },
+            "quantity": { "type": "integer" },
+            "created": { "type": "number" },
+            "profile": { "type": "object", "properties": {
+                "phone": { "type": "string" },
+            }},
+        }});
+
+        let diff = diff_schemas(&from, &to).unwrap();
+
+        assert_eq!(
+            diff.renames,
+            vec![
+                rule("qty", "quantity"),
+                rule("fname", "first_name"),
+                rule("profile.tel", "profile.phone"),
+            ]
+        );
+        assert_eq!(diff.unmatched_old, vec!["legacy".to_string()]);
+        assert_eq!(diff.unmatched_new, vec!["created".to_string()]);
+    }
+
+    #[test]
+    fn test_diff_schemas_requires_properties() {
+        assert!(diff_schemas(&json!({ "type": "string" }), &json!({ "properties": {} })).is_err());
+    }
+}
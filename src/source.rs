@@ -0,0 +1,193 @@
+use serde_json::Value;
+use std::future::Future;
+
+/// Where the documents of a run come from, one batch at a time: a CouchDB table
+/// (`FetchDocument`), a local file (`FileSource`), or anything else, e.g. fixtures in tests.
+/// `fetch::Feed` passes the documents of any source to the processing callback.
+pub trait DocumentSource: Send {
+    /// Fetches the next batch of documents. An empty batch marks the end of the source.
+    fn next_batch(&mut self) -> impl Future<Output = Result<Vec<Value>, String>> + Send;
+}
+
+/// Documents read from a local file instead of a CouchDB table, e.g. an exported dataset on which
+/// rename rules are tried offline. See `read_documents` for the accepted formats.
+pub struct FileSource {
+    documents: Vec<Value>, // Documents of the file not yet returned, in order
+}
+
+impl FileSource {
+    /// Reads every document of the file at `path`.
+    pub fn open(path: &str) -> Result<Self, String> {
+        Ok(FileSource {
+            documents: read_documents(path)?,
+        })
+    }
+
+    /// The number of documents not yet returned.
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    /// Whether every document was returned, or the file held none.
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+}
+
+impl DocumentSource for FileSource {
+    /// The whole file is a single batch.
+    async fn next_batch(&mut self) -> Result<Vec<Value>, String> {
+        Ok(std::mem::take(&mut self.documents))
+    }
+}
+
+/// Reads the documents of a file holding either a JSON array of documents or newline-delimited
+/// JSON (one document per line, blank lines ignored).
+pub fn read_documents(path: &str) -> Result<Vec<Value>, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read input file '{}': {}", path, e))?;
+
+    if content.trim_start().starts_with('[') {
+        return serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid JSON array in '{}': {}", path, e));
+    }
+
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line)
+                .map_err(|e| format!("Invalid JSON on line {} of '{}': {}", index + 1, path, e))
+        })
+        .collect()
+}
+
+/// Writes documents to a file as newline-delimited JSON, replacing its content.
+pub fn write_documents(path: &str, documents: &[Value]) -> Result<(), String> {
+    let mut content = String::new();
+    for doc in documents {
+        content.push_str(&doc.to_string());
+        content.push('\n');
+    }
+    std::fs::write(path, content).map_err(|e| format!("Failed to write '{}': {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetch::Feed;
+    use crate::rename::{rename_nested_field, split_path};
+    use serde_json::json;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    /// Batches held in memory, returned in order.
+    struct VecSource {
+        batches: VecDeque<Vec<Value>>, // Batches not yet returned
+    }
+
+    impl DocumentSource for VecSource {
+        async fn next_batch(&mut self) -> Result<Vec<Value>, String> {
+            Ok(self.batches.pop_front().unwrap_or_default())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_feed_applies_every_batch_of_any_source() {
+        for prefetch in [0, 2] {
+            let mut source = VecSource {
+                batches: VecDeque::from([
+                    vec![json!({ "_id": "a", "fname": "Ada" }), json!({ "_id": "b" })],
+                    vec![json!({ "_id": "c", "fname": "Cy" })],
+                    vec![],
+                    vec![json!({ "_id": "d", "fname": "Dee" })],
+                ]),
+            };
+            let renamed = Mutex::new(Vec::new());
+            let batches = Mutex::new(Vec::new());
+
+            let summary = Feed::new(Box::new(|mut doc: Value| {
+                rename_nested_field(&mut doc, &["fname"], "first_name");
+                renamed.lock().unwrap().push(doc);
+            }))
+            .with_batch_callback(Box::new(|batch| {
+                batches
+                    .lock()
+                    .unwrap()
+                    .push((batch.iteration, batch.documents));
+            }))
+            .with_prefetch(prefetch)
+            .run(&mut source)
+            .await
+            .unwrap();
+
+            assert_eq!(summary.iterations, 2, "The empty batch ends the source");
+            assert_eq!(summary.total_fetched, 3);
+            assert_eq!(*batches.lock().unwrap(), vec![(1, 2), (2, 1)]);
+            assert_eq!(
+                renamed.into_inner().unwrap(),
+                vec![
+                    json!({ "_id": "a", "first_name": "Ada" }),
+                    json!({ "_id": "b" }),
+                    json!({ "_id": "c", "first_name": "Cy" }),
+                ]
+            );
+            assert_eq!(source.batches.len(), 1, "Nothing is read past the end");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_file_source_round_trips_ndjson() {
+        let dir = std::env::temp_dir();
+        let input = dir.join(format!("refield-input-{}.ndjson", std::process::id()));
+        let output = dir.join(format!("refield-output-{}.ndjson", std::process::id()));
+        let (input, output) = (input.to_str().unwrap(), output.to_str().unwrap());
+        std::fs::write(
+            input,
+            "{\"_id\":\"a\",\"user\":{\"fname\":\"Ada\"}}\n\n{\"_id\":\"b\",\"user\":{}}\n",
+        )
+        .unwrap();
+
+        let renamed = Mutex::new(Vec::new());
+        let mut source = FileSource::open(input).unwrap();
+        assert_eq!(source.len(), 2);
+        let summary = Feed::new(Box::new(|mut doc: Value| {
+            rename_nested_field(&mut doc, &split_path("user.fname"), "user.first_name");
+            renamed.lock().unwrap().push(doc);
+        }))
+        .run(&mut source)
+        .await
+        .unwrap();
+        write_documents(output, &renamed.into_inner().unwrap()).unwrap();
+        let written = read_documents(output).unwrap();
+        std::fs::remove_file(input).unwrap();
+        std::fs::remove_file(output).unwrap();
+
+        assert_eq!(summary.total_fetched, 2);
+        assert_eq!(
+            written,
+            vec![
+                json!({ "_id": "a", "user": { "first_name": "Ada" } }),
+                json!({ "_id": "b", "user": {} }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_documents_accepts_a_json_array() {
+        let path = std::env::temp_dir().join(format!("refield-array-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "[{\"_id\":\"a\"},\n {\"_id\":\"b\"}]").unwrap();
+        let documents = read_documents(path);
+        std::fs::write(path, "{\"_id\":\"a\"}\n{\"_id\":").unwrap();
+        let invalid = read_documents(path);
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(
+            documents,
+            Ok(vec![json!({ "_id": "a" }), json!({ "_id": "b" })])
+        );
+        assert!(invalid.unwrap_err().contains("line 2"));
+    }
+}
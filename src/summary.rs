@@ -0,0 +1,271 @@
+use crate::fetch::FetchSummary;
+use std::str::FromStr;
+
+/// Output format for the end-of-run summary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SummaryFormat {
+    Text, // Human-readable text
+    Json, // A single JSON object
+    Csv,  // A single CSV row, suitable for appending to a migration ledger
+}
+
+impl FromStr for SummaryFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(SummaryFormat::Text),
+            "json" => Ok(SummaryFormat::Json),
+            "csv" => Ok(SummaryFormat::Csv),
+            _ => Err(format!(
+                "Unknown summary format '{}'. Expected one of: text, json, csv.",
+                s
+            )),
+        }
+    }
+}
+
+/// What `--count-only` counts instead of processing the documents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CountMode {
+    Matching, // Documents in scope (selector, prefix, or partition), from the metadata when possible
+    Field,    // Documents holding one of the old fields, each fetched and checked
+}
+
+impl FromStr for CountMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "matching" => Ok(CountMode::Matching),
+            "field" => Ok(CountMode::Field),
+            _ => Err(format!(
+                "Unknown count mode '{}'. Expected one of: matching, field.",
+                s
+            )),
+        }
+    }
+}
+
+/// Renders the summary of a run together with the rename rule that was applied.
+///
+/// The CSV row has no header; its columns are, in order:
+/// `table_name,old_field,new_field,doc_count,total_fetched,iterations,duration_secs,matched,written,failed`.
+pub fn render_summary(
+    summary: &FetchSummary,
+    old_field: &str,
+    new_field: &str,
+    format: SummaryFormat,
+) -> String {
+    match format {
+        SummaryFormat::Text => format!(
+            "Operation completed. Renamed '{}' -> '{}' in table '{}': \
+             fetched {}/{} documents in {} iterations ({:.2}s); \
+             {} changed, {} written, {} failed.",
+            old_field,
+            new_field,
+            summary.table_name,
+            summary.total_fetched,
+            summary.doc_count,
+            summary.iterations,
+            summary.duration_secs,
+            summary.matched,
+            summary.written,
+            summary.failed
+        ),
+        SummaryFormat::Json => serde_json::json!({
+            "table_name": summary.table_name,
+            "old_field": old_field,
+            "new_field": new_field,
+            "doc_count": summary.doc_count,
+            "total_fetched": summary.total_fetched,
+            "iterations": summary.iterations,
+            "duration_secs": summary.duration_secs,
+            "matched": summary.matched,
+            "written": summary.written,
+            "failed": summary.failed,
+        })
+        .to_string(),
+        SummaryFormat::Csv => [
+            csv_field(&summary.table_name),
+            csv_field(old_field),
+            csv_field(new_field),
+            summary.doc_count.to_string(),
+            summary.total_fetched.to_string(),
+            summary.iterations.to_string(),
+            format!("{:.3}", summary.duration_secs),
+            summary.matched.to_string(),
+            summary.written.to_string(),
+            summary.failed.to_string(),
+        ]
+        .join(","),
+    }
+}
+
+/// How a full run would issue its writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WritePlan {
+    pub docs_per_request: usize, // Documents written per request (the bulk size, or 1)
+    pub in_flight: usize,        // Write requests in flight at once
+}
+
+/// Extrapolates the total run duration from a sampled run and renders it as text.
+/// The sample's throughput (documents per second) is applied to the table's full document count.
+/// The documents the sample would change are extrapolated the same way, and the time their
+/// write requests take is added. Nothing is written while sampling, so each write request is
+/// approximated by the sample's average read round trip per batch.
+pub fn render_estimate(sample: &FetchSummary, writes: &WritePlan) -> String {
+    if sample.total_fetched == 0 || sample.duration_secs <= 0.0 {
+        return "Unable to estimate runtime: no documents were sampled.".to_string();
+    }
+
+    let rate = sample.total_fetched as f64 / sample.duration_secs;
+    let read_secs = sample.doc_count as f64 / rate;
+    let round_trip_secs = sample.duration_secs / sample.iterations.max(1) as f64;
+    let changed =
+        (sample.doc_count as f64 * sample.matched as f64 / sample.total_fetched as f64).ceil();
+    let requests = (changed / writes.docs_per_request.max(1) as f64).ceil();
+    let write_secs = requests * round_trip_secs / writes.in_flight.max(1) as f64;
+    let estimated_secs = read_secs + write_secs;
+
+    format!(
+        "Sampled {} documents in {} batches ({:.2}s, {:.1} docs/sec). \
+         About {:.0} documents would be changed, in {:.0} write requests \
+         (~{:.0}ms each, approximated by the read round trip; {} in flight): ~{:.0}s. \
+         Estimated total duration for {} documents: {:.0}s (~{:.1} minutes).",
+        sample.total_fetched,
+        sample.iterations,
+        sample.duration_secs,
+        rate,
+        changed,
+        requests,
+        round_trip_secs * 1000.0,
+        writes.in_flight,
+        write_secs,
+        sample.doc_count,
+        estimated_secs,
+        estimated_secs / 60.0
+    )
+}
+
+/// The final line reporting the documents whose update failed, or `None` if every update succeeded.
+/// A run with failed updates exits with a non-zero status.
+pub fn render_update_failures(errors: usize) -> Option<String> {
+    match errors {
+        0 => None,
+        1 => Some("1 document failed to update".to_string()),
+        n => Some(format!("{} documents failed to update", n)),
+    }
+}
+
+/// Quotes a CSV field if it contains a separator, quote, or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_summary() -> FetchSummary {
+        FetchSummary {
+            table_name: "users".to_string(),
+            doc_count: 10,
+            total_fetched: 10,
+            iterations: 2,
+            duration_secs: 1.5,
+            matched: 4,
+            written: 3,
+            failed: 1,
+        }
+    }
+
+    #[test]
+    fn test_render_summary_json() {
+        let output = render_summary(&sample_summary(), "a.b", "a.c", SummaryFormat::Json);
+        let json: serde_json::Value = serde_json::from_str(&output).unwrap();
+
+        assert_eq!(json["table_name"], "users");
+        assert_eq!(json["old_field"], "a.b");
+        assert_eq!(json["new_field"], "a.c");
+        assert_eq!(json["total_fetched"], 10);
+        assert_eq!(json["iterations"], 2);
+        assert_eq!(json["matched"], 4);
+        assert_eq!(json["written"], 3);
+        assert_eq!(json["failed"], 1);
+    }
+
+    #[test]
+    fn test_render_summary_text() {
+        let output = render_summary(&sample_summary(), "a.b", "a.c", SummaryFormat::Text);
+
+        assert!(output.contains("fetched 10/10 documents in 2 iterations (1.50s)"));
+        assert!(output.ends_with("4 changed, 3 written, 1 failed."));
+    }
+
+    #[test]
+    fn test_render_summary_csv_quotes_fields() {
+        let mut summary = sample_summary();
+        summary.table_name = "odd,name".to_string();
+
+        let output = render_summary(&summary, "a.b", "a.c", SummaryFormat::Csv);
+
+        assert_eq!(output, "\"odd,name\",a.b,a.c,10,10,2,1.500,4,3,1");
+    }
+
+    #[test]
+    fn test_render_estimate_extrapolates_to_doc_count() {
+        let mut sample = sample_summary();
+        sample.doc_count = 1000;
+        sample.matched = 0;
+        let writes = WritePlan {
+            docs_per_request: 1,
+            in_flight: 1,
+        };
+
+        let output = render_estimate(&sample, &writes);
+
+        assert!(output.contains("Estimated total duration for 1000 documents: 150s"));
+    }
+
+    #[test]
+    fn test_render_estimate_adds_the_approximate_write_time() {
+        let mut sample = sample_summary();
+        sample.doc_count = 1000;
+        let writes = WritePlan {
+            docs_per_request: 10,
+            in_flight: 2,
+        };
+
+        let output = render_estimate(&sample, &writes);
+
+        // 400 of the 1000 documents change, in 40 requests of a 750ms batch round trip, two at a time
+        assert!(output.contains("About 400 documents would be changed, in 40 write requests"));
+        assert!(output
+            .contains("(~750ms each, approximated by the read round trip; 2 in flight): ~15s."));
+        assert!(output.contains("Estimated total duration for 1000 documents: 165s"));
+    }
+
+    #[test]
+    fn test_summary_format_from_str() {
+        assert_eq!("csv".parse::<SummaryFormat>(), Ok(SummaryFormat::Csv));
+        assert!("xml".parse::<SummaryFormat>().is_err());
+    }
+
+    #[test]
+    fn test_render_update_failures() {
+        assert_eq!(render_update_failures(0), None);
+        assert_eq!(
+            render_update_failures(1),
+            Some("1 document failed to update".to_string())
+        );
+        assert_eq!(
+            render_update_failures(250),
+            Some("250 documents failed to update".to_string())
+        );
+    }
+}
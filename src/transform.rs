@@ -0,0 +1,176 @@
+use crate::rename::{
+    prune_empty, rename_nested_field_with_options, split_path, transform_nested_field,
+    RenameOptions, ValueTransform,
+};
+use serde_json::Value;
+
+/// A stage of a `Pipeline`: modifies a document in place and reports whether it changed it.
+/// Closures taking `&mut Value` and returning `bool` are stages too.
+pub trait Transform: Send + Sync {
+    /// Applies the stage to a document, returning whether the document was modified.
+    fn apply(&self, doc: &mut Value) -> bool;
+}
+
+impl<F> Transform for F
+where
+    F: Fn(&mut Value) -> bool + Send + Sync,
+{
+    fn apply(&self, doc: &mut Value) -> bool {
+        self(doc)
+    }
+}
+
+/// Renames a field, like `--old`/`--new` (see `rename_nested_field_with_options`).
+pub struct Rename {
+    old_path: Vec<String>,  // Old field path split into components
+    new_field: String,      // New field name (only its last segment is used)
+    options: RenameOptions, // Options controlling how the field is matched and renamed
+}
+
+impl Rename {
+    /// Renames `old_field` (dot notation) to `new_field` with the default options.
+    pub fn new(old_field: &str, new_field: &str) -> Self {
+        Rename {
+            old_path: split_path(old_field)
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            new_field: new_field.to_string(),
+            options: RenameOptions::default(),
+        }
+    }
+
+    /// Sets the options controlling how the field is matched and renamed.
+    pub fn with_options(mut self, options: RenameOptions) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+impl Transform for Rename {
+    fn apply(&self, doc: &mut Value) -> bool {
+        let old_path: Vec<&str> = self.old_path.iter().map(String::as_str).collect();
+        rename_nested_field_with_options(doc, &old_path, &self.new_field, &self.options)
+    }
+}
+
+/// Normalizes the values of a field in place, like `--transform`.
+/// Values the transformation does not apply to are left as they are.
+pub struct Normalize {
+    path: Vec<String>,         // Field path split into components
+    transform: ValueTransform, // Transformation applied to each value
+}
+
+impl Normalize {
+    /// Applies `transform` to every value of `field` (dot notation).
+    pub fn new(field: &str, transform: ValueTransform) -> Self {
+        Normalize {
+            path: split_path(field).into_iter().map(String::from).collect(),
+            transform,
+        }
+    }
+}
+
+impl Transform for Normalize {
+    fn apply(&self, doc: &mut Value) -> bool {
+        let path: Vec<&str> = self.path.iter().map(String::as_str).collect();
+        let changed = transform_nested_field(doc, &path, &|value| {
+            self.transform.apply(value).unwrap_or_else(|value| value)
+        });
+        changed > 0
+    }
+}
+
+/// An ordered list of stages applied to each document, e.g. a rename, then a value
+/// normalization, then pruning the empties left behind.
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Box<dyn Transform>>, // Stages, in the order they are applied
+    prune_empty: bool, // Whether the objects and arrays the stages left empty are removed afterwards
+}
+
+impl Pipeline {
+    /// Constructs an empty pipeline, which leaves documents unchanged.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a stage, applied after the stages added before it.
+    pub fn with_stage(mut self, stage: impl Transform + 'static) -> Self {
+        self.stages.push(Box::new(stage));
+        self
+    }
+
+    /// Removes the objects and arrays the stages left empty once they are all applied, like
+    /// `--prune-empty` (see `prune_empty`). Those that were already empty are kept.
+    pub fn with_prune_empty(mut self) -> Self {
+        self.prune_empty = true;
+        self
+    }
+
+    /// The number of stages.
+    pub fn len(&self) -> usize {
+        self.stages.len()
+    }
+
+    /// Whether the pipeline has no stages.
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    /// Applies every stage to a document, in order, each to the result of the previous one,
+    /// then prunes what they emptied if requested. Returns whether the document was modified.
+    pub fn apply(&self, doc: &mut Value) -> bool {
+        let original = self.prune_empty.then(|| doc.clone());
+        let changed = self
+            .stages
+            .iter()
+            .fold(false, |changed, stage| stage.apply(doc) | changed);
+        match original {
+            Some(original) if changed => prune_empty(doc, &original) | changed,
+            _ => changed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_pipeline_applies_stages_in_order() {
+        let pipeline = Pipeline::new()
+            .with_stage(Rename::new("meta.Status", "meta.status"))
+            .with_stage(Normalize::new("meta.status", ValueTransform::Lower))
+            .with_stage(|doc: &mut Value| {
+                doc.get_mut("legacy")
+                    .and_then(Value::as_object_mut)
+                    .is_some_and(|legacy| legacy.shift_remove("code").is_some())
+            })
+            .with_prune_empty();
+        assert_eq!(pipeline.len(), 3);
+
+        let mut doc =
+            json!({ "meta": { "Status": "ACTIVE" }, "legacy": { "code": 1 }, "tags": [] });
+        assert!(pipeline.apply(&mut doc));
+        assert_eq!(
+            doc,
+            json!({ "meta": { "status": "active" }, "tags": [] }),
+            "The emptied object is pruned, the array that was already empty is kept"
+        );
+
+        assert!(!pipeline.apply(&mut doc), "Nothing left to change");
+    }
+
+    #[test]
+    fn test_each_stage_reports_its_own_changes() {
+        let mut doc = json!({ "name": " ada " });
+
+        assert!(!Rename::new("missing", "other").apply(&mut doc));
+        assert!(!Normalize::new("name", ValueTransform::Lower).apply(&mut doc));
+        assert!(Normalize::new("name", ValueTransform::Trim).apply(&mut doc));
+        assert_eq!(doc, json!({ "name": "ada" }));
+        assert!(!Pipeline::new().apply(&mut doc));
+    }
+}
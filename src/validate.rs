@@ -0,0 +1,283 @@
+use crate::iam::{authorize, IamAuth};
+use crate::rename::find_nested_values;
+use reqwest::Client;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use tracing::warn;
+
+/// Distribution of an old field's presence and value types across scanned documents.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub total_docs: usize,                          // Number of documents examined
+    pub present_docs: usize,                        // Number of documents containing the field
+    pub type_counts: BTreeMap<&'static str, usize>, // Number of values found per JSON type
+}
+
+impl ValidationReport {
+    /// Records whether any of `field_paths` is present in `doc` and the JSON type of every value found.
+    pub fn record(&mut self, doc: &Value, field_paths: &[&[&str]]) {
+        self.total_docs += 1;
+
+        let values: Vec<&Value> = field_paths
+            .iter()
+            .flat_map(|path| find_nested_values(doc, path))
+            .collect();
+
+        if !values.is_empty() {
+            self.present_docs += 1;
+        }
+        for value in values {
+            *self.type_counts.entry(json_type_name(value)).or_insert(0) += 1;
+        }
+    }
+
+    /// Renders the report as text, e.g. `present in 98.0% (98/100), types: string 95.9%, null 4.1%`.
+    pub fn render(&self) -> String {
+        let total_values: usize = self.type_counts.values().sum();
+        let types: Vec<String> = self
+            .type_counts
+            .iter()
+            .map(|(name, count)| format!("{} {:.1}%", name, percent(*count, total_values)))
+            .collect();
+
+        format!(
+            "Field present in {:.1}% ({}/{}) of documents, types: {}",
+            percent(self.present_docs, self.total_docs),
+            self.present_docs,
+            self.total_docs,
+            if types.is_empty() {
+                "none".to_string()
+            } else {
+                types.join(", ")
+            }
+        )
+    }
+}
+
+/// Returns the JSON type name of a value.
+pub fn json_type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// Computes `part` as a percentage of `total`, treating an empty total as 0%.
+fn percent(part: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / total as f64
+    }
+}
+
+/// Safety valve tripping when too many of the first documents lack the old field,
+/// which usually means the field path is wrong.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MissingFieldGuard {
+    max_ratio: f64, // Largest tolerated fraction of documents lacking the field
+    window: usize,  // Number of documents examined before deciding
+    seen: usize,    // Documents examined so far (up to the window)
+    missing: usize, // Documents lacking the field among them
+}
+
+impl MissingFieldGuard {
+    /// Creates a guard judging the first `window` documents against `max_ratio` (0.0 to 1.0).
+    pub fn new(max_ratio: f64, window: usize) -> Self {
+        MissingFieldGuard {
+            max_ratio,
+            window,
+            seen: 0,
+            missing: 0,
+        }
+    }
+
+    /// Records whether a document had the field. Once the window is full, returns the fraction of
+    /// documents lacking the field if it exceeds the tolerated ratio; this happens at most once.
+    pub fn record(&mut self, found: bool) -> Option<f64> {
+        if self.seen >= self.window {
+            return None; // Already decided
+        }
+
+        self.seen += 1;
+        if !found {
+            self.missing += 1;
+        }
+        if self.seen < self.window {
+            return None;
+        }
+
+        let ratio = self.missing as f64 / self.seen as f64;
+        (ratio > self.max_ratio).then_some(ratio)
+    }
+}
+
+/// Checks that the table's `validate_doc_update` functions accept a transformed document by
+/// writing a scratch copy of it under a new ID, then deleting that copy straight away.
+/// The copy is written without `_id`, `_rev` and `_attachments`, so the validation functions
+/// see it as a new document. Returns the server's reason if the copy is rejected.
+pub async fn validate_on_server(
+    client: &Client,
+    db_host: &str,
+    table_name: &str,
+    doc: &Value,
+    auth: Option<&IamAuth>,
+) -> Result<(), String> {
+    let mut scratch = doc.clone();
+    if let Some(obj) = scratch.as_object_mut() {
+        for key in ["_id", "_rev", "_attachments"] {
+            obj.shift_remove(key);
+        }
+    }
+
+    let url = format!("{}/{}", db_host, table_name);
+    let response = authorize(auth, client.post(&url))
+        .await?
+        .json(&scratch)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let status = response.status();
+    let body: Value = response.json().await.unwrap_or_default();
+    if !status.is_success() {
+        // `validate_doc_update` rejections carry the reason in the body
+        return match body["reason"].as_str() {
+            Some(reason) => Err(format!("{} ({})", reason, status)),
+            None => Err(format!("Validation failed: Status code {}", status)),
+        };
+    }
+
+    // The copy was accepted; roll it back
+    let (Some(id), Some(rev)) = (body["id"].as_str(), body["rev"].as_str()) else {
+        warn!("the server accepted a scratch document without reporting its ID and revision.");
+        return Ok(());
+    };
+    let url = format!(
+        "{}/{}/{}?rev={}",
+        db_host,
+        table_name,
+        urlencoding::encode(id),
+        urlencoding::encode(rev)
+    );
+    let deleted = match authorize(auth, client.delete(&url)).await {
+        Ok(request) => request.send().await.map_err(|e| e.to_string()),
+        Err(err) => Err(err),
+    };
+    match deleted {
+        Ok(response) if response.status().is_success() => {}
+        Ok(response) => warn!(
+            "failed to delete the scratch document {}: Status code {}",
+            id,
+            response.status()
+        ),
+        Err(err) => warn!("failed to delete the scratch document {}: {}", id, err),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::matchers::{body_json, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn test_validation_report_distribution() {
+        let mut report = ValidationReport::default();
+        let paths: Vec<&[&str]> = vec![&["a", "b"]];
+
+        report.record(&json!({ "a": { "b": "x" } }), &paths);
+        report.record(&json!({ "a": { "b": null } }), &paths);
+        report.record(&json!({ "a": [{ "b": "y" }, { "b": "z" }] }), &paths);
+        report.record(&json!({ "c": 1 }), &paths);
+
+        assert_eq!(report.total_docs, 4);
+        assert_eq!(report.present_docs, 3);
+        assert_eq!(report.type_counts["string"], 3);
+        assert_eq!(report.type_counts["null"], 1);
+        assert_eq!(
+            report.render(),
+            "Field present in 75.0% (3/4) of documents, types: null 25.0%, string 75.0%"
+        );
+    }
+
+    #[test]
+    fn test_missing_field_guard_trips_once_after_window() {
+        let mut guard = MissingFieldGuard::new(0.5, 4);
+
+        assert_eq!(guard.record(false), None);
+        assert_eq!(guard.record(false), None);
+        assert_eq!(guard.record(true), None);
+        assert_eq!(guard.record(false), Some(0.75));
+        assert_eq!(guard.record(false), None, "Decided once the window is full");
+
+        let mut guard = MissingFieldGuard::new(0.5, 2);
+        guard.record(true);
+        assert_eq!(guard.record(false), None, "Exactly the tolerated ratio");
+    }
+
+    #[tokio::test]
+    async fn test_validate_on_server_writes_and_deletes_a_scratch_copy() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/orders"))
+            .and(body_json(json!({ "type": "order", "total": 3 })))
+            .respond_with(
+                ResponseTemplate::new(201)
+                    .set_body_json(json!({ "ok": true, "id": "scratch", "rev": "1-a" })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("DELETE"))
+            .and(path("/orders/scratch"))
+            .and(query_param("rev", "1-a"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "ok": true })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let doc = json!({
+            "_id": "o1",
+            "_rev": "3-c",
+            "_attachments": { "a.txt": { "stub": true } },
+            "type": "order",
+            "total": 3,
+        });
+        let accepted =
+            validate_on_server(&Client::new(), &server.uri(), "orders", &doc, None).await;
+        assert_eq!(accepted, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_validate_on_server_reports_rejections() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/orders"))
+            .respond_with(
+                ResponseTemplate::new(403)
+                    .set_body_json(json!({ "error": "forbidden", "reason": "total is required" })),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("DELETE"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        let doc = json!({ "_id": "o1", "type": "order" });
+        let rejected =
+            validate_on_server(&Client::new(), &server.uri(), "orders", &doc, None).await;
+        assert_eq!(
+            rejected,
+            Err("total is required (403 Forbidden)".to_string())
+        );
+    }
+}
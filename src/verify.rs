@@ -0,0 +1,58 @@
+use crate::rename::{find_nested_values, split_path};
+use serde_json::Value;
+
+/// The state a renamed document must be persisted in, checked by `--verify` once the writes are done.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expectation {
+    pub present: String,   // Field that must exist (dot notation)
+    pub gone: Vec<String>, // Fields that must no longer exist (dot notation)
+}
+
+impl Expectation {
+    /// Describes every way a re-fetched document differs from the expectation; empty if it matches.
+    pub fn discrepancies(&self, doc: &Value) -> Vec<String> {
+        let mut found = Vec::new();
+
+        if find_nested_values(doc, &split_path(&self.present)).is_empty() {
+            found.push(format!("'{}' is missing", self.present));
+        }
+        for field in &self.gone {
+            if !find_nested_values(doc, &split_path(field)).is_empty() {
+                found.push(format!("'{}' is still present", field));
+            }
+        }
+
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_discrepancies() {
+        let expectation = Expectation {
+            present: "items.sku".to_string(),
+            gone: vec!["items.code".to_string(), "legacy".to_string()],
+        };
+
+        let renamed = json!({ "items": [{ "sku": "a" }, { "qty": 1 }] });
+        assert!(expectation.discrepancies(&renamed).is_empty());
+
+        let reverted = json!({ "items": [{ "sku": "a" }, { "code": "b" }], "legacy": 1 });
+        assert_eq!(
+            expectation.discrepancies(&reverted),
+            vec![
+                "'items.code' is still present".to_string(),
+                "'legacy' is still present".to_string()
+            ]
+        );
+
+        assert_eq!(
+            expectation.discrepancies(&json!({})),
+            vec!["'items.sku' is missing".to_string()]
+        );
+    }
+}